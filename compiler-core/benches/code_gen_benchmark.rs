@@ -0,0 +1,33 @@
+use compiler_core::analyser::analyse;
+use compiler_core::code_gen::ast_to_wasm;
+use compiler_core::parser::ParseSession;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::fs;
+
+mod programs;
+
+fn bench_code_gen(c: &mut Criterion, name: &str, source: &str) {
+    let session = ParseSession::new();
+
+    let ast = session.parse(source).unwrap();
+
+    let analysis = analyse(&ast).unwrap();
+
+    c.bench_function(name, |b| {
+        b.iter(|| black_box(ast_to_wasm(&ast, &analysis).unwrap()))
+    });
+}
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let contents = fs::read_to_string("src/fixtures/example_program.lang").unwrap();
+
+    bench_code_gen(c, "generate example program", &contents);
+    bench_code_gen(
+        c,
+        "generate 1000 functions",
+        &programs::many_functions(1000),
+    );
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,30 @@
+use compiler_core::parser::ParseSession;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::fs;
+
+mod programs;
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let contents = fs::read_to_string("src/fixtures/example_program.lang").unwrap();
+    let generated = programs::many_functions(1000);
+
+    // a new session each time, so allocating the arena and freeing it are counted too
+    c.bench_function("parse example program", |b| {
+        b.iter(|| {
+            let session = ParseSession::new();
+
+            black_box(session.parse(&contents).unwrap());
+        })
+    });
+
+    c.bench_function("parse 1000 functions", |b| {
+        b.iter(|| {
+            let session = ParseSession::new();
+
+            black_box(session.parse(&generated).unwrap());
+        })
+    });
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,86 @@
+use compiler_core::analyser::analyse;
+use compiler_core::code_gen::ast_to_wasm;
+use compiler_core::compile;
+use compiler_core::parser::ParseSession;
+use compiler_core::wasm::{Wasm, WasmIndentation};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+mod programs;
+
+// Every stage is measured in bytes of source per second, so the stages can be compared with
+// each other, and programs of different sizes with each other
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let programs = programs::large_programs();
+
+    let mut group = c.benchmark_group("parse");
+
+    for (name, source) in &programs {
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), source, |b, source| {
+            b.iter(|| {
+                let session = ParseSession::new();
+
+                black_box(session.parse(source).unwrap());
+            })
+        });
+    }
+
+    group.finish();
+
+    let mut group = c.benchmark_group("ast_to_wasm");
+
+    for (name, source) in &programs {
+        let session = ParseSession::new();
+        let ast = session.parse(source).unwrap();
+        let analysis = analyse(&ast).unwrap();
+
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| black_box(ast_to_wasm(&ast, &analysis).unwrap()))
+        });
+    }
+
+    group.finish();
+
+    let mut group = c.benchmark_group("write_text");
+
+    for (name, source) in &programs {
+        let session = ParseSession::new();
+        let ast = session.parse(source).unwrap();
+        let module = ast_to_wasm(&ast, &analyse(&ast).unwrap()).unwrap();
+
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| {
+                let mut out = String::new();
+
+                module
+                    .write_text(&mut out, WasmIndentation::default())
+                    .unwrap();
+
+                black_box(out)
+            })
+        });
+    }
+
+    group.finish();
+
+    let mut group = c.benchmark_group("compile");
+
+    // everything from the source to the text format, with the default optimisations
+    for (name, source) in &programs {
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), source, |b, source| {
+            b.iter(|| {
+                let session = ParseSession::new();
+
+                black_box(compile(&session, source).unwrap())
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
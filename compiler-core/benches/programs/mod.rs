@@ -0,0 +1,59 @@
+// Each bench only uses some of these
+#![allow(dead_code)]
+
+// A program with this many functions, each calling the one before, so it's large but still
+// passes analysis. The last one is exported, so none of them are unused
+pub fn many_functions(functions: usize) -> String {
+    let mut program = String::from("fn f0(x)\n    x\n");
+
+    for i in 1..functions {
+        let export = if i == functions - 1 { "export " } else { "" };
+
+        program.push_str(&format!(
+            "\n{}fn f{}(x)\n    y = x * 2 + {}\n    if y == 0\n        f{}(y - 1)\n    else\n        f{}(x) - y / 3 * -1\n",
+            export,
+            i,
+            i,
+            i - 1,
+            i - 1
+        ));
+    }
+
+    program
+}
+
+// One expression with this many levels of brackets, like `((x + 0) * 1) - 2`
+pub fn nested_expression(depth: usize) -> String {
+    let mut expr = String::from("x");
+
+    for i in 0..depth {
+        let operator = ["+", "*", "-"][i % 3];
+
+        expr = format!("({} {} {})", expr, operator, i);
+    }
+
+    format!("export fn nested(x)\n    {}\n", expr)
+}
+
+// A function with an `if` that has this many `else if`s, which ends up as that many nested
+// `if`s in the wasm
+pub fn if_chain(cases: usize) -> String {
+    let mut program = String::from("export fn classify(x)\n    if x == 0\n        0\n");
+
+    for i in 1..cases {
+        program.push_str(&format!("    else if x == {}\n        x * {}\n", i, i));
+    }
+
+    program.push_str("    else\n        x\n");
+
+    program
+}
+
+// What the pipeline benches run over, by name
+pub fn large_programs() -> Vec<(&'static str, String)> {
+    vec![
+        ("2000 functions", many_functions(2000)),
+        ("expression nested 500 deep", nested_expression(500)),
+        ("if with 1000 cases", if_chain(1000)),
+    ]
+}
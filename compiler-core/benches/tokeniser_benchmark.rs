@@ -2,8 +2,8 @@ use compiler_core::tokeniser::tokenise;
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use std::fs;
 
-pub fn criterion_benchmark(c: &mut Criterion) -> std::io::Result<()> {
-    let contents = fs::read_to_string("src/fixtures/example_program.lang")?;
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let contents = fs::read_to_string("src/fixtures/example_program.lang").unwrap();
 
     c.bench_function("tokenise example program", |b| {
         b.iter(|| {
@@ -14,8 +14,6 @@ pub fn criterion_benchmark(c: &mut Criterion) -> std::io::Result<()> {
             }
         })
     });
-
-    Ok(())
 }
 
 criterion_group!(benches, criterion_benchmark);
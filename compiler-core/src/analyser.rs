@@ -1,92 +1,653 @@
-use crate::{
-    ast::*,
-    parser::{ParseError, Parser},
-};
-use std::collections::HashMap;
-
-type Result<'a, X> = std::result::Result<X, AnalyserError<'a>>;
-
-struct Symbol<'a> {
-    decl: Declaration<'a>,
-    exported: bool,
-    usage_count: u64,
+use crate::ast::*;
+use crate::builtins::Builtin;
+use crate::operators::*;
+use crate::scope::Scopes;
+use crate::symbol::{Name, Symbol};
+use crate::tokens::*;
+use crate::types::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+
+pub type Result<'a, X> = std::result::Result<X, AnalyserError<'a>>;
+
+#[derive(Debug, Default)]
+pub struct Analysis<'a> {
+    pub functions: BTreeMap<Symbol<'a>, FunctionInfo<'a>>,
 }
 
-fn analyse(parser: Parser) -> Result<()> {
-    let mut symbols = HashMap::new();
-
-    for statement in parser {
-        match statement? {
-            TopLevelStatement::Declaration { decl, exported } => {
-                let name = decl.name();
-                let symbol = Symbol {
-                    decl,
-                    exported,
-                    usage_count: 0,
-                };
+#[derive(Debug)]
+pub struct FunctionInfo<'a> {
+    pub params: Vec<(Symbol<'a>, Type)>,
+    pub return_type: Type,
+    // doesn't include the params. In the order they're first assigned, so a name used for
+    // different variables in different `if` branches is in here once for each
+    pub locals: Vec<(Symbol<'a>, Type)>,
+}
+
+pub fn analyse<'a>(ast: &Ast<'a>) -> Result<'a, Analysis<'a>> {
+    let mut declared = HashSet::new();
+    let mut functions = Vec::new();
+
+    for statement in ast.statements {
+        let TopLevelStatement::Declaration { decl, exported: _ } = statement;
+
+        if !declared.insert(decl.name().symbol) {
+            return Err(AnalyserError::DuplicateDeclaration(decl.name()));
+        }
+
+        if let Declaration::FunctionDecl {
+            name,
+            arguments,
+            body,
+        } = decl
+        {
+            functions.push((*name, arguments, body));
+        }
+    }
+
+    let mut signatures = HashMap::with_capacity(functions.len());
+
+    for (name, arguments, _) in &functions {
+        let signature = Signature {
+            params: argument_types(arguments)?
+                .into_iter()
+                .map(|(_, t)| t)
+                .collect(),
+            return_type: None,
+        };
+
+        signatures.insert(name.symbol, signature);
+    }
+
+    // functions declared in the program take the place of builtins with the same name
+    for builtin in Builtin::ALL {
+        signatures
+            .entry(ast.arena.intern(builtin.name()))
+            .or_insert(Signature {
+                params: builtin.params().to_vec(),
+                return_type: Some(builtin.return_type()),
+            });
+    }
+
+    // Return types come from the function bodies, which can call functions (including
+    // themselves) that we don't know the return type of yet, so keep going until we
+    // stop learning anything new
+    loop {
+        let mut changed = false;
+
+        for (name, arguments, body) in &functions {
+            if signatures[&name.symbol].return_type.is_some() {
+                continue;
+            }
+
+            let return_type = FunctionChecker::new(*name, &signatures, arguments)?.block(body)?;
+
+            if return_type.is_some() {
+                if let Some(signature) = signatures.get_mut(&name.symbol) {
+                    signature.return_type = return_type;
+                }
+
+                changed = true;
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    // Anything left over only ever calls itself (or other unresolved functions)
+    // so can never actually produce a value
+    for signature in signatures.values_mut() {
+        signature.return_type.get_or_insert(Type::Unit);
+    }
+
+    let mut analysis = Analysis::default();
+
+    for (name, arguments, body) in &functions {
+        let mut checker = FunctionChecker::new(*name, &signatures, arguments)?;
+
+        checker.block(body)?;
+
+        let info = FunctionInfo {
+            params: argument_types(arguments)?
+                .into_iter()
+                .map(|(param, t)| (param.symbol, t))
+                .collect(),
+            return_type: signatures[&name.symbol].return_type.unwrap_or(Type::Unit),
+            locals: checker.locals(),
+        };
+
+        analysis.functions.insert(name.symbol, info);
+    }
+
+    Ok(analysis)
+}
+
+pub(crate) fn argument_types<'a>(
+    arguments: &FunctionArgsList<'a>,
+) -> Result<'a, Vec<(Name<'a>, Type)>> {
+    let mut types = Vec::with_capacity(arguments.args.len());
+
+    for arg in arguments.args {
+        let arg_type = match arg.type_name {
+            Some(type_name) => {
+                Type::from_name(type_name.as_str()).ok_or(AnalyserError::UnknownType(type_name))?
+            }
+            None => Type::Int32,
+        };
+
+        types.push((arg.name, arg_type));
+    }
+
+    Ok(types)
+}
+
+#[derive(Debug)]
+struct Signature {
+    params: Vec<Type>,
+    return_type: Option<Type>,
+}
+
+// Types are `None` when they depend on a function whose return type isn't known yet
+struct FunctionChecker<'a, 'b> {
+    function: Name<'a>,
+    signatures: &'b HashMap<Symbol<'a>, Signature>,
+    // the params, then the locals, with the scopes saying which of them each name refers to
+    variables: Vec<(Symbol<'a>, Option<Type>)>,
+    params: usize,
+    scopes: Scopes<'a, usize>,
+}
+
+impl<'a, 'b> FunctionChecker<'a, 'b> {
+    fn new(
+        function: Name<'a>,
+        signatures: &'b HashMap<Symbol<'a>, Signature>,
+        arguments: &FunctionArgsList<'a>,
+    ) -> Result<'a, Self> {
+        let mut checker = FunctionChecker {
+            function,
+            signatures,
+            variables: Vec::with_capacity(arguments.args.len()),
+            params: arguments.args.len(),
+            scopes: Scopes::default(),
+        };
+
+        for (name, arg_type) in argument_types(arguments)? {
+            if checker.declare(name.symbol, Some(arg_type)).is_some() {
+                return Err(AnalyserError::DuplicateVariable(name));
+            }
+        }
+
+        Ok(checker)
+    }
+
+    // gives the variable the name referred to before, if there was one in the same scope
+    fn declare(&mut self, name: Symbol<'a>, var_type: Option<Type>) -> Option<usize> {
+        self.variables.push((name, var_type));
+
+        self.scopes.declare(name, self.variables.len() - 1)
+    }
+
+    fn variable(&self, name: Symbol<'a>) -> Option<Option<Type>> {
+        self.scopes.get(name).map(|&i| self.variables[i].1)
+    }
+
+    fn locals(&self) -> Vec<(Symbol<'a>, Type)> {
+        self.variables[self.params..]
+            .iter()
+            .map(|&(name, t)| (name, t.unwrap_or(Type::Unit)))
+            .collect()
+    }
+
+    fn scoped_block(&mut self, block: &[CodeBlockStatement<'a>]) -> Result<'a, Option<Type>> {
+        self.scopes.enter_branch();
+
+        let block_type = self.block(block);
+
+        self.scopes.exit_branch();
+
+        block_type
+    }
+
+    fn block(&mut self, block: &[CodeBlockStatement<'a>]) -> Result<'a, Option<Type>> {
+        let mut block_type = Some(Type::Unit);
+
+        for statement in block {
+            block_type = self.statement(statement)?;
+        }
+
+        Ok(block_type)
+    }
+
+    fn statement(&mut self, statement: &CodeBlockStatement<'a>) -> Result<'a, Option<Type>> {
+        match statement {
+            CodeBlockStatement::BareExpression(expr) => self.expression(expr),
+            CodeBlockStatement::Declaration(Declaration::Assignment { name, expr }) => {
+                let expr_type = self.expression_as(expr, self.variable(name.symbol).flatten())?;
+
+                if expr_type == Some(Type::Unit) {
+                    return Err(AnalyserError::UnitAssignment(*name));
+                }
+
+                match self.scopes.get(name.symbol) {
+                    Some(&i) => self.variables[i].1 = self.unify(self.variables[i].1, expr_type)?,
+                    None => {
+                        // an earlier branch of the same `if` might have assigned it already
+                        let earlier = self
+                            .scopes
+                            .from_earlier_branches(name.symbol)
+                            .copied()
+                            .find(|&i| self.unify(self.variables[i].1, expr_type).is_ok());
+
+                        match earlier {
+                            Some(i) => {
+                                self.variables[i].1 = self.unify(self.variables[i].1, expr_type)?;
+                                self.scopes.declare(name.symbol, i);
+                            }
+                            None => {
+                                self.declare(name.symbol, expr_type);
+                            }
+                        }
+                    }
+                }
+
+                Ok(Some(Type::Unit))
+            }
+            // closures get rejected by code gen
+            CodeBlockStatement::Declaration(Declaration::FunctionDecl { .. }) => {
+                Ok(Some(Type::Unit))
+            }
+            CodeBlockStatement::IfStatement { cases, else_case } => {
+                let mut result_type = None;
+
+                self.scopes.enter_if();
+
+                for IfStatementCase { condition, block } in *cases {
+                    let condition_type = self.expression(condition)?;
+
+                    self.expect(Type::Bool, condition_type)?;
 
-                let already_declared = symbols.insert(name, symbol).is_some();
+                    let block_type = self.scoped_block(block)?;
 
-                if already_declared {
-                    return Err(AnalyserError::DuplicateVariable(name));
+                    // without an else branch the value is thrown away, so the branches can differ
+                    if else_case.is_some() {
+                        result_type = self.unify(result_type, block_type)?;
+                    }
+                }
+
+                let if_type = match else_case {
+                    Some(block) => {
+                        let block_type = self.scoped_block(block)?;
+
+                        self.unify(result_type, block_type)
+                    }
+                    // without an else branch there might not be a value
+                    None => Ok(Some(Type::Unit)),
                 };
+
+                self.scopes.exit_if(else_case.is_some());
+
+                if_type
             }
         }
     }
 
-    let main = symbols.get("main").ok_or(AnalyserError::NoMain)?;
+    fn expression(&mut self, expr: &Expression<'a>) -> Result<'a, Option<Type>> {
+        self.expression_as(expr, None)
+    }
+
+    // `expected` is what the expression is used as, when that's known, which integers without
+    // a suffix take on
+    fn expression_as(
+        &mut self,
+        expr: &Expression<'a>,
+        expected: Option<Type>,
+    ) -> Result<'a, Option<Type>> {
+        match expr {
+            &Expression::Constant(Constant::Int(int)) => self.integer(int, int.value, expected),
+            &Expression::Constant(Constant::Int64(int)) => self.fits(int, int.value, Type::Int64),
+            Expression::Constant(Constant::Float(_)) => Ok(Some(Type::Float)),
+            Expression::Constant(Constant::Bool(_)) => Ok(Some(Type::Bool)),
+            Expression::Constant(Constant::Str(_)) => Ok(Some(Type::Str)),
+            &Expression::Variable(name) => self.variable(name.symbol).ok_or_else(|| {
+                if self.scopes.possibly_unassigned(name.symbol) {
+                    AnalyserError::PossiblyUnassignedVariable(name)
+                } else {
+                    AnalyserError::UndefinedVariable(name)
+                }
+            }),
+            &Expression::FunctionCall { name, args } => {
+                let signature = self
+                    .signatures
+                    .get(&name.symbol)
+                    .ok_or(AnalyserError::UndefinedFunction(name))?;
+
+                if args.len() != signature.params.len() {
+                    return Err(AnalyserError::WrongNumberOfArguments {
+                        function: name,
+                        expected: signature.params.len(),
+                        found: args.len(),
+                    });
+                }
+
+                for (arg, &param_type) in args.iter().zip(&signature.params) {
+                    let arg_type = self.expression_as(arg, Some(param_type))?;
+
+                    self.expect(param_type, arg_type)?;
+                }
+
+                Ok(signature.return_type)
+            }
+            Expression::BinaryOp {
+                left,
+                operator,
+                right,
+            } => {
+                let expected = match operator {
+                    BinaryOperator::DoubleEquals => None,
+                    _ => expected,
+                };
+
+                let (left, right) = self.operands(left, right, expected)?;
+
+                let operand_type = self.unify(left, right)?;
 
-    match main {
-        Symbol {
-            decl:
-                Declaration::FunctionDecl {
-                    name,
-                    arguments: FunctionArgsList { args },
-                    body,
-                },
-            exported,
-            usage_count,
-        } => {
-            let mut local_vars = Vec::with_capacity(args.len());
+                match operator {
+                    BinaryOperator::DoubleEquals => {
+                        self.expect_value(operand_type)?;
 
-            for FunctionArg { name } in args {
-                if local_vars.contains(name) {
-                    return Err(AnalyserError::DuplicateVariable(name));
+                        Ok(Some(Type::Bool))
+                    }
+                    _ => self.expect_number(operand_type),
                 }
+            }
+            Expression::Negation(expr) => {
+                // the smallest integers can only be written negated. Folding constants can
+                // make negative ones, which are negated like anything else
+                let expr_type = match expr {
+                    Expression::Constant(Constant::Int(int)) if int.value >= 0 => {
+                        self.integer(*int, -int.value, expected)?
+                    }
+                    Expression::Constant(Constant::Int64(int)) if int.value >= 0 => {
+                        self.fits(*int, -int.value, Type::Int64)?
+                    }
+                    expr => self.expression_as(expr, expected)?,
+                };
+
+                self.expect_number(expr_type)
+            }
+        }
+    }
+
+    // An `Int64` when that's what's expected and it can be, and otherwise an `Int32`
+    fn integer(
+        &self,
+        int: Integer,
+        value: i128,
+        expected: Option<Type>,
+    ) -> Result<'a, Option<Type>> {
+        if expected == Some(Type::Int64) && !int.has_suffix {
+            return self.fits(int, value, Type::Int64);
+        }
+
+        self.fits(int, value, Type::Int32)
+    }
+
+    fn fits(&self, int: Integer, value: i128, target: Type) -> Result<'a, Option<Type>> {
+        let fits = match target {
+            Type::Int64 => i64::try_from(value).is_ok(),
+            _ => i32::try_from(value).is_ok(),
+        };
+
+        match fits {
+            true => Ok(Some(target)),
+            false => Err(AnalyserError::IntegerOutOfRange {
+                function: self.function,
+                literal: int,
+                value,
+                target,
+            }),
+        }
+    }
+
+    // When one side is only integers without a suffix it's checked second, so they can take on
+    // the type of the other side
+    fn operands(
+        &mut self,
+        left: &Expression<'a>,
+        right: &Expression<'a>,
+        expected: Option<Type>,
+    ) -> Result<'a, (Option<Type>, Option<Type>)> {
+        if left.is_untyped_integer() {
+            let right_type = self.expression_as(right, expected)?;
+            let left_type = self.expression_as(left, right_type.or(expected))?;
+
+            Ok((left_type, right_type))
+        } else {
+            let left_type = self.expression_as(left, expected)?;
+            let right_type = self.expression_as(right, left_type.or(expected))?;
+
+            Ok((left_type, right_type))
+        }
+    }
 
-                local_vars.push(name);
+    fn unify(&self, a: Option<Type>, b: Option<Type>) -> Result<'a, Option<Type>> {
+        match (a, b) {
+            (Some(expected), Some(found)) if expected != found => {
+                Err(self.mismatch(expected, found))
             }
+            _ => Ok(a.or(b)),
+        }
+    }
+
+    fn expect(&self, expected: Type, found: Option<Type>) -> Result<'a, ()> {
+        self.unify(Some(expected), found).map(|_| ())
+    }
 
-            for statement in body.iter().enumerate() {
-                // match statement {
-                //     FuncBodyStatement::Declaration()
-                // }
+    fn expect_number(&self, found: Option<Type>) -> Result<'a, Option<Type>> {
+        match found {
+            Some(t @ Type::Unit) | Some(t @ Type::Bool) | Some(t @ Type::Str) => {
+                Err(self.mismatch(Type::Int32, t))
             }
+            _ => Ok(found),
         }
-        _ => return Err(AnalyserError::MainIsNotAFunction),
     }
 
-    Ok(())
+    // Strings are compared by address, so comparing them isn't allowed
+    fn expect_value(&self, found: Option<Type>) -> Result<'a, Option<Type>> {
+        match found {
+            Some(t @ Type::Unit) | Some(t @ Type::Str) => Err(self.mismatch(Type::Int32, t)),
+            _ => Ok(found),
+        }
+    }
+
+    fn mismatch(&self, expected: Type, found: Type) -> AnalyserError<'a> {
+        AnalyserError::TypeMismatch {
+            function: self.function,
+            expected,
+            found,
+        }
+    }
 }
 
+#[derive(Debug, Copy, Clone)]
 pub enum AnalyserError<'a> {
-    ParseError(ParseError<'a>),
-    DuplicateVariable(&'a str),
-    NoMain,
-    MainIsNotAFunction,
+    DuplicateDeclaration(Name<'a>),
+    DuplicateVariable(Name<'a>),
+    UndefinedVariable(Name<'a>),
+    // assigned by some branches of an earlier `if`, but not all of them
+    PossiblyUnassignedVariable(Name<'a>),
+    UndefinedFunction(Name<'a>),
+    WrongNumberOfArguments {
+        function: Name<'a>,
+        expected: usize,
+        found: usize,
+    },
+    // these don't point at anything in the source, so say which function they're in
+    TypeMismatch {
+        function: Name<'a>,
+        expected: Type,
+        found: Type,
+    },
+    UnitAssignment(Name<'a>),
+    UnknownType(Name<'a>),
+    IntegerOutOfRange {
+        function: Name<'a>,
+        literal: Integer,
+        // too big for anything when it's as big as an `i128` goes
+        value: i128,
+        target: Type,
+    },
 }
 
-impl<'a> From<ParseError<'a>> for AnalyserError<'a> {
-    fn from(error: ParseError<'a>) -> Self {
-        AnalyserError::ParseError(error)
+impl<'a> fmt::Display for AnalyserError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use AnalyserError::*;
+
+        match self {
+            DuplicateDeclaration(name) => write!(f, "`{}` is already declared", name),
+            DuplicateVariable(name) => write!(f, "there's already a parameter `{}`", name),
+            UndefinedVariable(name) => write!(f, "`{}` isn't defined", name),
+            PossiblyUnassignedVariable(name) => write!(
+                f,
+                "`{}` is possibly unassigned, as not every branch of the `if` before assigns it",
+                name
+            ),
+            UndefinedFunction(name) => write!(f, "there's no function `{}`", name),
+            WrongNumberOfArguments {
+                function,
+                expected,
+                found,
+            } => write!(
+                f,
+                "`{}` takes {} arguments but was given {}",
+                function, expected, found
+            ),
+            TypeMismatch {
+                function,
+                expected,
+                found,
+            } => write!(
+                f,
+                "expected {:?} but found {:?} in `{}`",
+                expected, found, function
+            ),
+            UnitAssignment(name) => write!(f, "`{}` is assigned something without a value", name),
+            UnknownType(name) => write!(f, "there's no type `{}`", name),
+            IntegerOutOfRange {
+                function,
+                value,
+                target,
+                ..
+            } if value.unsigned_abs() == i128::MAX.unsigned_abs() => write!(
+                f,
+                "an integer in `{}` is far too big to fit in {:?}",
+                function, target
+            ),
+            IntegerOutOfRange {
+                function,
+                value,
+                target,
+                ..
+            } => write!(f, "{} doesn't fit in {:?} in `{}`", value, target, function),
+        }
     }
 }
 
-// #[derive(Debug)]
-// pub struct Analyser<'a> {
-//     parser: Parser<'a>,
-// }
+impl<'a> std::error::Error for AnalyserError<'a> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::ParseSession;
+    use insta::assert_debug_snapshot;
+    use std::fs;
+    use test_case::test_case;
+
+    #[test_case("src/fixtures/example_program.lang"; "example program")]
+    #[test_case("src/fixtures/fibonacci.lang"; "fibonacci")]
+    #[test_case("src/fixtures/unit_functions.lang"; "unit functions")]
+    #[test_case("src/fixtures/booleans.lang"; "booleans")]
+    #[test_case("src/fixtures/int64.lang"; "int64")]
+    #[test_case("src/fixtures/hello.lang"; "hello")]
+    fn fixtures(fixture_file_name: &str) -> std::io::Result<()> {
+        let contents = fs::read_to_string(fixture_file_name)?;
+
+        let session = ParseSession::new();
+
+        let ast = session.parse(&contents).unwrap();
+
+        let analysis = analyse(&ast);
+
+        assert_debug_snapshot!(analysis);
+
+        Ok(())
+    }
+
+    #[test_case("fn f()\n    x = g()\n\nfn g()\n    y = 1\n"; "assigning unit")]
+    #[test_case("fn f(x)\n    if x == 1\n        1\n    else\n        f()\n"; "wrong number of arguments")]
+    #[test_case("fn f(x)\n    if x == 1\n        1\n    else\n        1.5\n"; "mismatched if branches")]
+    #[test_case("fn f()\n    if 5\n        1\n    else\n        2\n"; "non boolean condition")]
+    #[test_case("fn f()\n    true + 1\n"; "adding booleans")]
+    #[test_case("fn f(x)\n    y = -(x == 1)\n"; "negating booleans")]
+    #[test_case("fn f(x)\n    y + x\n"; "undefined variable")]
+    #[test_case("fn f(x, x)\n    x\n"; "duplicate argument")]
+    #[test_case("fn f()\n    3000000000\n"; "int32 out of range")]
+    #[test_case("fn f(x: Int64, y)\n    x + y\n"; "mixing integer sizes")]
+    #[test_case("fn f(x: Int64)\n    x + 1i32\n"; "integers with a suffix keep their size")]
+    #[test_case("fn f()\n    x = -2147483649\n"; "negative int32 out of range")]
+    #[test_case("fn f()\n    9223372036854775808i64\n"; "int64 out of range")]
+    #[test_case("fn f()\n    x = -9223372036854775809i64\n"; "negative int64 out of range")]
+    #[test_case("fn f(x: Text)\n    x\n"; "unknown argument type")]
+    #[test_case("fn f()\n    \"a\" == \"a\"\n"; "comparing strings")]
+    #[test_case("fn f()\n    print(1)\n"; "printing a number")]
+    #[test_case("fn f(c)\n    if c == 0\n        x = 1\n    else\n        x = 2i64\n    x\n"; "variable of different types used after branches")]
+    #[test_case("fn f(c)\n    if c == 0\n        x = 1\n    else if c == 1\n        x = 2\n    x\n"; "variable assigned in every case without an else")]
+    fn errors(source: &str) {
+        let session = ParseSession::new();
+
+        let ast = session.parse(source).unwrap();
+
+        assert_debug_snapshot!(analyse(&ast));
+    }
+
+    #[test]
+    fn if_without_else_has_no_value() {
+        let source = "fn f(c)\n    if c == 0\n        1\n    else if c == 1\n        true\n";
+
+        let session = ParseSession::new();
+
+        let ast = session.parse(source).unwrap();
+
+        let analysis = analyse(&ast).unwrap();
+
+        assert_eq!(
+            analysis.functions[&ast.arena.intern("f")].return_type,
+            Type::Unit
+        );
+    }
+
+    #[test]
+    fn branches_have_their_own_variables() {
+        let source = "fn f(c)\n    y = 1\n    if c == 0\n        x = 1\n        y = x\n    else\n        x = 2i64\n        y = 3\n    y\n";
+
+        let session = ParseSession::new();
+
+        let ast = session.parse(source).unwrap();
+
+        assert_debug_snapshot!(analyse(&ast));
+    }
+
+    #[test_case("fn f(c)\n    if c == 0\n        x = 1\n    else\n        x = 2\n    x\n"; "in every branch")]
+    #[test_case("fn f(c)\n    if c == 0\n        y = 5\n    y\n"; "in one branch")]
+    #[test_case("fn f(c)\n    if c == 0\n        if c == 1\n            x = 1\n        else\n            x = 2\n    else\n        x = 3\n    x\n"; "in nested branches")]
+    fn variables_from_branches_are_there_after_them(source: &str) {
+        let session = ParseSession::new();
+
+        let ast = session.parse(source).unwrap();
 
-// impl<'a> Analyser<'a> {
-//     fn collect(&mut self, )
-// }
+        assert_debug_snapshot!(analyse(&ast).map(|analysis| analysis.functions));
+    }
+}
@@ -1,33 +1,132 @@
 use super::operators::*;
+use super::parser::ParseSession;
+use super::symbol::{Interner, Name, Symbol};
 use super::tokens::*;
+use super::OwnedCompileError;
+use bumpalo::Bump;
+use self_cell::self_cell;
+use std::fmt;
 
-#[derive(Debug, Default)]
+// The nodes of an `Ast` live in the arena of the session that parsed it, which passes that
+// rewrite the tree allocate from as well
+#[derive(Copy, Clone)]
 pub struct Ast<'a> {
-    pub statements: Vec<TopLevelStatement<'a>>,
+    pub statements: &'a [TopLevelStatement<'a>],
+    pub arena: &'a Arena,
 }
 
 impl<'a> Ast<'a> {
-    pub fn append_statement(&mut self, statement: TopLevelStatement<'a>) {
-        self.statements.push(statement)
+    pub fn new(statements: &'a [TopLevelStatement<'a>], arena: &'a Arena) -> Self {
+        Ast { statements, arena }
+    }
+}
+
+// The arena is left out, as it's the same for everything parsed in a session
+impl<'a> fmt::Debug for Ast<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Ast")
+            .field("statements", &self.statements)
+            .finish()
+    }
+}
+
+// Allocating from an arena is just bumping a pointer, and everything in it is freed at once,
+// which is much quicker than allocating and freeing every node of a large tree separately. The
+// names in the nodes are interned in it too, so they go when it does
+#[derive(Debug, Default)]
+pub struct Arena {
+    bump: Bump,
+    names: Interner,
+}
+
+impl Arena {
+    pub fn intern(&self, name: &str) -> Symbol<'_> {
+        self.names.intern(name)
+    }
+
+    // Nothing in the arena is ever dropped, so only things that don't need dropping go in it
+    pub fn alloc<T: Copy>(&self, value: T) -> &T {
+        self.bump.alloc(value)
+    }
+
+    pub fn alloc_slice<T: Copy>(&self, values: impl IntoIterator<Item = T>) -> &[T] {
+        let mut slice = self.vec();
+
+        slice.extend(values);
+
+        slice.into_bump_slice()
+    }
+
+    // for slices built up one item at a time
+    pub(crate) fn vec<T: Copy>(&self) -> bumpalo::collections::Vec<'_, T> {
+        bumpalo::collections::Vec::new_in(&self.bump)
+    }
+
+    pub fn allocated_bytes(&self) -> usize {
+        self.bump.allocated_bytes()
     }
 }
 
+// What an `OwnedAst` borrows from
 #[derive(Debug)]
+pub struct AstOwner {
+    source: String,
+    session: ParseSession,
+}
+
+self_cell!(
+    // An `Ast` kept together with the source it borrows its names from and the session it was
+    // allocated in, so it can be stored for as long as needed
+    pub struct OwnedAst {
+        owner: AstOwner,
+
+        #[covariant]
+        dependent: Ast,
+    }
+
+    impl {Debug}
+);
+
+impl OwnedAst {
+    pub fn parse(source: impl Into<String>) -> Result<OwnedAst, OwnedCompileError> {
+        let owner = AstOwner {
+            source: source.into(),
+            session: ParseSession::new(),
+        };
+
+        OwnedAst::try_new(owner, |owner| {
+            owner
+                .session
+                .parse(&owner.source)
+                .map_err(|error| OwnedCompileError::new(&owner.source, &error.into()))
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.borrow_owner().source
+    }
+
+    pub fn ast(&self) -> &Ast<'_> {
+        self.borrow_dependent()
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
 pub enum Declaration<'a> {
     Assignment {
-        name: &'a str,
+        name: Name<'a>,
         expr: Expression<'a>,
     },
     FunctionDecl {
-        name: &'a str,
+        name: Name<'a>,
         arguments: FunctionArgsList<'a>,
-        body: Vec<CodeBlockStatement<'a>>,
+        body: CodeBlock<'a>,
     },
 }
 
 impl<'a> Declaration<'a> {
-    pub fn name(&self) -> &'a str {
-        match self {
+    pub fn name(&self) -> Name<'a> {
+        match *self {
             Declaration::Assignment { name, expr: _ } => name,
             Declaration::FunctionDecl {
                 name,
@@ -38,7 +137,7 @@ impl<'a> Declaration<'a> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
 pub enum TopLevelStatement<'a> {
     Declaration {
         decl: Declaration<'a>,
@@ -46,46 +145,193 @@ pub enum TopLevelStatement<'a> {
     },
 }
 
-pub type CodeBlock<'a> = Vec<CodeBlockStatement<'a>>;
+pub type CodeBlock<'a> = &'a [CodeBlockStatement<'a>];
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
 pub enum CodeBlockStatement<'a> {
     Declaration(Declaration<'a>),
     BareExpression(Expression<'a>),
     IfStatement {
-        cases: Vec<IfStatementCase<'a>>,
-        else_case: Option<Box<CodeBlock<'a>>>,
+        cases: &'a [IfStatementCase<'a>],
+        else_case: Option<CodeBlock<'a>>,
     },
 }
 
-#[derive(Debug)]
+impl<'a> CodeBlockStatement<'a> {
+    // names of the functions called, in the order they get called
+    pub fn calls(&self, out: &mut Vec<Name<'a>>) {
+        match self {
+            CodeBlockStatement::Declaration(Declaration::Assignment { expr, .. }) => {
+                expr.calls(out)
+            }
+            CodeBlockStatement::Declaration(Declaration::FunctionDecl { body, .. }) => {
+                for statement in *body {
+                    statement.calls(out);
+                }
+            }
+            CodeBlockStatement::BareExpression(expr) => expr.calls(out),
+            CodeBlockStatement::IfStatement { cases, else_case } => {
+                for IfStatementCase { condition, block } in *cases {
+                    condition.calls(out);
+
+                    for statement in *block {
+                        statement.calls(out);
+                    }
+                }
+
+                for statement in else_case.iter().flat_map(|block| block.iter()) {
+                    statement.calls(out);
+                }
+            }
+        }
+    }
+
+    // string constants, in the order they appear
+    pub fn strings(&self, out: &mut Vec<&'a str>) {
+        match self {
+            CodeBlockStatement::Declaration(Declaration::Assignment { expr, .. }) => {
+                expr.strings(out)
+            }
+            CodeBlockStatement::Declaration(Declaration::FunctionDecl { body, .. }) => {
+                for statement in *body {
+                    statement.strings(out);
+                }
+            }
+            CodeBlockStatement::BareExpression(expr) => expr.strings(out),
+            CodeBlockStatement::IfStatement { cases, else_case } => {
+                for IfStatementCase { condition, block } in *cases {
+                    condition.strings(out);
+
+                    for statement in *block {
+                        statement.strings(out);
+                    }
+                }
+
+                for statement in else_case.iter().flat_map(|block| block.iter()) {
+                    statement.strings(out);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
 pub struct IfStatementCase<'a> {
     pub condition: Expression<'a>,
     pub block: CodeBlock<'a>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
 pub struct FunctionArgsList<'a> {
-    pub args: Vec<FunctionArg<'a>>,
+    pub args: &'a [FunctionArg<'a>],
 }
 
 #[derive(Debug, Copy, Clone)]
 pub struct FunctionArg<'a> {
-    pub name: &'a str,
+    pub name: Name<'a>,
+    pub type_name: Option<Name<'a>>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
 pub enum Expression<'a> {
-    Variable(&'a str),
+    Variable(Name<'a>),
     Constant(Constant<'a>),
     FunctionCall {
-        name: &'a str,
-        args: Vec<Expression<'a>>,
+        name: Name<'a>,
+        args: &'a [Expression<'a>],
     },
     BinaryOp {
-        left: Box<Expression<'a>>,
+        left: &'a Expression<'a>,
         operator: BinaryOperator,
-        right: Box<Expression<'a>>,
+        right: &'a Expression<'a>,
     },
-    Negation(Box<Expression<'a>>),
+    Negation(&'a Expression<'a>),
+}
+
+impl<'a> Expression<'a> {
+    pub fn calls(&self, out: &mut Vec<Name<'a>>) {
+        match self {
+            Expression::Variable(_) | Expression::Constant(_) => {}
+            Expression::FunctionCall { name, args } => {
+                for arg in *args {
+                    arg.calls(out);
+                }
+
+                out.push(*name);
+            }
+            Expression::BinaryOp { left, right, .. } => {
+                left.calls(out);
+                right.calls(out);
+            }
+            Expression::Negation(expr) => expr.calls(out),
+        }
+    }
+
+    pub fn strings(&self, out: &mut Vec<&'a str>) {
+        match self {
+            Expression::Constant(Constant::Str(string)) => out.push(string),
+            Expression::Variable(_) | Expression::Constant(_) => {}
+            Expression::FunctionCall { args, .. } => {
+                for arg in *args {
+                    arg.strings(out);
+                }
+            }
+            Expression::BinaryOp { left, right, .. } => {
+                left.strings(out);
+                right.strings(out);
+            }
+            Expression::Negation(expr) => expr.strings(out),
+        }
+    }
+
+    // Only integers without a suffix, which can be an `Int32` or an `Int64` depending on what
+    // they're used with
+    pub fn is_untyped_integer(&self) -> bool {
+        match self {
+            Expression::Constant(Constant::Int(int)) => !int.has_suffix,
+            Expression::BinaryOp {
+                left,
+                operator,
+                right,
+            } => {
+                !matches!(operator, BinaryOperator::DoubleEquals)
+                    && left.is_untyped_integer()
+                    && right.is_untyped_integer()
+            }
+            Expression::Negation(expr) => expr.is_untyped_integer(),
+            Expression::Variable(_) | Expression::Constant(_) | Expression::FunctionCall { .. } => {
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ErrorKind;
+
+    fn parse_owned() -> OwnedAst {
+        let source = String::from("fn double(x)\n    x * 2\n");
+
+        OwnedAst::parse(source).unwrap()
+    }
+
+    #[test]
+    fn owned_ast_outlives_the_source_it_was_given() {
+        let owned = parse_owned();
+
+        let TopLevelStatement::Declaration { decl, .. } = &owned.ast().statements[0];
+
+        assert_eq!(decl.name().as_str(), "double");
+        assert!(owned.source().starts_with("fn double"));
+    }
+
+    #[test]
+    fn owned_parse_errors_say_where_they_are() {
+        let error = OwnedAst::parse("fn f(\n").unwrap_err();
+
+        assert_eq!(error.kind, ErrorKind::Parse);
+        assert!(error.diagnostics[0].location.is_some());
+    }
 }
@@ -0,0 +1,37 @@
+use crate::types::Type;
+
+// Functions provided by the compiler rather than declared in the program.
+// They need the host to provide WASI, as that's where their output goes
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Builtin {
+    Print,
+    Println,
+}
+
+impl Builtin {
+    pub const ALL: [Builtin; 2] = [Builtin::Print, Builtin::Println];
+
+    pub fn from_name(name: &str) -> Option<Builtin> {
+        Builtin::ALL
+            .iter()
+            .copied()
+            .find(|builtin| builtin.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Print => "print",
+            Builtin::Println => "println",
+        }
+    }
+
+    pub fn params(self) -> &'static [Type] {
+        match self {
+            Builtin::Print | Builtin::Println => &[Type::Str],
+        }
+    }
+
+    pub fn return_type(self) -> Type {
+        Type::Unit
+    }
+}
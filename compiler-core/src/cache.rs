@@ -0,0 +1,453 @@
+use crate::analyser::Analysis;
+use crate::ast::*;
+use crate::code_gen::{function_to_wasm, module_header, CodeGenError, StaticData, TargetFeatures};
+use crate::parser::ParseSession;
+use crate::symbol::Symbol;
+use crate::wasm::*;
+use crate::{front_end, CompileError, OptimisationLevel};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+// Part of every key, so output from other versions of the compiler is never reused
+const CACHE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Remembers compiled modules by the hash of their source, and compiled functions by the hash of
+// everything their code depends on: their body after the AST passes, their types, and the
+// signatures of the functions they call. Editing a function then only recompiles it and the
+// functions it was inlined into
+#[derive(Debug, Default)]
+pub struct CompileCache {
+    dir: Option<PathBuf>,
+    entries: HashMap<(Table, u64), String>,
+    stats: CacheStats,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub modules_reused: usize,
+    pub functions_reused: usize,
+    pub functions_compiled: usize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum Table {
+    Modules,
+    Functions,
+}
+
+impl Table {
+    fn dir_name(self) -> &'static str {
+        match self {
+            Table::Modules => "modules",
+            Table::Functions => "functions",
+        }
+    }
+}
+
+impl CompileCache {
+    // Only keeps entries for as long as the cache is alive
+    pub fn in_memory() -> Self {
+        CompileCache::default()
+    }
+
+    // Also keeps entries as files in `dir`, so later builds can use them
+    pub fn persistent(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+
+        for table in [Table::Modules, Table::Functions] {
+            fs::create_dir_all(dir.join(table.dir_name()))?;
+        }
+
+        Ok(CompileCache {
+            dir: Some(dir),
+            ..CompileCache::default()
+        })
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    // Gives the same output as `compile_with`
+    pub fn compile<'a>(
+        &mut self,
+        session: &'a ParseSession,
+        source: &'a str,
+        optimisation: OptimisationLevel,
+    ) -> Result<String, CompileError<'a>> {
+        let settings = format!("{} {:?}", CACHE_VERSION, optimisation);
+
+        let module_key = content_hash(&[&settings, source]);
+
+        if let Some(text) = self.get(Table::Modules, module_key) {
+            self.stats.modules_reused += 1;
+
+            self.entries
+                .retain(|&(table, key), _| table == Table::Functions || key == module_key);
+
+            return Ok(text);
+        }
+
+        let (ast, analysis) = front_end(session, source, optimisation)?;
+
+        let data = StaticData::new(&ast, &analysis);
+
+        // functions refer to strings by address, so depend on where all of them are
+        let function_settings = format!("{} {:?}", settings, data.memory());
+
+        let mut module = module_header(ast.arena, &data, TargetFeatures::default());
+        let mut function_texts = HashMap::new();
+        let mut compiled = HashMap::new();
+        let mut used = HashSet::new();
+
+        for statement in ast.statements {
+            let TopLevelStatement::Declaration { decl, exported } = statement;
+
+            let (name, body) = match decl {
+                Declaration::FunctionDecl { name, body, .. } => (name.symbol, body),
+                Declaration::Assignment { .. } => {
+                    return Err(CodeGenError::TopLevelAssignmentNotYetSupported.into())
+                }
+            };
+
+            let key = function_key(&function_settings, decl, &analysis);
+
+            used.insert(key);
+
+            let function = match self.get(Table::Functions, key) {
+                Some(text) => {
+                    self.stats.functions_reused += 1;
+                    function_texts.insert(name, text);
+
+                    // stands in for the real function when optimising and validating the others
+                    stub(name, &analysis)?
+                }
+                None => {
+                    self.stats.functions_compiled += 1;
+                    compiled.insert(name, key);
+
+                    function_to_wasm(
+                        ast.arena,
+                        name,
+                        body,
+                        &analysis,
+                        &data,
+                        TargetFeatures::default(),
+                    )?
+                }
+            };
+
+            module.add_function(function, *exported);
+        }
+
+        if optimisation >= OptimisationLevel::Basic {
+            peephole::optimise_module(&mut module);
+        }
+
+        validate::validate_module(&module)?;
+
+        let format = WasmIndentation::default().increase_indent();
+
+        for function in module.functions() {
+            if let Entry::Vacant(entry) = function_texts.entry(function.name()) {
+                let mut function_text = String::new();
+
+                function.write_text(&mut function_text, format)?;
+
+                self.insert(
+                    Table::Functions,
+                    compiled[&function.name()],
+                    function_text.clone(),
+                );
+                entry.insert(function_text);
+            }
+        }
+
+        let mut text = String::new();
+
+        module.write_text_with(&mut text, WasmIndentation::default(), &function_texts)?;
+
+        self.insert(Table::Modules, module_key, text.clone());
+
+        // only what this build used is kept in memory, so watching a file doesn't keep every
+        // version of it. Anything older is still read back from disk if it's needed again
+        self.entries.retain(|&(table, key), _| match table {
+            Table::Modules => key == module_key,
+            Table::Functions => used.contains(&key),
+        });
+
+        Ok(text)
+    }
+
+    fn path(&self, table: Table, key: u64) -> Option<PathBuf> {
+        let dir = self.dir.as_ref()?;
+
+        Some(dir.join(table.dir_name()).join(format!("{:016x}.wat", key)))
+    }
+
+    fn get(&mut self, table: Table, key: u64) -> Option<String> {
+        if let Some(text) = self.entries.get(&(table, key)) {
+            return Some(text.clone());
+        }
+
+        let text = fs::read_to_string(self.path(table, key)?).ok()?;
+
+        self.entries.insert((table, key), text.clone());
+
+        Some(text)
+    }
+
+    fn insert(&mut self, table: Table, key: u64, text: String) {
+        if let Some(path) = self.path(table, key) {
+            let temp_path = path.with_extension("tmp");
+
+            // writing then renaming means a build that's killed halfway can't leave half an
+            // entry behind, and one that can't be written just makes the next build slower
+            if fs::write(&temp_path, &text).is_ok() {
+                let _ = fs::rename(temp_path, path);
+            }
+        }
+
+        self.entries.insert((table, key), text);
+    }
+}
+
+fn function_key(settings: &str, decl: &Declaration, analysis: &Analysis) -> u64 {
+    let mut parts = vec![settings.to_string(), format!("{:?}", decl)];
+
+    let name = decl.name();
+
+    let mut calls = vec![name];
+
+    if let Declaration::FunctionDecl { body, .. } = decl {
+        for statement in *body {
+            statement.calls(&mut calls);
+        }
+    }
+
+    for called in calls {
+        if let Some(function) = analysis.functions.get(&called.symbol) {
+            parts.push(format!(
+                "{} {:?} {:?}",
+                called, function.params, function.return_type
+            ));
+        }
+    }
+
+    if let Some(function) = analysis.functions.get(&name.symbol) {
+        parts.push(format!("{:?}", function.locals));
+    }
+
+    content_hash(&parts.iter().map(String::as_str).collect::<Vec<_>>())
+}
+
+// A function with the same signature, but none of the code
+fn stub<'a>(name: Symbol<'a>, analysis: &Analysis<'a>) -> Result<WasmFunction<'a>, CodeGenError> {
+    let function = analysis
+        .functions
+        .get(&name)
+        .ok_or(CodeGenError::MissingAnalysis)?;
+
+    let params = function
+        .params
+        .iter()
+        .map(|&(name, t)| Ok((name, t.wasm_type().ok_or(CodeGenError::UnitValue)?)))
+        .collect::<Result<_, _>>()?;
+
+    Ok(WasmFunction::new(
+        name,
+        params,
+        BTreeMap::new(),
+        function.return_type.wasm_type(),
+        vec![WasmInstr::Unreachable],
+    ))
+}
+
+// FNV-1a, which unlike the std hashers is the same on every platform and compiler version, so
+// the hashes can be stored. Each part ends in a byte that can't appear in UTF-8, so different
+// splits of the same text hash differently
+fn content_hash(parts: &[&str]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+
+    for part in parts {
+        for byte in part.bytes().chain(Some(0xff)) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile_with;
+    use std::fs;
+    use test_case::test_case;
+
+    const PROGRAM: &str = "fn fibo(n)
+    if n == 0
+        0
+    else if n == 1
+        1
+    else
+        fibo(n - 1) + fibo(n - 2)
+
+fn triple(n)
+    n * 3
+
+export fn main(n)
+    fibo(n) + triple(n)
+";
+
+    #[test_case("example_program")]
+    #[test_case("fibonacci")]
+    #[test_case("unit_functions")]
+    #[test_case("booleans")]
+    #[test_case("int64")]
+    #[test_case("constants")]
+    #[test_case("inlining")]
+    #[test_case("tail_calls")]
+    #[test_case("comments")]
+    fn output_is_the_same_as_compiling_directly(name: &str) {
+        let session = ParseSession::new();
+
+        let contents = fs::read_to_string(format!("src/fixtures/{}.lang", name)).unwrap();
+
+        let mut cache = CompileCache::in_memory();
+
+        for level in [OptimisationLevel::None, OptimisationLevel::Basic] {
+            let expected = compile_with(&session, &contents, level).unwrap();
+
+            assert_eq!(cache.compile(&session, &contents, level).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn unchanged_sources_are_reused() {
+        let session = ParseSession::new();
+
+        let mut cache = CompileCache::in_memory();
+
+        let first = cache
+            .compile(&session, PROGRAM, OptimisationLevel::Basic)
+            .unwrap();
+        let second = cache
+            .compile(&session, PROGRAM, OptimisationLevel::Basic)
+            .unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                modules_reused: 1,
+                functions_reused: 0,
+                functions_compiled: 2,
+            }
+        );
+    }
+
+    // `triple` is inlined into `main`, but `fibo` is only called by it
+    #[test_case("        0\n", "        2\n", 1, 1; "changing a called function")]
+    #[test_case("n * 3", "n * 4", 1, 1; "changing an inlined function")]
+    #[test_case("fibo(n) + triple(n)", "fibo(n) - triple(n)", 1, 1; "changing the caller")]
+    #[test_case("else\n", "else\n\n", 0, 2; "changing only the layout")]
+    fn only_changed_functions_are_recompiled(from: &str, to: &str, compiled: usize, reused: usize) {
+        let session = ParseSession::new();
+
+        let mut cache = CompileCache::in_memory();
+
+        cache
+            .compile(&session, PROGRAM, OptimisationLevel::Basic)
+            .unwrap();
+
+        let changed = PROGRAM.replacen(from, to, 1);
+
+        let stats = cache.stats();
+
+        let output = cache
+            .compile(&session, &changed, OptimisationLevel::Basic)
+            .unwrap();
+
+        assert_eq!(
+            output,
+            compile_with(&session, &changed, OptimisationLevel::Basic).unwrap()
+        );
+        assert_eq!(
+            cache.stats().functions_compiled - stats.functions_compiled,
+            compiled
+        );
+        assert_eq!(
+            cache.stats().functions_reused - stats.functions_reused,
+            reused
+        );
+    }
+
+    #[test]
+    fn only_the_latest_build_is_kept_in_memory() {
+        let session = ParseSession::new();
+
+        let changed = PROGRAM.replacen("n * 3", "n * 4", 1);
+
+        let mut cache = CompileCache::in_memory();
+        let mut fresh = CompileCache::in_memory();
+
+        cache
+            .compile(&session, PROGRAM, OptimisationLevel::Basic)
+            .unwrap();
+        cache
+            .compile(&session, &changed, OptimisationLevel::Basic)
+            .unwrap();
+        fresh
+            .compile(&session, &changed, OptimisationLevel::Basic)
+            .unwrap();
+
+        assert_eq!(
+            cache.entries.keys().collect::<HashSet<_>>(),
+            fresh.entries.keys().collect()
+        );
+    }
+
+    #[test]
+    fn entries_persist_between_caches() {
+        let session = ParseSession::new();
+
+        let dir = std::env::temp_dir().join(format!("lang-cache-test-{}", std::process::id()));
+
+        let _ = fs::remove_dir_all(&dir);
+
+        let first = CompileCache::persistent(&dir)
+            .unwrap()
+            .compile(&session, PROGRAM, OptimisationLevel::Basic)
+            .unwrap();
+
+        let mut cache = CompileCache::persistent(&dir).unwrap();
+
+        let changed = PROGRAM.replacen("n * 3", "n * 4", 1);
+
+        assert_eq!(
+            cache
+                .compile(&session, PROGRAM, OptimisationLevel::Basic)
+                .unwrap(),
+            first
+        );
+        cache
+            .compile(&session, &changed, OptimisationLevel::Basic)
+            .unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                modules_reused: 1,
+                functions_reused: 1,
+                functions_compiled: 1,
+            }
+        );
+    }
+}
@@ -1,37 +1,48 @@
+use super::analyser::{Analysis, FunctionInfo};
 use super::ast::*;
+use super::builtins::Builtin;
 use super::operators::*;
+use super::scope::Scopes;
+use super::symbol::Symbol;
 use super::tokens::*;
+use super::types::*;
 use super::wasm::*;
-use std::collections::BTreeSet;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
+
+pub fn ast_to_wasm<'a>(
+    ast: &Ast<'a>,
+    analysis: &Analysis<'a>,
+) -> Result<WasmModule<'a>, CodeGenError> {
+    ast_to_wasm_with(ast, analysis, TargetFeatures::default())
+}
 
-pub fn ast_to_wasm<'a>(ast: &Ast<'a>) -> Result<WasmModule<'a>, CodeGenError> {
+pub fn ast_to_wasm_with<'a>(
+    ast: &Ast<'a>,
+    analysis: &Analysis<'a>,
+    features: TargetFeatures,
+) -> Result<WasmModule<'a>, CodeGenError> {
     use self::Declaration::*;
     use TopLevelStatement::*;
 
-    let mut module = WasmModule::default();
+    let data = StaticData::new(ast, analysis);
+
+    let mut module = module_header(ast.arena, &data, features);
 
-    for statement in &ast.statements {
+    for statement in ast.statements {
         match statement {
             Declaration { decl, exported } => match decl {
                 FunctionDecl {
                     name,
-                    arguments,
+                    arguments: _,
                     body,
                 } => {
-                    let wasm_args = arguments.args.iter().map(|arg| arg.name).collect();
-
-                    let mut wasm_body = Vec::with_capacity(body.len());
-
-                    let mut locals = BTreeSet::new();
+                    let function =
+                        function_to_wasm(ast.arena, name.symbol, body, analysis, &data, features)?;
 
-                    for statement in body {
-                        compile_func_body_statement(statement, &mut wasm_body, &mut locals)?;
-                    }
-
-                    module.add_function(
-                        WasmFunction::new(name, wasm_args, locals, Some(WasmType::I32), wasm_body),
-                        *exported,
-                    )
+                    module.add_function(function, *exported)
                 }
                 Assignment { name: _, expr: _ } => {
                     return Err(CodeGenError::TopLevelAssignmentNotYetSupported)
@@ -40,68 +51,469 @@ pub fn ast_to_wasm<'a>(ast: &Ast<'a>) -> Result<WasmModule<'a>, CodeGenError> {
         }
     }
 
+    if features.wasi {
+        module.add_function(start_function(ast.arena, analysis)?, true);
+    }
+
     Ok(module)
 }
 
+// A module with the imports and memory the functions need, but no functions yet
+pub fn module_header<'a>(
+    arena: &'a Arena,
+    data: &StaticData,
+    features: TargetFeatures,
+) -> WasmModule<'a> {
+    let mut module = WasmModule::default();
+
+    if features.wasi {
+        module.add_import(WasmImport {
+            module: WASI_MODULE,
+            field: "fd_write",
+            name: arena.intern(FD_WRITE),
+            params: vec![WasmType::I32; 4],
+            return_type: Some(WasmType::I32),
+        });
+
+        module.add_import(WasmImport {
+            module: WASI_MODULE,
+            field: "proc_exit",
+            name: arena.intern(PROC_EXIT),
+            params: vec![WasmType::I32],
+            return_type: None,
+        });
+    }
+
+    // WASI needs memory even without any strings, for `fd_write` to write to
+    if features.wasi || !data.is_empty() {
+        module.set_memory(data.memory().clone());
+    }
+
+    module
+}
+
+// WASI runs `_start`, which exits with what `main` returns when that's an Int32
+pub fn start_function<'a>(
+    arena: &'a Arena,
+    analysis: &Analysis<'a>,
+) -> Result<WasmFunction<'a>, CodeGenError> {
+    let main_name = arena.intern("main");
+
+    let main = analysis
+        .functions
+        .get(&main_name)
+        .ok_or(CodeGenError::MissingMain)?;
+
+    if !main.params.is_empty() {
+        return Err(CodeGenError::MainTakesArguments);
+    }
+
+    let mut body = vec![WasmInstr::Call(main_name)];
+
+    match main.return_type {
+        Type::Int32 => body.push(WasmInstr::Call(arena.intern(PROC_EXIT))),
+        Type::Unit => {}
+        _ => body.push(WasmInstr::Drop),
+    }
+
+    Ok(WasmFunction::new(
+        arena.intern("_start"),
+        vec![],
+        BTreeMap::new(),
+        None,
+        body,
+    ))
+}
+
+pub fn function_to_wasm<'a>(
+    arena: &'a Arena,
+    name: Symbol<'a>,
+    body: &[CodeBlockStatement<'a>],
+    analysis: &Analysis<'a>,
+    data: &StaticData<'a>,
+    features: TargetFeatures,
+) -> Result<WasmFunction<'a>, CodeGenError> {
+    let function = analysis
+        .functions
+        .get(&name)
+        .ok_or(CodeGenError::MissingAnalysis)?;
+
+    let mut variables = Scopes::default();
+
+    for &(param, t) in &function.params {
+        variables.declare(param, (param, t));
+    }
+
+    let context = FunctionContext {
+        arena,
+        analysis,
+        data,
+        features,
+        name,
+        function,
+        loops: Cell::new(false),
+        variables: RefCell::new(variables),
+        locals: RefCell::new(BTreeMap::new()),
+    };
+
+    let wasm_args = function
+        .params
+        .iter()
+        .map(|&(name, t)| Ok((name, value_type(t)?)))
+        .collect::<Result<_, _>>()?;
+
+    let mut wasm_body = Vec::with_capacity(body.len());
+
+    compile_code_block(body, &mut wasm_body, &context, true)?;
+
+    // self calls in tail position branch back to the start instead
+    if context.loops.get() {
+        wasm_body = vec![WasmInstr::Loop {
+            label: name,
+            result_type: function.return_type.wasm_type(),
+            body: wasm_body,
+        }];
+    }
+
+    Ok(WasmFunction::new(
+        name,
+        wasm_args,
+        context.locals.into_inner(),
+        function.return_type.wasm_type(),
+        wasm_body,
+    ))
+}
+
+// Wasm proposals the runtime running the output supports, and whether it provides WASI
+#[derive(Debug, Copy, Clone, Default)]
+pub struct TargetFeatures {
+    pub tail_call: bool,
+    pub wasi: bool,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum CodeGenError {
     TopLevelAssignmentNotYetSupported,
     ClosuresNotSupportedYet,
     StringsNotSupportedYet,
+    FloatOperationsNotSupportedYet,
+    MissingAnalysis,
+    IntegerOutOfRange,
+    UnitValue,
+    BuiltinNeedsWasiTarget(Builtin),
+    MissingMain,
+    MainTakesArguments,
+}
+
+impl fmt::Display for CodeGenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use CodeGenError::*;
+
+        match self {
+            TopLevelAssignmentNotYetSupported => {
+                write!(f, "top level assignments aren't supported yet")
+            }
+            ClosuresNotSupportedYet => write!(f, "functions inside functions aren't supported yet"),
+            StringsNotSupportedYet => write!(f, "strings can't be used here yet"),
+            FloatOperationsNotSupportedYet => {
+                write!(f, "operations on floats aren't supported yet")
+            }
+            MissingAnalysis => write!(f, "the program wasn't fully analysed"),
+            IntegerOutOfRange => write!(f, "integer out of range"),
+            UnitValue => write!(f, "used a value that doesn't exist"),
+            BuiltinNeedsWasiTarget(builtin) => write!(
+                f,
+                "`{}` can only be used with the WASI target",
+                builtin.name()
+            ),
+            MissingMain => write!(f, "the WASI target needs a `main` function"),
+            MainTakesArguments => write!(f, "`main` can't take arguments with the WASI target"),
+        }
+    }
+}
+
+impl std::error::Error for CodeGenError {}
+
+const WASI_MODULE: &str = "wasi_snapshot_preview1";
+const FD_WRITE: &str = "_fd_write";
+const PROC_EXIT: &str = "_proc_exit";
+
+const STDOUT: i32 = 1;
+// where `fd_write` says how many bytes it wrote, which is never read
+const NWRITTEN_ADDRESS: i32 = 0;
+const DATA_START: u32 = 8;
+
+// The string constants in a module, which live in memory as the (address, length) iovec
+// `fd_write` takes, followed by their bytes. A string's value is the address of its iovec
+#[derive(Debug)]
+pub struct StaticData<'a> {
+    iovecs: HashMap<&'a str, i32>,
+    memory: WasmMemory,
 }
 
+impl<'a> StaticData<'a> {
+    pub fn new(ast: &Ast<'a>, analysis: &Analysis<'a>) -> Self {
+        let mut strings = Vec::new();
+        let mut calls = Vec::new();
+
+        for statement in ast.statements {
+            let TopLevelStatement::Declaration { decl, .. } = statement;
+
+            if let Declaration::FunctionDecl { body, .. } = decl {
+                for statement in *body {
+                    statement.strings(&mut strings);
+                    statement.calls(&mut calls);
+                }
+            }
+        }
+
+        if calls
+            .iter()
+            .any(|name| builtin(analysis, name.symbol) == Some(Builtin::Println))
+        {
+            strings.push("\n");
+        }
+
+        let mut iovecs = HashMap::new();
+        let mut data = Vec::new();
+
+        for string in strings {
+            if iovecs.contains_key(string) {
+                continue;
+            }
+
+            let address = DATA_START + data.len() as u32;
+
+            data.extend_from_slice(&(address + 8).to_le_bytes());
+            data.extend_from_slice(&(string.len() as u32).to_le_bytes());
+            data.extend_from_slice(string.as_bytes());
+
+            // keeps the next iovec aligned
+            data.resize((data.len() + 3) & !3, 0);
+
+            iovecs.insert(string, address as i32);
+        }
+
+        let size = DATA_START as usize + data.len();
+
+        StaticData {
+            iovecs,
+            memory: WasmMemory {
+                pages: size.div_ceil(PAGE_SIZE) as u32,
+                data_offset: DATA_START,
+                data,
+            },
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iovecs.is_empty()
+    }
+
+    pub fn memory(&self) -> &WasmMemory {
+        &self.memory
+    }
+
+    fn iovec(&self, string: &str) -> Result<i32, CodeGenError> {
+        self.iovecs
+            .get(string)
+            .copied()
+            .ok_or(CodeGenError::MissingAnalysis)
+    }
+}
+
+// Calls to functions the program doesn't declare itself are to builtins
+fn builtin<'a>(analysis: &Analysis<'a>, name: Symbol<'a>) -> Option<Builtin> {
+    match analysis.functions.contains_key(&name) {
+        true => None,
+        false => Builtin::from_name(name.as_str()),
+    }
+}
+
+fn value_type(t: Type) -> Result<WasmType, CodeGenError> {
+    t.wasm_type().ok_or(CodeGenError::UnitValue)
+}
+
+struct FunctionContext<'a, 'b> {
+    // where the names of numbered locals are interned
+    arena: &'a Arena,
+    analysis: &'b Analysis<'a>,
+    data: &'b StaticData<'a>,
+    features: TargetFeatures,
+    name: Symbol<'a>,
+    function: &'b FunctionInfo<'a>,
+    // whether any tail calls were turned into branches to the function's loop
+    loops: Cell<bool>,
+    // the wasm local each variable in scope is kept in, and its type
+    variables: RefCell<Scopes<'a, (Symbol<'a>, Type)>>,
+    locals: RefCell<BTreeMap<Symbol<'a>, WasmType>>,
+}
+
+impl<'a, 'b> FunctionContext<'a, 'b> {
+    // The local a variable is kept in, which is a new one the first time it's assigned in a
+    // scope, unless an earlier branch of the same `if` assigned a variable with the same name and
+    // type. Every variable gets its own local, with any after the first with the same name
+    // numbered, like `x.1`, which can't clash with a name in the program
+    fn assign(&self, name: Symbol<'a>, var_type: Type) -> Result<Symbol<'a>, CodeGenError> {
+        let mut variables = self.variables.borrow_mut();
+
+        if let Some(&(local, _)) = variables.get(name) {
+            return Ok(local);
+        }
+
+        let earlier = variables
+            .from_earlier_branches(name)
+            .copied()
+            .find(|&(_, t)| t == var_type);
+
+        if let Some((local, _)) = earlier {
+            variables.declare(name, (local, var_type));
+
+            return Ok(local);
+        }
+
+        let mut locals = self.locals.borrow_mut();
+        let mut local = name;
+
+        for n in 1.. {
+            let is_param = self
+                .function
+                .params
+                .iter()
+                .any(|&(param, _)| param == local);
+
+            if !is_param && !locals.contains_key(&local) {
+                break;
+            }
+
+            local = self.arena.intern(&format!("{}.{}", name, n));
+        }
+
+        locals.insert(local, value_type(var_type)?);
+        variables.declare(name, (local, var_type));
+
+        Ok(local)
+    }
+
+    fn variable(&self, name: Symbol<'a>) -> Result<(Symbol<'a>, Type), CodeGenError> {
+        self.variables
+            .borrow()
+            .get(name)
+            .copied()
+            .ok_or(CodeGenError::MissingAnalysis)
+    }
+}
+
+// Returns the type of the value the block leaves on the stack.
+// A block is in tail position when its value is returned straight from the function.
 fn compile_code_block<'a>(
-    block: &CodeBlock<'a>,
-) -> Result<(Vec<WasmInstr<'a>>, BTreeSet<&'a str>), CodeGenError> {
-    let mut instr = Vec::with_capacity(block.len()); // generally at least one instruction per statement
+    block: &[CodeBlockStatement<'a>],
+    instructions: &mut WasmBlock<'a>,
+    context: &FunctionContext<'a, '_>,
+    tail: bool,
+) -> Result<Type, CodeGenError> {
+    let mut block_type = Type::Unit;
+
+    for (i, statement) in block.iter().enumerate() {
+        let is_tail = tail && i + 1 == block.len();
 
-    let mut locals = BTreeSet::new();
+        block_type = compile_func_body_statement(statement, instructions, context, is_tail)?;
 
-    for s in block {
-        compile_func_body_statement(s, &mut instr, &mut locals)?;
+        // only the last statement's value is kept
+        if i + 1 < block.len() && !block_type.is_unit() {
+            instructions.push(WasmInstr::Drop);
+        }
     }
 
-    Ok((instr, locals))
+    Ok(block_type)
+}
+
+// Each branch of an `if` is its own scope
+fn compile_scoped_block<'a>(
+    block: &[CodeBlockStatement<'a>],
+    instructions: &mut WasmBlock<'a>,
+    context: &FunctionContext<'a, '_>,
+    tail: bool,
+) -> Result<Type, CodeGenError> {
+    context.variables.borrow_mut().enter_branch();
+
+    let block_type = compile_code_block(block, instructions, context, tail);
+
+    context.variables.borrow_mut().exit_branch();
+
+    block_type
 }
 
 fn compile_func_body_statement<'a>(
     statement: &CodeBlockStatement<'a>,
-    mut instructions: &mut Vec<WasmInstr<'a>>,
-    locals: &mut BTreeSet<&'a str>,
-) -> Result<(), CodeGenError> {
+    instructions: &mut WasmBlock<'a>,
+    context: &FunctionContext<'a, '_>,
+    tail: bool,
+) -> Result<Type, CodeGenError> {
     match statement {
-        CodeBlockStatement::BareExpression(expr) => compile_expression(expr, &mut instructions)?,
+        // calls to other functions can only be made in place with the tail call proposal, and
+        // only when they return what this function does
+        CodeBlockStatement::BareExpression(Expression::FunctionCall { name, args })
+            if tail
+                && builtin(context.analysis, name.symbol).is_none()
+                && (name.symbol == context.name
+                    || context.features.tail_call && returns_same_type(name.symbol, context)) =>
+        {
+            compile_tail_call(name.symbol, args, instructions, context)
+        }
+        CodeBlockStatement::BareExpression(expr) => compile_expression(expr, instructions, context),
         CodeBlockStatement::Declaration(Declaration::Assignment { name, expr }) => {
-            compile_expression(expr, &mut instructions)?;
-            locals.insert(name);
-            instructions.push(WasmInstr::SetLocal(name));
+            let existing = context.variables.borrow().get(name.symbol).map(|&(_, t)| t);
+
+            let expr_type = compile_expression_as(expr, instructions, context, existing)?;
+
+            let local = context.assign(name.symbol, expr_type)?;
+
+            instructions.push(WasmInstr::SetLocal(local));
+
+            Ok(Type::Unit)
         }
         CodeBlockStatement::Declaration(Declaration::FunctionDecl { .. }) => {
-            return Err(CodeGenError::ClosuresNotSupportedYet)
+            Err(CodeGenError::ClosuresNotSupportedYet)
         }
         CodeBlockStatement::IfStatement { cases, else_case } => {
-            let mut fallback = match else_case {
+            let mut compiled_cases = Vec::with_capacity(cases.len());
+
+            context.variables.borrow_mut().enter_if();
+
+            for IfStatementCase { condition, block } in *cases {
+                let mut wasm_cond = Vec::new();
+
+                compile_expression(condition, &mut wasm_cond, context)?;
+
+                let mut then = Vec::new();
+
+                let then_type = compile_scoped_block(block, &mut then, context, tail)?;
+
+                compiled_cases.push((wasm_cond, then, then_type));
+            }
+
+            let (mut fallback, result_type) = match else_case {
                 Some(block) => {
-                    let (instr, else_locals) = compile_code_block(&block)?;
+                    let mut instr = Vec::new();
 
-                    locals.extend(else_locals);
+                    let block_type = compile_scoped_block(block, &mut instr, context, tail)?;
 
-                    Some(instr)
+                    (Some(instr), block_type)
                 }
-                None => None,
+                None => (None, Type::Unit),
             };
 
-            for IfStatementCase { condition, block } in cases.iter().rev() {
-                let mut wasm_cond = Vec::new();
-
-                compile_expression(&condition, &mut wasm_cond)?;
-
-                let (then, then_locals) = compile_code_block(&block)?;
+            context.variables.borrow_mut().exit_if(else_case.is_some());
 
-                locals.extend(then_locals);
+            for (wasm_cond, mut then, then_type) in compiled_cases.into_iter().rev() {
+                // without an else branch the if can't produce a value
+                if result_type.is_unit() && !then_type.is_unit() {
+                    then.push(WasmInstr::Drop);
+                }
 
                 let instr = WasmInstr::If {
-                    result_type: WasmType::I32,
+                    result_type: result_type.wasm_type(),
                     condition: wasm_cond,
                     then,
                     else_: fallback,
@@ -113,36 +525,131 @@ fn compile_func_body_statement<'a>(
             if let Some(last_if) = fallback {
                 instructions.extend(last_if);
             }
+
+            Ok(result_type)
         }
-    };
+    }
+}
+
+fn returns_same_type<'a>(callee: Symbol<'a>, context: &FunctionContext<'a, '_>) -> bool {
+    context
+        .analysis
+        .functions
+        .get(&callee)
+        .is_some_and(|callee| callee.return_type == context.function.return_type)
+}
+
+fn compile_tail_call<'a>(
+    name: Symbol<'a>,
+    args: &[Expression<'a>],
+    instr: &mut WasmBlock<'a>,
+    context: &FunctionContext<'a, '_>,
+) -> Result<Type, CodeGenError> {
+    instr.reserve(args.len() * 2 + 1);
+
+    compile_args(name, args, instr, context)?;
+
+    if name != context.name {
+        instr.push(WasmInstr::ReturnCall(name));
+
+        return Ok(context.function.return_type);
+    }
+
+    // the arguments are all on the stack before any parameter gets overwritten
+    for &(param, _) in context.function.params.iter().rev() {
+        instr.push(WasmInstr::SetLocal(param));
+    }
+
+    instr.push(WasmInstr::Branch(name));
+
+    context.loops.set(true);
+
+    Ok(context.function.return_type)
+}
+
+// Arguments are what the function's parameters expect
+fn compile_args<'a>(
+    name: Symbol<'a>,
+    args: &[Expression<'a>],
+    instr: &mut WasmBlock<'a>,
+    context: &FunctionContext<'a, '_>,
+) -> Result<(), CodeGenError> {
+    let function = context
+        .analysis
+        .functions
+        .get(&name)
+        .ok_or(CodeGenError::MissingAnalysis)?;
+
+    for (expr, &(_, param_type)) in args.iter().zip(&function.params) {
+        compile_expression_as(expr, instr, context, Some(param_type))?;
+    }
 
     Ok(())
 }
 
 fn compile_expression<'a>(
     expr: &Expression<'a>,
-    mut instr: &mut Vec<WasmInstr<'a>>,
-) -> Result<(), CodeGenError> {
+    instr: &mut WasmBlock<'a>,
+    context: &FunctionContext<'a, '_>,
+) -> Result<Type, CodeGenError> {
+    compile_expression_as(expr, instr, context, None)
+}
+
+// Integers without a suffix are `Int64`s when that's what's expected, like in the analyser
+fn compile_expression_as<'a>(
+    expr: &Expression<'a>,
+    instr: &mut WasmBlock<'a>,
+    context: &FunctionContext<'a, '_>,
+    expected: Option<Type>,
+) -> Result<Type, CodeGenError> {
     use self::Constant::*;
     use Expression::*;
 
-    match expr {
-        &Constant(Int(int)) => {
-            instr.push(WasmInstr::ConstI32(int as i32));
+    let expr_type = match expr {
+        &Constant(Int(int)) => compile_integer(int, int.value, instr, expected)?,
+        // the smallest integers can only be written negated, like in the analyser
+        &Negation(&Constant(Int(int))) if int.value >= 0 => {
+            compile_integer(int, -int.value, instr, expected)?
         }
+        &Constant(Int64(int)) => compile_int64(int.value, instr)?,
+        &Negation(&Constant(Int64(int))) if int.value >= 0 => compile_int64(-int.value, instr)?,
         &Constant(Float(float)) => {
             instr.push(WasmInstr::ConstF32(float as f32));
+            Type::Float
+        }
+        &Constant(Bool(boolean)) => {
+            instr.push(WasmInstr::ConstI32(boolean as i32));
+            Type::Bool
+        }
+        Constant(Str(string)) => {
+            instr.push(WasmInstr::ConstI32(context.data.iovec(string)?));
+            Type::Str
         }
-        Constant(Str(_)) => return Err(CodeGenError::StringsNotSupportedYet),
         Variable(name) => {
-            instr.push(WasmInstr::GetLocal(name));
+            let (local, var_type) = context.variable(name.symbol)?;
+
+            instr.push(WasmInstr::GetLocal(local));
+
+            var_type
         }
         Negation(expr) => {
-            compile_expression(expr, &mut instr)?;
+            let expr_type = compile_expression_as(expr, instr, context, expected)?;
 
             instr.reserve(2);
-            instr.push(WasmInstr::ConstI32(-1));
-            instr.push(WasmInstr::MultiplyI32);
+
+            match expr_type {
+                Type::Int64 => {
+                    instr.push(WasmInstr::ConstI64(-1));
+                    instr.push(WasmInstr::MultiplyI64);
+                }
+                Type::Float => return Err(CodeGenError::FloatOperationsNotSupportedYet),
+                _ => {
+                    instr.push(WasmInstr::ConstI32(-1));
+                    instr.push(WasmInstr::MultiplyI32);
+                }
+            }
+
+            expr_type
         }
         BinaryOp {
             operator,
@@ -151,56 +658,313 @@ fn compile_expression<'a>(
         } => {
             instr.reserve(3);
 
-            compile_expression(left, &mut instr)?;
-            compile_expression(right, &mut instr)?;
+            let expected = match operator {
+                BinaryOperator::DoubleEquals => None,
+                _ => expected,
+            };
 
-            instr.push(binary_op_to_wasm_instruction(*operator));
-        }
-        FunctionCall { name, args } => {
-            instr.reserve(args.len() + 1);
+            // integers without a suffix on the left take on the type of the right
+            let operand_type = if left.is_untyped_integer() {
+                let mut right_instr = Vec::new();
 
-            for expr in args {
-                compile_expression(expr, &mut instr)?;
-            }
+                let right_type = compile_expression_as(right, &mut right_instr, context, expected)?;
+
+                compile_expression_as(left, instr, context, Some(right_type))?;
+
+                instr.extend(right_instr);
+
+                right_type
+            } else {
+                let left_type = compile_expression_as(left, instr, context, expected)?;
+
+                compile_expression_as(right, instr, context, Some(left_type))?;
 
-            instr.push(WasmInstr::Call(name));
+                left_type
+            };
+
+            instr.push(binary_op_to_wasm_instruction(*operator, operand_type)?);
+
+            match operator {
+                BinaryOperator::DoubleEquals => Type::Bool,
+                _ => operand_type,
+            }
         }
+        FunctionCall { name, args } => match builtin(context.analysis, name.symbol) {
+            Some(builtin) => compile_builtin_call(builtin, args, instr, context)?,
+            None => {
+                instr.reserve(args.len() + 1);
+
+                compile_args(name.symbol, args, instr, context)?;
+
+                instr.push(WasmInstr::Call(name.symbol));
+
+                context
+                    .analysis
+                    .functions
+                    .get(&name.symbol)
+                    .ok_or(CodeGenError::MissingAnalysis)?
+                    .return_type
+            }
+        },
     };
 
-    Ok(())
+    Ok(expr_type)
+}
+
+fn compile_integer(
+    int: Integer,
+    value: i128,
+    instr: &mut WasmBlock,
+    expected: Option<Type>,
+) -> Result<Type, CodeGenError> {
+    if expected == Some(Type::Int64) && !int.has_suffix {
+        return compile_int64(value, instr);
+    }
+
+    let value = i32::try_from(value).map_err(|_| CodeGenError::IntegerOutOfRange)?;
+
+    instr.push(WasmInstr::ConstI32(value));
+
+    Ok(Type::Int32)
+}
+
+fn compile_int64(value: i128, instr: &mut WasmBlock) -> Result<Type, CodeGenError> {
+    let value = i64::try_from(value).map_err(|_| CodeGenError::IntegerOutOfRange)?;
+
+    instr.push(WasmInstr::ConstI64(value));
+
+    Ok(Type::Int64)
 }
 
-fn binary_op_to_wasm_instruction<'a>(op: BinaryOperator) -> WasmInstr<'a> {
+fn compile_builtin_call<'a>(
+    builtin: Builtin,
+    args: &[Expression<'a>],
+    instr: &mut WasmBlock<'a>,
+    context: &FunctionContext<'a, '_>,
+) -> Result<Type, CodeGenError> {
+    if !context.features.wasi {
+        return Err(CodeGenError::BuiltinNeedsWasiTarget(builtin));
+    }
+
+    for expr in args {
+        instr.push(WasmInstr::ConstI32(STDOUT));
+        compile_expression(expr, instr, context)?;
+        write_iovec(instr, context);
+    }
+
+    if builtin == Builtin::Println {
+        instr.push(WasmInstr::ConstI32(STDOUT));
+        instr.push(WasmInstr::ConstI32(context.data.iovec("\n")?));
+        write_iovec(instr, context);
+    }
+
+    Ok(builtin.return_type())
+}
+
+// Expects the file descriptor and the address of a single iovec on the stack.
+// There's nowhere for the program to handle an error, so the errno is dropped
+fn write_iovec<'a>(instr: &mut WasmBlock<'a>, context: &FunctionContext<'a, '_>) {
+    instr.extend([
+        WasmInstr::ConstI32(1),
+        WasmInstr::ConstI32(NWRITTEN_ADDRESS),
+        WasmInstr::Call(context.arena.intern(FD_WRITE)),
+        WasmInstr::Drop,
+    ]);
+}
+
+fn binary_op_to_wasm_instruction(
+    op: BinaryOperator,
+    operand_type: Type,
+) -> Result<WasmInstr<'static>, CodeGenError> {
     use BinaryOperator::*;
     use WasmInstr::*;
 
-    match op {
-        Plus => AddI32,
-        Minus => MinusI32,
-        Multiply => MultiplyI32,
-        Divide => SignedDivideI32,
-        DoubleEquals => EqualI32,
-    }
+    let instr = match (operand_type, op) {
+        (Type::Float, _) => return Err(CodeGenError::FloatOperationsNotSupportedYet),
+        (Type::Int64, Plus) => AddI64,
+        (Type::Int64, Minus) => MinusI64,
+        (Type::Int64, Multiply) => MultiplyI64,
+        (Type::Int64, Divide) => SignedDivideI64,
+        (Type::Int64, DoubleEquals) => EqualI64,
+        (_, Plus) => AddI32,
+        (_, Minus) => MinusI32,
+        (_, Multiply) => MultiplyI32,
+        (_, Divide) => SignedDivideI32,
+        (_, DoubleEquals) => EqualI32,
+    };
+
+    Ok(instr)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::super::parser::parse;
+    use super::super::parser::ParseSession;
     use super::*;
+    use crate::analyser::analyse;
+    use crate::wasm::validate::validate_module;
     use insta::assert_debug_snapshot;
     use std::fs;
     use test_case::test_case;
 
     #[test_case("src/fixtures/example_program.lang"; "example program")]
+    #[test_case("src/fixtures/unit_functions.lang"; "unit functions")]
+    #[test_case("src/fixtures/int64.lang"; "int64")]
+    #[test_case("src/fixtures/integers.lang"; "integers")]
+    #[test_case("src/fixtures/tail_calls.lang"; "tail calls")]
     fn fixtures(fixture_file_name: &str) -> std::io::Result<()> {
         let contents = fs::read_to_string(fixture_file_name)?;
 
-        let ast = parse(&contents).unwrap();
+        let session = ParseSession::new();
 
-        let wasm = ast_to_wasm(&ast);
+        let ast = session.parse(&contents).unwrap();
+
+        let analysis = analyse(&ast).unwrap();
+
+        let wasm = ast_to_wasm(&ast, &analysis);
 
         assert_debug_snapshot!(wasm);
 
         Ok(())
     }
+
+    #[test]
+    fn tail_calls_to_other_functions_use_return_call() {
+        let contents = fs::read_to_string("src/fixtures/tail_calls.lang").unwrap();
+
+        let session = ParseSession::new();
+
+        let ast = session.parse(&contents).unwrap();
+
+        let analysis = analyse(&ast).unwrap();
+
+        let features = TargetFeatures {
+            tail_call: true,
+            ..Default::default()
+        };
+
+        let mut out = String::new();
+
+        ast_to_wasm_with(&ast, &analysis, features)
+            .unwrap()
+            .write_text(&mut out, WasmIndentation::default())
+            .unwrap();
+
+        insta::assert_snapshot!(out);
+    }
+
+    #[test]
+    fn tail_calls_returning_something_else_are_plain_calls() {
+        let source = "fn g(n)\n    n\n\nexport fn f(n)\n    if n == 0\n        g(n)\n";
+
+        let session = ParseSession::new();
+
+        let ast = session.parse(source).unwrap();
+
+        let analysis = analyse(&ast).unwrap();
+
+        let features = TargetFeatures {
+            tail_call: true,
+            ..Default::default()
+        };
+
+        let module = ast_to_wasm_with(&ast, &analysis, features).unwrap();
+
+        validate_module(&module).unwrap();
+
+        let mut out = String::new();
+
+        module
+            .write_text(&mut out, WasmIndentation::default())
+            .unwrap();
+
+        insta::assert_snapshot!(out);
+    }
+
+    #[test]
+    fn variables_in_different_branches_get_their_own_locals() {
+        let source = "fn f(c)\n    if c == 0\n        x = 1\n        x\n    else\n        x = 2i64\n        0\n";
+
+        let session = ParseSession::new();
+
+        let ast = session.parse(source).unwrap();
+
+        let analysis = analyse(&ast).unwrap();
+
+        let mut out = String::new();
+
+        ast_to_wasm(&ast, &analysis)
+            .unwrap()
+            .write_text(&mut out, WasmIndentation::default())
+            .unwrap();
+
+        insta::assert_snapshot!(out);
+    }
+
+    #[test]
+    fn variables_assigned_in_every_branch_share_a_local() {
+        let source = "fn f(c)\n    if c == 0\n        x = 1\n    else\n        x = 2\n    x\n";
+
+        let session = ParseSession::new();
+
+        let ast = session.parse(source).unwrap();
+
+        let analysis = analyse(&ast).unwrap();
+
+        let mut out = String::new();
+
+        ast_to_wasm(&ast, &analysis)
+            .unwrap()
+            .write_text(&mut out, WasmIndentation::default())
+            .unwrap();
+
+        insta::assert_snapshot!(out);
+    }
+
+    fn compile_wasi(source: &str) -> Result<String, CodeGenError> {
+        let session = ParseSession::new();
+
+        let ast = session.parse(source).unwrap();
+
+        let analysis = analyse(&ast).unwrap();
+
+        let features = TargetFeatures {
+            wasi: true,
+            ..Default::default()
+        };
+
+        let mut out = String::new();
+
+        ast_to_wasm_with(&ast, &analysis, features)?
+            .write_text(&mut out, WasmIndentation::default())
+            .unwrap();
+
+        Ok(out)
+    }
+
+    #[test]
+    fn wasi_target() {
+        let contents = fs::read_to_string("src/fixtures/hello.lang").unwrap();
+
+        insta::assert_snapshot!(compile_wasi(&contents).unwrap());
+    }
+
+    #[test_case("export fn main()\n    print(\"a\")\n    print(\"a\")\n"; "repeated strings are stored once")]
+    #[test_case("export fn main()\n    true\n"; "main result is dropped unless it is an exit code")]
+    #[test_case("fn print(x)\n    x\n\nexport fn main()\n    print(1)\n"; "declared functions replace builtins")]
+    fn wasi_programs(source: &str) {
+        insta::assert_snapshot!(compile_wasi(source).unwrap());
+    }
+
+    #[test_case("export fn main()\n    print(\"a\")\n", TargetFeatures::default(); "printing without wasi")]
+    #[test_case("export fn f()\n    1\n", TargetFeatures { wasi: true, ..Default::default() }; "no main")]
+    #[test_case("export fn main(x)\n    x\n", TargetFeatures { wasi: true, ..Default::default() }; "main with arguments")]
+    fn errors(source: &str, features: TargetFeatures) {
+        let session = ParseSession::new();
+
+        let ast = session.parse(source).unwrap();
+
+        let analysis = analyse(&ast).unwrap();
+
+        assert_debug_snapshot!(ast_to_wasm_with(&ast, &analysis, features).unwrap_err());
+    }
 }
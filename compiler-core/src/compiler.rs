@@ -0,0 +1,442 @@
+use crate::analyser::{analyse, Analysis};
+use crate::ast::Ast;
+use crate::code_gen::{ast_to_wasm_with, TargetFeatures};
+use crate::constant_folding::fold_constants;
+use crate::dead_code::{remove_unused_functions, unused_functions};
+use crate::diagnostics::{self, Diagnostic, Severity};
+use crate::glue::{js_glue, JsGlue};
+use crate::inlining::inline_functions;
+use crate::parser::ParseSession;
+use crate::wasm::binary::{encode_module, encode_module_with_names};
+use crate::wasm::peephole::optimise_module;
+use crate::wasm::validate::validate_module;
+use crate::wasm::{Wasm, WasmIndentation, WasmModule};
+use crate::{CompileError, OptimisationLevel};
+
+// Where the module will run, which decides what gets generated
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Target {
+    // only the text format
+    #[default]
+    Wat,
+    // the binary format as well
+    Wasm,
+    // a binary that starts from `main`, and can print
+    Wasi,
+    // a binary with an ES module and TypeScript declarations for loading it
+    Js,
+}
+
+impl std::str::FromStr for Target {
+    type Err = String;
+
+    fn from_str(target: &str) -> Result<Self, Self::Err> {
+        match target {
+            "wat" => Ok(Target::Wat),
+            "wasm" => Ok(Target::Wasm),
+            "wasi" => Ok(Target::Wasi),
+            "js" => Ok(Target::Js),
+            _ => Err(format!("Unknown target: {}", target)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileOptions {
+    pub target: Target,
+    pub optimisation: OptimisationLevel,
+    // these two only apply when optimising
+    pub inline_functions: bool,
+    pub remove_unused_functions: bool,
+    // use `return_call` for tail calls, which needs a runtime supporting it
+    pub tail_call: bool,
+    // keep function and local names in the binary
+    pub debug_info: bool,
+    pub warnings_as_errors: bool,
+    // where the JS glue loads the binary from, relative to itself
+    pub wasm_file: String,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        CompileOptions {
+            target: Target::default(),
+            optimisation: OptimisationLevel::default(),
+            inline_functions: true,
+            remove_unused_functions: true,
+            tail_call: false,
+            debug_info: false,
+            warnings_as_errors: false,
+            wasm_file: "out.wasm".to_string(),
+        }
+    }
+}
+
+// Everything generated for the target
+#[derive(Debug)]
+pub struct Artifacts<'a> {
+    pub module: WasmModule<'a>,
+    pub wat: String,
+    // for every target but `Wat`
+    pub wasm: Option<Vec<u8>>,
+    // only for the `Js` target
+    pub glue: Option<JsGlue>,
+}
+
+#[derive(Debug)]
+pub struct CompileOutput<'a> {
+    // `None` when there were any errors
+    pub artifacts: Option<Artifacts<'a>>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> CompileOutput<'a> {
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Compiler {
+    options: CompileOptions,
+}
+
+impl Compiler {
+    pub fn new(options: CompileOptions) -> Self {
+        Compiler { options }
+    }
+
+    pub fn options(&self) -> &CompileOptions {
+        &self.options
+    }
+
+    // The module borrows its names from the session, so is only around for as long as it is
+    pub fn compile<'a>(&self, session: &'a ParseSession, source: &'a str) -> CompileOutput<'a> {
+        let mut diagnostics = Vec::new();
+
+        let artifacts = match self.artifacts(session, source, &mut diagnostics) {
+            Ok(artifacts) => Some(artifacts),
+            Err(error) => {
+                diagnostics.extend(diagnostics::diagnostics(source, &error));
+                None
+            }
+        };
+
+        if self.options.warnings_as_errors {
+            for diagnostic in &mut diagnostics {
+                diagnostic.severity = Severity::Error;
+            }
+        }
+
+        let output = CompileOutput {
+            artifacts,
+            diagnostics,
+        };
+
+        match output.has_errors() {
+            true => CompileOutput {
+                artifacts: None,
+                ..output
+            },
+            false => output,
+        }
+    }
+
+    // Only what can be found without generating any code, for when the module comes from
+    // somewhere else, like the cache
+    pub fn warnings(&self, source: &str) -> Vec<Diagnostic> {
+        let session = ParseSession::new();
+
+        match session.parse(source) {
+            Ok(ast) if analyse(&ast).is_ok() => self.warnings_for(source, &ast),
+            _ => vec![],
+        }
+    }
+
+    // Everything after parsing, for an AST that didn't come from source, like a generated one
+    pub fn compile_ast<'a>(&self, ast: Ast<'a>) -> Result<WasmModule<'a>, CompileError<'a>> {
+        let analysis = analyse(&ast)?;
+
+        let (ast, analysis) = self.optimise(ast, analysis)?;
+
+        self.back_end(&ast, &analysis)
+    }
+
+    fn warnings_for(&self, source: &str, ast: &Ast) -> Vec<Diagnostic> {
+        unused_functions(ast)
+            .into_iter()
+            .map(|name| diagnostics::unused_function(source, name))
+            .collect()
+    }
+
+    fn artifacts<'a>(
+        &self,
+        session: &'a ParseSession,
+        source: &'a str,
+        warnings: &mut Vec<Diagnostic>,
+    ) -> Result<Artifacts<'a>, CompileError<'a>> {
+        let ast = session.parse(source)?;
+
+        let analysis = analyse(&ast)?;
+
+        warnings.extend(self.warnings_for(source, &ast));
+
+        let (ast, analysis) = self.optimise(ast, analysis)?;
+
+        let module = self.back_end(&ast, &analysis)?;
+
+        let mut wat = String::new();
+
+        module.write_text(&mut wat, WasmIndentation::default())?;
+
+        let wasm = match (self.options.target, self.options.debug_info) {
+            (Target::Wat, _) => None,
+            (_, false) => Some(encode_module(&module)?),
+            (_, true) => Some(encode_module_with_names(&module)?),
+        };
+
+        let glue = match self.options.target {
+            Target::Js => Some(js_glue(&ast, &analysis, &self.options.wasm_file)?),
+            _ => None,
+        };
+
+        Ok(Artifacts {
+            module,
+            wat,
+            wasm,
+            glue,
+        })
+    }
+
+    // Parses and checks the program, then runs the AST passes for the options
+    pub(crate) fn front_end<'a>(
+        &self,
+        session: &'a ParseSession,
+        source: &'a str,
+    ) -> Result<(Ast<'a>, Analysis<'a>), CompileError<'a>> {
+        let ast = session.parse(source)?;
+
+        let analysis = analyse(&ast)?;
+
+        self.optimise(ast, analysis)
+    }
+
+    fn optimise<'a>(
+        &self,
+        mut ast: Ast<'a>,
+        analysis: Analysis<'a>,
+    ) -> Result<(Ast<'a>, Analysis<'a>), CompileError<'a>> {
+        if self.options.optimisation < OptimisationLevel::Basic {
+            // dividing by a constant zero is an error at every level, so the folding that finds
+            // it still runs, even though what it folds isn't used
+            fold_constants(ast)?;
+
+            return Ok((ast, analysis));
+        }
+
+        if self.options.inline_functions {
+            ast = inline_functions(ast);
+        }
+
+        if self.options.remove_unused_functions {
+            ast = remove_unused_functions(ast);
+        }
+
+        ast = fold_constants(ast)?;
+
+        let analysis = analyse(&ast)?;
+
+        Ok((ast, analysis))
+    }
+
+    pub(crate) fn back_end<'a>(
+        &self,
+        ast: &Ast<'a>,
+        analysis: &Analysis<'a>,
+    ) -> Result<WasmModule<'a>, CompileError<'a>> {
+        let features = TargetFeatures {
+            tail_call: self.options.tail_call,
+            wasi: self.options.target == Target::Wasi,
+        };
+
+        let mut module = ast_to_wasm_with(ast, analysis, features)?;
+
+        if self.options.optimisation >= OptimisationLevel::Basic {
+            optimise_module(&mut module);
+        }
+
+        validate_module(&module)?;
+
+        Ok(module)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasm::interpreter::{Interpreter, Trap};
+    use insta::assert_debug_snapshot;
+    use std::fs;
+    use test_case::test_case;
+
+    fn compiler(target: Target) -> Compiler {
+        Compiler::new(CompileOptions {
+            target,
+            ..Default::default()
+        })
+    }
+
+    #[test_case(Target::Wat, false, false; "wat")]
+    #[test_case(Target::Wasm, true, false; "wasm")]
+    #[test_case(Target::Wasi, true, false; "wasi")]
+    #[test_case(Target::Js, true, true; "js")]
+    fn artifacts_for_each_target(target: Target, has_wasm: bool, has_glue: bool) {
+        let session = ParseSession::new();
+
+        let output = compiler(target).compile(&session, "export fn main()\n    1\n");
+
+        let artifacts = output.artifacts.unwrap();
+
+        assert!(artifacts.wat.starts_with("(module"));
+        assert_eq!(artifacts.wasm.is_some(), has_wasm);
+        assert_eq!(artifacts.glue.is_some(), has_glue);
+    }
+
+    #[test]
+    fn wasi_target_runs_main_from_start() {
+        let session = ParseSession::new();
+
+        let source = fs::read_to_string("src/fixtures/hello.lang").unwrap();
+
+        let output = compiler(Target::Wasi).compile(&session, &source);
+
+        let interpreter = Interpreter::new(&output.artifacts.as_ref().unwrap().module);
+
+        assert_eq!(interpreter.invoke("_start", &[]), Err(Trap::Exit(0)));
+    }
+
+    #[test]
+    fn same_module_as_compile_with() {
+        let session = ParseSession::new();
+
+        let source = fs::read_to_string("src/fixtures/fibonacci.lang").unwrap();
+
+        for &optimisation in &[OptimisationLevel::None, OptimisationLevel::Basic] {
+            let output = Compiler::new(CompileOptions {
+                optimisation,
+                ..Default::default()
+            })
+            .compile(&session, &source);
+
+            assert_eq!(
+                output.artifacts.unwrap().wat,
+                crate::compile_with(&session, &source, optimisation).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn debug_info_names_the_binary() {
+        let session = ParseSession::new();
+
+        let with_names = Compiler::new(CompileOptions {
+            target: Target::Wasm,
+            debug_info: true,
+            ..Default::default()
+        });
+
+        let source = "export fn main()\n    1\n";
+
+        let plain = compiler(Target::Wasm)
+            .compile(&session, source)
+            .artifacts
+            .unwrap();
+        let named = with_names.compile(&session, source).artifacts.unwrap();
+
+        assert!(named.wasm.unwrap().len() > plain.wasm.unwrap().len());
+        assert_eq!(named.wat, plain.wat);
+    }
+
+    #[test]
+    fn same_module_from_the_ast() {
+        let source = fs::read_to_string("src/fixtures/tail_calls.lang").unwrap();
+
+        let compiler = Compiler::new(CompileOptions {
+            tail_call: true,
+            ..Default::default()
+        });
+
+        let session = ParseSession::new();
+
+        let module = compiler
+            .compile_ast(session.parse(&source).unwrap())
+            .unwrap();
+
+        let mut wat = String::new();
+
+        module
+            .write_text(&mut wat, WasmIndentation::default())
+            .unwrap();
+
+        assert_eq!(
+            wat,
+            compiler.compile(&session, &source).artifacts.unwrap().wat
+        );
+    }
+
+    #[test]
+    fn warnings_still_give_artifacts() {
+        let session = ParseSession::new();
+
+        let source =
+            "fn unused(x)\n    x\n\nfn helper()\n    1\n\nexport fn main()\n    helper()\n";
+
+        let output = compiler(Target::Wat).compile(&session, source);
+
+        assert!(!output.has_errors());
+        assert!(output.artifacts.is_some());
+        assert_debug_snapshot!(output.diagnostics);
+    }
+
+    #[test]
+    fn warnings_as_errors() {
+        let session = ParseSession::new();
+
+        let compiler = Compiler::new(CompileOptions {
+            warnings_as_errors: true,
+            ..Default::default()
+        });
+
+        let output = compiler.compile(&session, "fn unused()\n    1\n\nexport fn main()\n    2\n");
+
+        assert!(output.has_errors());
+        assert!(output.artifacts.is_none());
+        assert_eq!(output.diagnostics.len(), 1);
+    }
+
+    #[test]
+    fn errors_give_no_artifacts() {
+        let session = ParseSession::new();
+
+        let output = compiler(Target::Wasm).compile(&session, "export fn main()\n    x\n");
+
+        assert!(output.has_errors());
+        assert!(output.artifacts.is_none());
+        assert_eq!(output.diagnostics[0].message, "`x` isn't defined");
+    }
+
+    #[test]
+    fn warnings_without_compiling() {
+        let session = ParseSession::new();
+
+        let source = "fn unused()\n    1\n";
+
+        assert_eq!(
+            compiler(Target::Wat).warnings(source),
+            compiler(Target::Wat).compile(&session, source).diagnostics
+        );
+        assert_eq!(compiler(Target::Wat).warnings("fn f(\n"), []);
+    }
+}
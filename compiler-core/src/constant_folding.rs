@@ -0,0 +1,416 @@
+use crate::ast::*;
+use crate::operators::*;
+use crate::symbol::{Name, Symbol};
+use crate::tokens::*;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+pub type Result<'a, X> = std::result::Result<X, ConstantFoldingError<'a>>;
+
+// Expects an AST that has already passed analysis, as simplifications like `x * 1` -> `x`
+// are only valid when both sides have the same type
+pub fn fold_constants<'a>(ast: Ast<'a>) -> Result<'a, Ast<'a>> {
+    let mut folded = Vec::with_capacity(ast.statements.len());
+
+    for &statement in ast.statements {
+        let statement = match statement {
+            TopLevelStatement::Declaration {
+                decl:
+                    Declaration::FunctionDecl {
+                        name,
+                        arguments,
+                        body,
+                    },
+                exported,
+            } => {
+                let body = FunctionFolder::new(name, body, ast.arena).function_body(body)?;
+
+                TopLevelStatement::Declaration {
+                    decl: Declaration::FunctionDecl {
+                        name,
+                        arguments,
+                        body,
+                    },
+                    exported,
+                }
+            }
+            statement => statement,
+        };
+
+        folded.push(statement);
+    }
+
+    Ok(Ast::new(ast.arena.alloc_slice(folded), ast.arena))
+}
+
+struct FunctionFolder<'a> {
+    function: Name<'a>,
+    arena: &'a Arena,
+    assignment_counts: HashMap<Symbol<'a>, usize>,
+    constants: HashMap<Symbol<'a>, Constant<'a>>,
+}
+
+impl<'a> FunctionFolder<'a> {
+    fn new(function: Name<'a>, body: &[CodeBlockStatement<'a>], arena: &'a Arena) -> Self {
+        let mut folder = FunctionFolder {
+            function,
+            arena,
+            assignment_counts: HashMap::new(),
+            constants: HashMap::new(),
+        };
+
+        folder.count_assignments(body);
+
+        folder
+    }
+
+    fn count_assignments(&mut self, block: &[CodeBlockStatement<'a>]) {
+        for statement in block {
+            match statement {
+                CodeBlockStatement::Declaration(Declaration::Assignment { name, .. }) => {
+                    *self.assignment_counts.entry(name.symbol).or_default() += 1;
+                }
+                CodeBlockStatement::IfStatement { cases, else_case } => {
+                    for case in *cases {
+                        self.count_assignments(case.block);
+                    }
+
+                    if let Some(block) = else_case {
+                        self.count_assignments(block);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Variables that are only ever assigned a constant once, at the top level of the function,
+    // get replaced by that constant everywhere after the assignment
+    fn function_body(&mut self, body: CodeBlock<'a>) -> Result<'a, CodeBlock<'a>> {
+        let mut folded = self.arena.vec();
+        let last = body.len().saturating_sub(1);
+
+        for (i, &statement) in body.iter().enumerate() {
+            if let CodeBlockStatement::Declaration(Declaration::Assignment { name, expr }) =
+                statement
+            {
+                let expr = self.expression(expr)?;
+
+                if let (Expression::Constant(c), Some(1)) =
+                    (&expr, self.assignment_counts.get(&name.symbol).copied())
+                {
+                    self.constants.insert(name.symbol, *c);
+
+                    // the last statement decides the function's return type, so has to stay
+                    if i != last {
+                        continue;
+                    }
+                }
+
+                folded.push(CodeBlockStatement::Declaration(Declaration::Assignment {
+                    name,
+                    expr,
+                }));
+            } else {
+                folded.push(self.statement(statement)?);
+            }
+        }
+
+        Ok(folded.into_bump_slice())
+    }
+
+    fn block(&mut self, block: CodeBlock<'a>) -> Result<'a, CodeBlock<'a>> {
+        let mut folded = self.arena.vec();
+
+        for &statement in block {
+            folded.push(self.statement(statement)?);
+        }
+
+        Ok(folded.into_bump_slice())
+    }
+
+    fn statement(
+        &mut self,
+        statement: CodeBlockStatement<'a>,
+    ) -> Result<'a, CodeBlockStatement<'a>> {
+        let statement = match statement {
+            CodeBlockStatement::BareExpression(expr) => {
+                CodeBlockStatement::BareExpression(self.expression(expr)?)
+            }
+            CodeBlockStatement::Declaration(Declaration::Assignment { name, expr }) => {
+                CodeBlockStatement::Declaration(Declaration::Assignment {
+                    name,
+                    expr: self.expression(expr)?,
+                })
+            }
+            CodeBlockStatement::IfStatement { cases, else_case } => {
+                let mut folded_cases = self.arena.vec();
+
+                for &IfStatementCase { condition, block } in cases {
+                    folded_cases.push(IfStatementCase {
+                        condition: self.expression(condition)?,
+                        block: self.block(block)?,
+                    });
+                }
+
+                let else_case = match else_case {
+                    Some(block) => Some(self.block(block)?),
+                    None => None,
+                };
+
+                CodeBlockStatement::IfStatement {
+                    cases: folded_cases.into_bump_slice(),
+                    else_case,
+                }
+            }
+            statement => statement,
+        };
+
+        Ok(statement)
+    }
+
+    fn expression(&mut self, expr: Expression<'a>) -> Result<'a, Expression<'a>> {
+        use Expression::*;
+
+        let folded = match expr {
+            Variable(name) => match self.constants.get(&name.symbol) {
+                Some(&c) => Constant(c),
+                None => Variable(name),
+            },
+            FunctionCall { name, args } => {
+                let mut folded_args = self.arena.vec();
+
+                for &arg in args {
+                    folded_args.push(self.expression(arg)?);
+                }
+
+                FunctionCall {
+                    name,
+                    args: folded_args.into_bump_slice(),
+                }
+            }
+            Negation(expr) => match self.expression(*expr)? {
+                Constant(c) => match negate(c) {
+                    Some(negated) => Constant(negated),
+                    None => Negation(self.arena.alloc(Constant(c))),
+                },
+                Negation(inner) => *inner,
+                expr => Negation(self.arena.alloc(expr)),
+            },
+            BinaryOp {
+                left,
+                operator,
+                right,
+            } => {
+                let left = self.expression(*left)?;
+                let right = self.expression(*right)?;
+
+                self.binary_op(left, operator, right)?
+            }
+            expr => expr,
+        };
+
+        Ok(folded)
+    }
+
+    fn binary_op(
+        &self,
+        left: Expression<'a>,
+        operator: BinaryOperator,
+        right: Expression<'a>,
+    ) -> Result<'a, Expression<'a>> {
+        use BinaryOperator::*;
+        use Expression::Constant as C;
+
+        if let (Divide, C(c)) = (operator, &right) {
+            if is_int(*c, 0) {
+                return Err(ConstantFoldingError::DivisionByZero {
+                    function: self.function,
+                });
+            }
+        }
+
+        if let (C(l), C(r)) = (&left, &right) {
+            if let Some(c) = fold_binary_op(*l, operator, *r) {
+                return Ok(C(c));
+            }
+        }
+
+        let simplified = match (left, operator, right) {
+            (C(c), Plus, expr) | (expr, Plus, C(c)) | (expr, Minus, C(c)) if is_int(c, 0) => expr,
+            (C(c), Multiply, expr) | (expr, Multiply, C(c)) | (expr, Divide, C(c))
+                if is_int(c, 1) =>
+            {
+                expr
+            }
+            (left, operator, right) => Expression::BinaryOp {
+                left: self.arena.alloc(left),
+                operator,
+                right: self.arena.alloc(right),
+            },
+        };
+
+        Ok(simplified)
+    }
+}
+
+fn is_int(c: Constant, value: i64) -> bool {
+    match c {
+        Constant::Int(int) | Constant::Int64(int) => int.value == value.into(),
+        _ => false,
+    }
+}
+
+// Integer maths follows wasm's semantics, so an `Int32` wraps at 32 bits. Integers without a
+// suffix might be either size, so they're only folded when the result is the same for both
+fn fold_binary_op<'a>(
+    left: Constant<'a>,
+    operator: BinaryOperator,
+    right: Constant<'a>,
+) -> Option<Constant<'a>> {
+    use BinaryOperator::*;
+    use Constant::*;
+
+    let folded = match (left, right) {
+        (Int(l), Int(r)) if l.has_suffix || r.has_suffix => {
+            let (lv, rv) = (l.value as i32, r.value as i32);
+
+            let int = |value: i32| {
+                Int(Integer {
+                    value: value.into(),
+                    has_suffix: true,
+                    ..l
+                })
+            };
+
+            match operator {
+                Plus => int(lv.wrapping_add(rv)),
+                Minus => int(lv.wrapping_sub(rv)),
+                Multiply => int(lv.wrapping_mul(rv)),
+                // dividing the smallest int by -1 overflows, which traps at runtime
+                Divide => int(lv.checked_div(rv)?),
+                DoubleEquals => Bool(lv == rv),
+            }
+        }
+        (Int(l), Int(r)) => {
+            let value = match operator {
+                Plus => l.value.checked_add(r.value),
+                Minus => l.value.checked_sub(r.value),
+                Multiply => l.value.checked_mul(r.value),
+                Divide => l.value.checked_div(r.value),
+                DoubleEquals => return Some(Bool(l.value == r.value)),
+            }?;
+
+            untyped_integer(l, value)?
+        }
+        // integers without a suffix used with an `Int64` are `Int64`s too
+        (Int64(l), Int(r)) if !r.has_suffix => fold_binary_op(Int64(l), operator, Int64(r))?,
+        (Int(l), Int64(r)) if !l.has_suffix => fold_binary_op(Int64(l), operator, Int64(r))?,
+        (Int64(l), Int64(r)) => {
+            let (lv, rv) = (i64::try_from(l.value).ok()?, i64::try_from(r.value).ok()?);
+
+            let int64 = |value: i64| {
+                Int64(Integer {
+                    value: value.into(),
+                    has_suffix: true,
+                    ..l
+                })
+            };
+
+            match operator {
+                Plus => int64(lv.wrapping_add(rv)),
+                Minus => int64(lv.wrapping_sub(rv)),
+                Multiply => int64(lv.wrapping_mul(rv)),
+                Divide => int64(lv.checked_div(rv)?),
+                DoubleEquals => Bool(lv == rv),
+            }
+        }
+        (Bool(l), Bool(r)) => match operator {
+            DoubleEquals => Bool(l == r),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    Some(folded)
+}
+
+// Where it would have wrapped as an `Int32` it's left for the runtime, which knows its size
+fn untyped_integer<'a>(int: Integer, value: i128) -> Option<Constant<'a>> {
+    i32::try_from(value)
+        .ok()
+        .map(|_| Constant::Int(Integer { value, ..int }))
+}
+
+fn negate(c: Constant) -> Option<Constant> {
+    match c {
+        Constant::Int(int) if int.has_suffix => Some(Constant::Int(Integer {
+            value: (int.value as i32).wrapping_neg().into(),
+            ..int
+        })),
+        Constant::Int(int) => untyped_integer(int, int.value.checked_neg()?),
+        // the smallest `Int64` is written negated, but what's negated doesn't fit in one, so is
+        // left for code gen
+        Constant::Int64(int) => Some(Constant::Int64(Integer {
+            value: i64::try_from(int.value).ok()?.wrapping_neg().into(),
+            ..int
+        })),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum ConstantFoldingError<'a> {
+    DivisionByZero { function: Name<'a> },
+}
+
+impl<'a> fmt::Display for ConstantFoldingError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConstantFoldingError::DivisionByZero { function } => {
+                write!(f, "`{}` always divides by zero", function)
+            }
+        }
+    }
+}
+
+impl<'a> std::error::Error for ConstantFoldingError<'a> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyser::analyse;
+    use crate::parser::ParseSession;
+    use insta::assert_debug_snapshot;
+    use std::fs;
+    use test_case::test_case;
+
+    fn fold<'a>(session: &'a ParseSession, source: &'a str) -> Result<'a, Ast<'a>> {
+        let ast = session.parse(source).unwrap();
+
+        analyse(&ast).unwrap();
+
+        fold_constants(ast)
+    }
+
+    #[test_case("src/fixtures/example_program.lang"; "example program")]
+    #[test_case("src/fixtures/constants.lang"; "constants")]
+    fn fixtures(fixture_file_name: &str) -> std::io::Result<()> {
+        let contents = fs::read_to_string(fixture_file_name)?;
+
+        assert_debug_snapshot!(fold(&ParseSession::new(), &contents));
+
+        Ok(())
+    }
+
+    #[test_case("fn f()\n    2147483647i32 + 1\n"; "int32 wraps")]
+    #[test_case("fn f(x: Int64)\n    x + (2147483647 + 1)\n"; "integers that could be either size")]
+    #[test_case("fn f(x: Int64)\n    x + 2 * 3\n"; "integers used with an int64")]
+    #[test_case("fn f(x)\n    (x * 1) + 0 - 0\n"; "identities")]
+    #[test_case("fn f(x)\n    y = -(-x)\n    y\n"; "double negation")]
+    #[test_case("fn f(x)\n    x / (2 - 2)\n"; "division by zero")]
+    fn expressions(source: &str) {
+        assert_debug_snapshot!(fold(&ParseSession::new(), source));
+    }
+}
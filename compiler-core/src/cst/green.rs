@@ -0,0 +1,150 @@
+use super::SyntaxKind;
+use std::fmt;
+use std::rc::Rc;
+
+// Green trees are immutable and only know their width, not their position,
+// so identical subtrees can be shared between trees
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GreenToken {
+    kind: SyntaxKind,
+    text: Box<str>,
+}
+
+impl GreenToken {
+    pub fn new(kind: SyntaxKind, text: &str) -> Self {
+        GreenToken {
+            kind,
+            text: text.into(),
+        }
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.kind
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn width(&self) -> usize {
+        self.text.len()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GreenNode {
+    kind: SyntaxKind,
+    width: usize,
+    children: Vec<GreenElement>,
+}
+
+impl GreenNode {
+    pub fn new(kind: SyntaxKind, children: Vec<GreenElement>) -> Self {
+        let width = children.iter().map(GreenElement::width).sum();
+
+        GreenNode {
+            kind,
+            width,
+            children,
+        }
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.kind
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn children(&self) -> &[GreenElement] {
+        &self.children
+    }
+}
+
+impl fmt::Display for GreenNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for child in &self.children {
+            match child {
+                GreenElement::Node(node) => write!(f, "{}", node)?,
+                GreenElement::Token(token) => write!(f, "{}", token.text)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum GreenElement {
+    Node(Rc<GreenNode>),
+    Token(Rc<GreenToken>),
+}
+
+impl GreenElement {
+    pub fn kind(&self) -> SyntaxKind {
+        match self {
+            GreenElement::Node(node) => node.kind,
+            GreenElement::Token(token) => token.kind,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        match self {
+            GreenElement::Node(node) => node.width,
+            GreenElement::Token(token) => token.width(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub(super) struct Checkpoint(usize);
+
+// Builds a tree bottom up, as the parser finishes each node
+#[derive(Debug, Default)]
+pub(super) struct GreenBuilder {
+    parents: Vec<(SyntaxKind, usize)>,
+    children: Vec<GreenElement>,
+}
+
+impl GreenBuilder {
+    pub(super) fn start_node(&mut self, kind: SyntaxKind) {
+        self.parents.push((kind, self.children.len()));
+    }
+
+    // starts a node that wraps everything added since the checkpoint, for when the parser
+    // only finds out what a node is after seeing the start of it
+    pub(super) fn start_node_at(&mut self, Checkpoint(first): Checkpoint, kind: SyntaxKind) {
+        self.parents.push((kind, first));
+    }
+
+    pub(super) fn checkpoint(&self) -> Checkpoint {
+        Checkpoint(self.children.len())
+    }
+
+    pub(super) fn finish_node(&mut self) {
+        let (kind, first) = self.parents.pop().expect("no node to finish");
+
+        let children = self.children.split_off(first);
+
+        self.children
+            .push(GreenElement::Node(Rc::new(GreenNode::new(kind, children))));
+    }
+
+    pub(super) fn token(&mut self, kind: SyntaxKind, text: &str) {
+        self.children
+            .push(GreenElement::Token(Rc::new(GreenToken::new(kind, text))));
+    }
+
+    pub(super) fn finish(mut self) -> Rc<GreenNode> {
+        match self.children.pop() {
+            Some(GreenElement::Node(node))
+                if self.parents.is_empty() && self.children.is_empty() =>
+            {
+                node
+            }
+            _ => panic!("the builder should be left with a single root node"),
+        }
+    }
+}
@@ -0,0 +1,68 @@
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SyntaxKind {
+    // trivia, which doesn't change the meaning of the code
+    Whitespace,
+    Newline,
+    Comment,
+
+    // zero width tokens marking where indentation changes, as the tokeniser works them out
+    Indent,
+    Dedent,
+
+    Name,
+    Int,
+    Float,
+    Str,
+    ImportKw,
+    ExportKw,
+    TypeKw,
+    FnKw,
+    IfKw,
+    ElseKw,
+    TrueKw,
+    FalseKw,
+    Equals,
+    DoubleEquals,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    OpenParen,
+    CloseParen,
+    Comma,
+    Colon,
+    Pipe,
+    FatRightArrow,
+    // a char or unterminated string the tokeniser would reject
+    ErrorToken,
+
+    Root,
+    FunctionDecl,
+    ParamList,
+    Param,
+    TypeAnnotation,
+    Block,
+    Assignment,
+    ExprStatement,
+    IfStatement,
+    IfCase,
+    ElseCase,
+    Literal,
+    NameRef,
+    CallExpr,
+    ArgList,
+    BinaryExpr,
+    PrefixExpr,
+    ParenExpr,
+    // wraps anything the parser couldn't make sense of
+    Error,
+}
+
+impl SyntaxKind {
+    pub fn is_trivia(self) -> bool {
+        matches!(
+            self,
+            SyntaxKind::Whitespace | SyntaxKind::Newline | SyntaxKind::Comment
+        )
+    }
+}
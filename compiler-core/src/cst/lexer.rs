@@ -0,0 +1,159 @@
+use super::SyntaxKind::{self, *};
+use crate::keywords::*;
+use std::cmp::Ordering;
+
+// Splits the source into tokens that cover every byte of it, adding zero width `Indent` and
+// `Dedent` tokens wherever the tokeniser would output `IndentIncr` and `IndentDecr`
+pub(super) fn tokenise_lossless(source: &str) -> Vec<(SyntaxKind, &str)> {
+    let mut tokens = Vec::new();
+    let mut indent_stack: Vec<usize> = Vec::new();
+    let mut current_line_indent = 0;
+
+    // only set once a newline has been seen since the last significant token
+    let mut line_indent: Option<usize> = None;
+    let mut in_leading_spaces = false;
+
+    for (kind, text) in lex(source) {
+        match kind {
+            Newline => {
+                line_indent = Some(0);
+                in_leading_spaces = true;
+            }
+            Whitespace => {
+                if let (Some(indent), true) = (&mut line_indent, in_leading_spaces) {
+                    let spaces = text.chars().take_while(|&c| c == ' ').count();
+
+                    *indent += spaces;
+                    in_leading_spaces = spaces == text.len();
+                }
+            }
+            // comments always run to the end of the line, so a line with one is like a blank line
+            Comment => in_leading_spaces = false,
+            _ => {
+                if let Some(indent) = line_indent.take() {
+                    current_line_indent = indent;
+
+                    let level = indent_stack.last().copied().unwrap_or_default();
+
+                    match current_line_indent.cmp(&level) {
+                        Ordering::Greater => {
+                            indent_stack.push(current_line_indent);
+                            tokens.push((Indent, ""));
+                        }
+                        Ordering::Less => {
+                            indent_stack.pop();
+                            tokens.push((Dedent, ""));
+                        }
+                        Ordering::Equal => {}
+                    }
+                }
+
+                while indent_stack.last().copied().unwrap_or_default() > current_line_indent {
+                    indent_stack.pop();
+                    tokens.push((Dedent, ""));
+                }
+
+                in_leading_spaces = false;
+            }
+        }
+
+        tokens.push((kind, text));
+    }
+
+    tokens.extend(indent_stack.iter().map(|_| (Dedent, "")));
+
+    tokens
+}
+
+fn lex(source: &str) -> Vec<(SyntaxKind, &str)> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+
+    while let Some(c) = rest.chars().next() {
+        let (kind, len) = match c {
+            ' ' | '\t' => (
+                Whitespace,
+                rest.find(|c| c != ' ' && c != '\t').unwrap_or(rest.len()),
+            ),
+            '\r' if rest.starts_with("\r\n") => (Newline, 2),
+            '\n' | '\r' => (Newline, 1),
+            '/' if rest.starts_with("//") => {
+                (Comment, rest.find(['\n', '\r']).unwrap_or(rest.len()))
+            }
+            '"' => match rest[1..].find('"') {
+                Some(end) => (Str, end + 2),
+                None => (ErrorToken, rest.len()),
+            },
+            '(' => (OpenParen, 1),
+            ')' => (CloseParen, 1),
+            ',' => (Comma, 1),
+            ':' => (Colon, 1),
+            '|' => (Pipe, 1),
+            '+' => (Plus, 1),
+            '-' => (Minus, 1),
+            '*' => (Star, 1),
+            '/' => (Slash, 1),
+            '=' if rest.starts_with("=>") => (FatRightArrow, 2),
+            '=' if rest.starts_with("==") => (DoubleEquals, 2),
+            '=' => (Equals, 1),
+            c if c.is_alphabetic() => {
+                let len = rest
+                    .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+
+                (name_kind(&rest[..len]), len)
+            }
+            c if c.is_numeric() => number(rest),
+            c => (ErrorToken, c.len_utf8()),
+        };
+
+        tokens.push((kind, &rest[..len]));
+        rest = &rest[len..];
+    }
+
+    tokens
+}
+
+fn name_kind(name: &str) -> SyntaxKind {
+    match get_matching_keyword(name) {
+        Some(Keyword::Import) => ImportKw,
+        Some(Keyword::Export) => ExportKw,
+        Some(Keyword::Type) => TypeKw,
+        Some(Keyword::Function) => FnKw,
+        Some(Keyword::If) => IfKw,
+        Some(Keyword::Else) => ElseKw,
+        Some(Keyword::True) => TrueKw,
+        Some(Keyword::False) => FalseKw,
+        None => Name,
+    }
+}
+
+// Follows the tokeniser: digits with at most one `.`, then an integer suffix if there wasn't one
+fn number(source: &str) -> (SyntaxKind, usize) {
+    let mut is_float = false;
+
+    let mut len = source
+        .char_indices()
+        .find(|&(i, c)| {
+            if c == '.' && !is_float && i > 0 {
+                is_float = true;
+                false
+            } else {
+                !c.is_numeric()
+            }
+        })
+        .map_or(source.len(), |(i, _)| i);
+
+    if is_float {
+        return (Float, len);
+    }
+
+    if ["i32", "i64"]
+        .iter()
+        .any(|suffix| source[len..].starts_with(suffix))
+    {
+        len += 3;
+    }
+
+    (Int, len)
+}
@@ -0,0 +1,257 @@
+use super::parser::binary_operator;
+use super::SyntaxKind::{self, *};
+use super::{GreenElement, GreenNode, SyntaxError};
+use crate::ast::*;
+use crate::symbol::Name;
+use crate::tokeniser::tokenise;
+use crate::tokens::{Constant, Integer, Token};
+
+type Result<X> = std::result::Result<X, SyntaxError>;
+
+// Constants in the `Ast` borrow their text from the tree's tokens
+pub(super) fn lower<'t>(root: &'t GreenNode, arena: &'t Arena) -> Result<Ast<'t>> {
+    let root = Node {
+        green: root,
+        offset: 0,
+    };
+
+    let statements = root
+        .nodes()
+        .map(|node| {
+            Ok(TopLevelStatement::Declaration {
+                decl: declaration(node, arena)?,
+                exported: node.token(ExportKw).is_some(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Ast::new(arena.alloc_slice(statements), arena))
+}
+
+// A green node along with where it starts, so errors can point at it
+#[derive(Copy, Clone)]
+struct Node<'t> {
+    green: &'t GreenNode,
+    offset: usize,
+}
+
+impl<'t> Node<'t> {
+    fn nodes(self) -> impl Iterator<Item = Node<'t>> {
+        self.green
+            .children()
+            .iter()
+            .scan(self.offset, |offset, child| {
+                let child_offset = *offset;
+
+                *offset += child.width();
+
+                Some((child_offset, child))
+            })
+            .filter_map(|(offset, child)| match child {
+                GreenElement::Node(green) => Some(Node { green, offset }),
+                GreenElement::Token(_) => None,
+            })
+    }
+
+    fn tokens(self) -> impl Iterator<Item = (SyntaxKind, &'t str)> {
+        self.green
+            .children()
+            .iter()
+            .filter_map(|child| match child {
+                GreenElement::Token(token) if !token.kind().is_trivia() => {
+                    Some((token.kind(), token.text()))
+                }
+                _ => None,
+            })
+    }
+
+    fn token(self, kind: SyntaxKind) -> Option<&'t str> {
+        self.tokens()
+            .find(|&(token_kind, _)| token_kind == kind)
+            .map(|(_, text)| text)
+    }
+
+    fn child(self, kind: SyntaxKind) -> Result<Node<'t>> {
+        self.nodes()
+            .find(|node| node.green.kind() == kind)
+            .ok_or_else(|| self.malformed())
+    }
+
+    fn first_child(self) -> Result<Node<'t>> {
+        self.nodes().next().ok_or_else(|| self.malformed())
+    }
+
+    fn name(self, arena: &'t Arena) -> Result<Name<'t>> {
+        let (text, offset) = self.token_at(SyntaxKind::Name)?;
+
+        Ok(Name::new(arena.intern(text), offset))
+    }
+
+    // the text of the first token of the kind, and where it starts
+    fn token_at(self, kind: SyntaxKind) -> Result<(&'t str, usize)> {
+        let mut offset = self.offset;
+
+        for child in self.green.children() {
+            if let GreenElement::Token(token) = child {
+                if token.kind() == kind {
+                    return Ok((token.text(), offset));
+                }
+            }
+
+            offset += child.width();
+        }
+
+        Err(self.malformed())
+    }
+
+    // the integer read from the first token of the kind on its own, moved to where the token is
+    fn moved(self, kind: SyntaxKind, int: Integer) -> Result<Integer> {
+        let (_, offset) = self.token_at(kind)?;
+
+        Ok(Integer {
+            offset: int.offset + offset as u32,
+            ..int
+        })
+    }
+
+    // only reachable for trees with errors, which aren't lowered
+    fn malformed(self) -> SyntaxError {
+        SyntaxError {
+            message: "malformed syntax tree",
+            range: self.offset..self.offset + self.green.width(),
+        }
+    }
+}
+
+fn declaration<'t>(node: Node<'t>, arena: &'t Arena) -> Result<Declaration<'t>> {
+    match node.green.kind() {
+        Assignment => Ok(Declaration::Assignment {
+            name: node.name(arena)?,
+            expr: expression(node.first_child()?, arena)?,
+        }),
+        FunctionDecl => {
+            let args = node
+                .child(ParamList)?
+                .nodes()
+                .map(|param| {
+                    Ok(FunctionArg {
+                        name: param.name(arena)?,
+                        type_name: match param.nodes().next() {
+                            Some(annotation) => Some(annotation.name(arena)?),
+                            None => None,
+                        },
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            Ok(Declaration::FunctionDecl {
+                name: node.name(arena)?,
+                arguments: FunctionArgsList {
+                    args: arena.alloc_slice(args),
+                },
+                body: block(node.child(Block)?, arena)?,
+            })
+        }
+        _ => Err(node.malformed()),
+    }
+}
+
+fn block<'t>(node: Node<'t>, arena: &'t Arena) -> Result<CodeBlock<'t>> {
+    let statements = node
+        .nodes()
+        .map(|node| statement(node, arena))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(arena.alloc_slice(statements))
+}
+
+fn statement<'t>(node: Node<'t>, arena: &'t Arena) -> Result<CodeBlockStatement<'t>> {
+    let statement = match node.green.kind() {
+        Assignment | FunctionDecl => CodeBlockStatement::Declaration(declaration(node, arena)?),
+        ExprStatement => {
+            CodeBlockStatement::BareExpression(expression(node.first_child()?, arena)?)
+        }
+        IfStatement => {
+            let mut cases = Vec::new();
+            let mut else_case = None;
+
+            for case in node.nodes() {
+                match case.green.kind() {
+                    IfCase => cases.push(IfStatementCase {
+                        condition: expression(case.first_child()?, arena)?,
+                        block: block(case.child(Block)?, arena)?,
+                    }),
+                    ElseCase => else_case = Some(block(case.child(Block)?, arena)?),
+                    _ => return Err(case.malformed()),
+                }
+            }
+
+            CodeBlockStatement::IfStatement {
+                cases: arena.alloc_slice(cases),
+                else_case,
+            }
+        }
+        _ => return Err(node.malformed()),
+    };
+
+    Ok(statement)
+}
+
+fn expression<'t>(node: Node<'t>, arena: &'t Arena) -> Result<Expression<'t>> {
+    let expr = match node.green.kind() {
+        Literal => match node.tokens().next() {
+            Some((TrueKw, _)) => Expression::Constant(Constant::Bool(true)),
+            Some((FalseKw, _)) => Expression::Constant(Constant::Bool(false)),
+            // the tokeniser already knows how to read numbers and strings, but only sees the
+            // one token, so integers are moved to where it is
+            Some((kind, text)) => match tokenise(text).next() {
+                Some(Ok(Token::Constant(Constant::Int(int)))) => {
+                    Expression::Constant(Constant::Int(node.moved(kind, int)?))
+                }
+                Some(Ok(Token::Constant(Constant::Int64(int)))) => {
+                    Expression::Constant(Constant::Int64(node.moved(kind, int)?))
+                }
+                Some(Ok(Token::Constant(c))) => Expression::Constant(c),
+                _ => return Err(node.malformed()),
+            },
+            None => return Err(node.malformed()),
+        },
+        NameRef => Expression::Variable(node.name(arena)?),
+        CallExpr => Expression::FunctionCall {
+            name: node.name(arena)?,
+            args: {
+                let args = node
+                    .child(ArgList)?
+                    .nodes()
+                    .map(|arg| expression(arg, arena))
+                    .collect::<Result<Vec<_>>>()?;
+
+                arena.alloc_slice(args)
+            },
+        },
+        BinaryExpr => {
+            let mut operands = node.nodes();
+
+            let (left, right) = match (operands.next(), operands.next()) {
+                (Some(left), Some(right)) => (left, right),
+                _ => return Err(node.malformed()),
+            };
+
+            let operator = node
+                .tokens()
+                .find_map(|(kind, _)| binary_operator(kind))
+                .ok_or_else(|| node.malformed())?;
+
+            Expression::BinaryOp {
+                left: arena.alloc(expression(left, arena)?),
+                operator,
+                right: arena.alloc(expression(right, arena)?),
+            }
+        }
+        PrefixExpr => Expression::Negation(arena.alloc(expression(node.first_child()?, arena)?)),
+        ParenExpr => expression(node.first_child()?, arena)?,
+        _ => return Err(node.malformed()),
+    };
+
+    Ok(expr)
+}
@@ -0,0 +1,126 @@
+pub use green::{GreenElement, GreenNode, GreenToken};
+pub use kinds::SyntaxKind;
+pub use parser::{parse, Parse, SyntaxError};
+pub use red::{SyntaxElement, SyntaxNode, SyntaxToken};
+pub use reparse::TextEdit;
+
+mod green;
+mod kinds;
+mod lexer;
+mod lower;
+mod parser;
+mod red;
+mod reparse;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyser::{analyse, AnalyserError};
+    use crate::parser::ParseSession;
+    use insta::assert_debug_snapshot;
+    use std::fs;
+    use test_case::test_case;
+
+    fn fixture(name: &str) -> String {
+        fs::read_to_string(format!("src/fixtures/{}.lang", name)).unwrap()
+    }
+
+    #[test_case("example_program")]
+    #[test_case("fibonacci")]
+    #[test_case("functions")]
+    #[test_case("maths")]
+    #[test_case("strings")]
+    #[test_case("unit_functions")]
+    #[test_case("booleans")]
+    #[test_case("int64")]
+    #[test_case("integers")]
+    #[test_case("constants")]
+    #[test_case("inlining")]
+    #[test_case("tail_calls")]
+    #[test_case("comments")]
+    fn fixtures_lower_to_the_same_ast_as_the_parser(name: &str) {
+        let contents = fixture(name);
+
+        let cst = parse(&contents);
+
+        assert_eq!(cst.syntax().to_string(), contents);
+        assert_eq!(cst.errors(), []);
+
+        let session = ParseSession::new();
+
+        let expected = session.parse(&contents).unwrap();
+
+        assert_eq!(
+            format!("{:?}", cst.to_ast(&session).unwrap()),
+            format!("{:?}", expected)
+        );
+    }
+
+    #[test_case(""; "empty")]
+    #[test_case("fn f(x)\r\n    x\r\n"; "crlf line endings")]
+    #[test_case("fn f(x)\n\tx\n"; "tabs")]
+    #[test_case("fn f(x)   \n    x   \n\n   \n"; "trailing spaces")]
+    #[test_case("fn f(x) // comment\n    x // comment"; "comments")]
+    #[test_case("fn f(x)\n    \"unterminated\n"; "unterminated string")]
+    #[test_case("fn f(x)\n    x + $ ? @\n"; "unknown characters")]
+    #[test_case("fn f(x)\n    x\n        y\n  z\n"; "inconsistent indentation")]
+    #[test_case("fn (\nexport export\n= = )"; "garbage")]
+    fn printing_reproduces_the_source(source: &str) {
+        assert_eq!(parse(source).syntax().to_string(), source);
+    }
+
+    #[test]
+    fn tree() {
+        assert_debug_snapshot!(parse(&fixture("example_program")).syntax());
+    }
+
+    #[test_case("fn f(x\n    x\n"; "missing close paren")]
+    #[test_case("fn f(x)\nx\n"; "missing block")]
+    #[test_case("export\nfn f()\n    1 +\n"; "missing expression")]
+    #[test_case("fn f()\n    1\n        2\n"; "unexpected indentation")]
+    #[test_case("fn f()\n    1²\n"; "invalid number")]
+    fn errors(source: &str) {
+        let cst = parse(source);
+
+        assert_eq!(cst.syntax().to_string(), source);
+        assert_debug_snapshot!(cst.errors());
+    }
+
+    #[test]
+    fn lowered_integers_know_where_they_are() {
+        let source = "fn f()\n    x = 3000000000\n";
+
+        let session = ParseSession::new();
+
+        let cst = parse(source);
+
+        for ast in [
+            cst.to_ast(&session).unwrap(),
+            session.parse(source).unwrap(),
+        ] {
+            match analyse(&ast) {
+                Err(AnalyserError::IntegerOutOfRange { literal, .. }) => {
+                    assert_eq!(literal.offset, 15)
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn nodes_know_their_position_and_parent() {
+        let source = "fn f(x)\n    x + 1\n";
+
+        let root = parse(source).syntax();
+
+        let binary = root
+            .descendants()
+            .into_iter()
+            .find(|node| node.kind() == SyntaxKind::BinaryExpr)
+            .unwrap();
+
+        assert_eq!(binary.text(), "x + 1");
+        assert_eq!(&source[binary.text_range()], "x + 1");
+        assert_eq!(binary.parent().unwrap().kind(), SyntaxKind::ExprStatement);
+    }
+}
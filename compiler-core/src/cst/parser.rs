@@ -0,0 +1,481 @@
+use super::green::{Checkpoint, GreenBuilder, GreenNode};
+use super::lexer::tokenise_lossless;
+use super::lower::lower;
+use super::SyntaxKind::{self, *};
+use super::SyntaxNode;
+use crate::ast::Ast;
+use crate::binding_power::*;
+use crate::operators::BinaryOperator;
+use crate::parser::ParseSession;
+use crate::tokeniser::tokenise;
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+
+// Parses the same grammar as `crate::parser`, but keeps every token, and carries on past errors
+// so that tooling always gets a tree
+pub fn parse(source: &str) -> Parse {
+    let mut parser = Parser {
+        tokens: tokenise_lossless(source),
+        pos: 0,
+        offset: 0,
+        builder: GreenBuilder::default(),
+        errors: Vec::new(),
+    };
+
+    parser.root();
+
+    Parse {
+        green: parser.builder.finish(),
+        errors: parser.errors,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Parse {
+    pub(super) green: Rc<GreenNode>,
+    pub(super) errors: Vec<SyntaxError>,
+}
+
+impl Parse {
+    pub fn green(&self) -> &Rc<GreenNode> {
+        &self.green
+    }
+
+    pub fn syntax(&self) -> SyntaxNode {
+        SyntaxNode::new_root(self.green.clone())
+    }
+
+    pub fn errors(&self) -> &[SyntaxError] {
+        &self.errors
+    }
+
+    // Lowers the tree to the `Ast` the rest of the compiler uses, if it parsed without errors
+    pub fn to_ast<'a>(&'a self, session: &'a ParseSession) -> Result<Ast<'a>, SyntaxError> {
+        match self.errors.first() {
+            Some(error) => Err(error.clone()),
+            None => lower(&self.green, session.arena()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub message: &'static str,
+    pub range: Range<usize>,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+struct Parser<'s> {
+    tokens: Vec<(SyntaxKind, &'s str)>,
+    pos: usize,
+    // byte offset of the token at `pos`
+    offset: usize,
+    builder: GreenBuilder,
+    errors: Vec<SyntaxError>,
+}
+
+impl<'s> Parser<'s> {
+    // the nth token after the current position that isn't trivia
+    fn nth(&self, n: usize) -> Option<SyntaxKind> {
+        self.tokens[self.pos..]
+            .iter()
+            .map(|&(kind, _)| kind)
+            .filter(|kind| !kind.is_trivia())
+            .nth(n)
+    }
+
+    fn current(&self) -> Option<SyntaxKind> {
+        self.nth(0)
+    }
+
+    fn at(&self, kind: SyntaxKind) -> bool {
+        self.current() == Some(kind)
+    }
+
+    fn push_token(&mut self) {
+        let (kind, text) = self.tokens[self.pos];
+
+        self.builder.token(kind, text);
+        self.pos += 1;
+        self.offset += text.len();
+    }
+
+    // trivia goes into whichever node is open when the next token is reached
+    fn eat_trivia(&mut self) {
+        while self.pos < self.tokens.len() && self.tokens[self.pos].0.is_trivia() {
+            self.push_token();
+        }
+    }
+
+    fn bump(&mut self) {
+        self.eat_trivia();
+
+        if self.pos < self.tokens.len() {
+            self.push_token();
+        }
+    }
+
+    fn eat(&mut self, kind: SyntaxKind) -> bool {
+        let at_kind = self.at(kind);
+
+        if at_kind {
+            self.bump();
+        }
+
+        at_kind
+    }
+
+    fn start_node(&mut self, kind: SyntaxKind) {
+        self.eat_trivia();
+        self.builder.start_node(kind);
+    }
+
+    fn start_node_at(&mut self, checkpoint: Checkpoint, kind: SyntaxKind) {
+        self.builder.start_node_at(checkpoint, kind);
+    }
+
+    fn checkpoint(&mut self) -> Checkpoint {
+        self.eat_trivia();
+        self.builder.checkpoint()
+    }
+
+    fn finish_node(&mut self) {
+        self.builder.finish_node();
+    }
+
+    fn error(&mut self, message: &'static str) {
+        self.eat_trivia();
+
+        let len = self.tokens.get(self.pos).map_or(0, |(_, text)| text.len());
+
+        self.errors.push(SyntaxError {
+            message,
+            range: self.offset..self.offset + len,
+        });
+    }
+
+    // wraps the current token in an error node, so parsing can carry on after it
+    fn error_and_bump(&mut self, message: &'static str) {
+        self.error(message);
+
+        if self.current().is_some() {
+            self.start_node(Error);
+            self.bump();
+            self.finish_node();
+        }
+    }
+
+    fn root(&mut self) {
+        self.builder.start_node(Root);
+
+        while self.current().is_some() {
+            self.top_level_statement();
+        }
+
+        self.eat_trivia();
+        self.finish_node();
+    }
+
+    fn top_level_statement(&mut self) {
+        match self.current() {
+            Some(ExportKw) => {
+                let checkpoint = self.checkpoint();
+
+                self.bump();
+
+                match self.current() {
+                    Some(FnKw) => self.function(Some(checkpoint)),
+                    Some(Name) => self.assignment(Some(checkpoint)),
+                    Some(ExportKw) => {
+                        self.start_node_at(checkpoint, Error);
+                        self.error_and_bump("export specified twice");
+                        self.finish_node();
+                    }
+                    _ => {
+                        self.start_node_at(checkpoint, Error);
+                        self.error("expected a declaration to export");
+                        self.finish_node();
+                    }
+                }
+            }
+            Some(FnKw) => self.function(None),
+            Some(Name) => self.assignment(None),
+            Some(Indent) => self.unexpected_block(),
+            _ => self.error_and_bump("expected a top level statement"),
+        }
+    }
+
+    fn statement(&mut self) {
+        match self.current() {
+            Some(Name) if self.nth(1) == Some(Equals) => self.assignment(None),
+            Some(Name | Int | Float | Str | TrueKw | FalseKw | OpenParen) => {
+                self.start_node(ExprStatement);
+                self.expression(BindingPower::default());
+                self.finish_node();
+            }
+            Some(FnKw) => self.function(None),
+            Some(IfKw) => self.if_statement(),
+            Some(Indent) => self.unexpected_block(),
+            _ => self.error_and_bump("expected a statement"),
+        }
+    }
+
+    // keeps indentation balanced by parsing over-indented lines as their own block
+    fn unexpected_block(&mut self) {
+        self.start_node(Error);
+        self.error("unexpected indentation");
+        self.block();
+        self.finish_node();
+    }
+
+    fn assignment(&mut self, checkpoint: Option<Checkpoint>) {
+        match checkpoint {
+            Some(checkpoint) => self.start_node_at(checkpoint, Assignment),
+            None => self.start_node(Assignment),
+        }
+
+        self.bump();
+
+        if self.eat(Equals) {
+            self.expression(BindingPower::default());
+        } else {
+            self.error("expected `=`");
+        }
+
+        self.finish_node();
+    }
+
+    fn function(&mut self, checkpoint: Option<Checkpoint>) {
+        match checkpoint {
+            Some(checkpoint) => self.start_node_at(checkpoint, FunctionDecl),
+            None => self.start_node(FunctionDecl),
+        }
+
+        self.bump();
+
+        if !self.eat(Name) {
+            self.error("expected a function name");
+        }
+
+        self.param_list();
+        self.block();
+        self.finish_node();
+    }
+
+    fn param_list(&mut self) {
+        self.start_node(ParamList);
+
+        if self.eat(OpenParen) {
+            loop {
+                match self.current() {
+                    Some(CloseParen) => {
+                        self.bump();
+                        break;
+                    }
+                    Some(Name) => self.param(),
+                    None | Some(Indent | Dedent) => {
+                        self.error("expected `)`");
+                        break;
+                    }
+                    _ => self.error_and_bump("expected an argument name"),
+                }
+            }
+        } else {
+            self.error("expected function arguments");
+        }
+
+        self.finish_node();
+    }
+
+    fn param(&mut self) {
+        self.start_node(Param);
+        self.bump();
+
+        if self.at(Colon) {
+            self.start_node(TypeAnnotation);
+            self.bump();
+
+            if !self.eat(Name) {
+                self.error("expected a type name");
+            }
+
+            self.finish_node();
+        }
+
+        self.eat(Comma);
+        self.finish_node();
+    }
+
+    fn block(&mut self) {
+        self.start_node(Block);
+
+        if self.eat(Indent) {
+            loop {
+                match self.current() {
+                    Some(Dedent) => {
+                        self.bump();
+                        break;
+                    }
+                    None => {
+                        self.error("unexpected end of input");
+                        break;
+                    }
+                    _ => self.statement(),
+                }
+            }
+        } else {
+            self.error("expected an indented block");
+        }
+
+        self.finish_node();
+    }
+
+    fn if_statement(&mut self) {
+        self.start_node(IfStatement);
+
+        self.start_node(IfCase);
+        self.bump();
+        self.expression(BindingPower::default());
+        self.block();
+        self.finish_node();
+
+        while self.at(ElseKw) {
+            if self.nth(1) == Some(IfKw) {
+                self.start_node(IfCase);
+                self.bump();
+                self.bump();
+                self.expression(BindingPower::default());
+                self.block();
+                self.finish_node();
+            } else {
+                self.start_node(ElseCase);
+                self.bump();
+                self.block();
+                self.finish_node();
+
+                break;
+            }
+        }
+
+        self.finish_node();
+    }
+
+    fn expression(&mut self, right_binding_power: BindingPower) {
+        let checkpoint = self.checkpoint();
+
+        self.null_denotation();
+
+        while let Some(operator) = self.current().and_then(binary_operator) {
+            let binding_power = operator.binding_power();
+
+            if right_binding_power >= binding_power {
+                break;
+            }
+
+            self.start_node_at(checkpoint, BinaryExpr);
+            self.bump();
+            self.expression(binding_power);
+            self.finish_node();
+        }
+    }
+
+    fn null_denotation(&mut self) {
+        match self.current() {
+            Some(Int | Float) => {
+                let text = self.tokens[self.pos..]
+                    .iter()
+                    .find(|(kind, _)| !kind.is_trivia())
+                    .map_or("", |&(_, text)| text);
+
+                if !matches!(tokenise(text).next(), Some(Ok(_))) {
+                    self.error("invalid number");
+                }
+
+                self.literal();
+            }
+            Some(Str | TrueKw | FalseKw) => self.literal(),
+            Some(Name) if self.nth(1) == Some(OpenParen) => {
+                self.start_node(CallExpr);
+                self.bump();
+                self.arg_list();
+                self.finish_node();
+            }
+            Some(Name) => {
+                self.start_node(NameRef);
+                self.bump();
+                self.finish_node();
+            }
+            Some(Minus) => {
+                self.start_node(PrefixExpr);
+                self.bump();
+                self.expression(BindingPower::negation());
+                self.finish_node();
+            }
+            Some(OpenParen) => {
+                self.start_node(ParenExpr);
+                self.bump();
+                self.expression(BindingPower::default());
+
+                if !self.eat(CloseParen) {
+                    self.error("expected `)`");
+                }
+
+                self.finish_node();
+            }
+            // leaves indentation for the enclosing block to deal with
+            None | Some(Indent | Dedent) => self.error("expected an expression"),
+            _ => self.error_and_bump("expected an expression"),
+        }
+    }
+
+    fn literal(&mut self) {
+        self.start_node(Literal);
+        self.bump();
+        self.finish_node();
+    }
+
+    fn arg_list(&mut self) {
+        self.start_node(ArgList);
+        self.bump();
+
+        loop {
+            match self.current() {
+                Some(CloseParen) => {
+                    self.bump();
+                    break;
+                }
+                None | Some(Indent | Dedent) => {
+                    self.error("expected `)`");
+                    break;
+                }
+                _ => {
+                    self.expression(BindingPower::default());
+                    self.eat(Comma);
+                }
+            }
+        }
+
+        self.finish_node();
+    }
+}
+
+pub(super) fn binary_operator(kind: SyntaxKind) -> Option<BinaryOperator> {
+    let operator = match kind {
+        Plus => BinaryOperator::Plus,
+        Minus => BinaryOperator::Minus,
+        Star => BinaryOperator::Multiply,
+        Slash => BinaryOperator::Divide,
+        DoubleEquals => BinaryOperator::DoubleEquals,
+        _ => return None,
+    };
+
+    Some(operator)
+}
@@ -0,0 +1,174 @@
+use super::{GreenElement, GreenNode, GreenToken, SyntaxKind};
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+
+// Red trees wrap the green tree, working out absolute positions and parents as they're walked
+
+#[derive(Clone)]
+pub struct SyntaxNode(Rc<NodeData>);
+
+struct NodeData {
+    green: Rc<GreenNode>,
+    offset: usize,
+    parent: Option<SyntaxNode>,
+}
+
+impl SyntaxNode {
+    pub fn new_root(green: Rc<GreenNode>) -> Self {
+        SyntaxNode(Rc::new(NodeData {
+            green,
+            offset: 0,
+            parent: None,
+        }))
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.0.green.kind()
+    }
+
+    pub fn green(&self) -> &Rc<GreenNode> {
+        &self.0.green
+    }
+
+    pub fn text_range(&self) -> Range<usize> {
+        self.0.offset..self.0.offset + self.0.green.width()
+    }
+
+    pub fn parent(&self) -> Option<SyntaxNode> {
+        self.0.parent.clone()
+    }
+
+    pub fn children_with_tokens(&self) -> impl Iterator<Item = SyntaxElement> + '_ {
+        let mut offset = self.0.offset;
+
+        self.0.green.children().iter().map(move |child| {
+            let child_offset = offset;
+
+            offset += child.width();
+
+            match child {
+                GreenElement::Node(green) => SyntaxElement::Node(SyntaxNode(Rc::new(NodeData {
+                    green: green.clone(),
+                    offset: child_offset,
+                    parent: Some(self.clone()),
+                }))),
+                GreenElement::Token(green) => SyntaxElement::Token(SyntaxToken {
+                    green: green.clone(),
+                    offset: child_offset,
+                    parent: self.clone(),
+                }),
+            }
+        })
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = SyntaxNode> + '_ {
+        self.children_with_tokens().filter_map(|child| match child {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None,
+        })
+    }
+
+    // every node in the tree under this one, including itself, in source order
+    pub fn descendants(&self) -> Vec<SyntaxNode> {
+        let mut descendants = vec![self.clone()];
+
+        for child in self.children() {
+            descendants.extend(child.descendants());
+        }
+
+        descendants
+    }
+
+    pub fn text(&self) -> String {
+        self.0.green.to_string()
+    }
+}
+
+impl PartialEq for SyntaxNode {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0.green, &other.0.green) && self.0.offset == other.0.offset
+    }
+}
+
+impl fmt::Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.green)
+    }
+}
+
+// Prints the tree with one node or token per line, indented by depth
+impl fmt::Debug for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let depth = std::iter::successors(self.parent(), SyntaxNode::parent).count();
+
+        let range = self.text_range();
+
+        writeln!(
+            f,
+            "{:indent$}{:?}@{}..{}",
+            "",
+            self.kind(),
+            range.start,
+            range.end,
+            indent = depth * 2
+        )?;
+
+        for child in self.children_with_tokens() {
+            match child {
+                SyntaxElement::Node(node) => write!(f, "{:?}", node)?,
+                SyntaxElement::Token(token) => {
+                    writeln!(f, "{:indent$}{:?}", "", token, indent = depth * 2 + 2)?
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct SyntaxToken {
+    green: Rc<GreenToken>,
+    offset: usize,
+    parent: SyntaxNode,
+}
+
+impl SyntaxToken {
+    pub fn kind(&self) -> SyntaxKind {
+        self.green.kind()
+    }
+
+    pub fn text(&self) -> &str {
+        self.green.text()
+    }
+
+    pub fn text_range(&self) -> Range<usize> {
+        self.offset..self.offset + self.green.width()
+    }
+
+    pub fn parent(&self) -> SyntaxNode {
+        self.parent.clone()
+    }
+}
+
+impl fmt::Debug for SyntaxToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let range = self.text_range();
+
+        write!(
+            f,
+            "{:?}@{}..{} {:?}",
+            self.kind(),
+            range.start,
+            range.end,
+            self.text()
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
@@ -0,0 +1,263 @@
+use super::SyntaxKind::*;
+use super::{parse, GreenElement, GreenNode, GreenToken, Parse, SyntaxError};
+use std::fmt::Write;
+use std::ops::Range;
+use std::rc::Rc;
+
+// Replaces the text in `range` with `replacement`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub replacement: String,
+}
+
+impl Parse {
+    // Gives the same result as parsing the edited text from scratch, but only re-tokenises and
+    // reparses the top level declarations around the edit, sharing the rest of the tree.
+    // Panics if the range isn't in the text, like `String::replace_range`
+    pub fn reparse(&self, edit: &TextEdit) -> Parse {
+        self.reparse_declarations(edit).unwrap_or_else(|| {
+            let mut text = self.green.to_string();
+
+            text.replace_range(edit.range.clone(), &edit.replacement);
+
+            parse(&text)
+        })
+    }
+
+    fn reparse_declarations(&self, edit: &TextEdit) -> Option<Parse> {
+        let children = self.green.children();
+        let boundaries = boundaries(&self.green);
+
+        // the first token after the start has to be untouched, or the edit could join it onto
+        // the end of the previous declaration
+        let start = boundaries
+            .iter()
+            .rev()
+            .find(|b| b.index == 0 || b.offset + b.first_token_width < edit.range.start)?;
+
+        let end = boundaries.iter().find(|b| {
+            b.offset > edit.range.end || (b.index == children.len() && b.offset >= edit.range.end)
+        })?;
+
+        if start.index == 0 && end.index == children.len() {
+            return None;
+        }
+
+        let mut text = String::with_capacity(end.offset - start.offset);
+
+        for child in &children[start.index..end.index] {
+            match child {
+                GreenElement::Node(node) => write!(text, "{}", node).ok()?,
+                GreenElement::Token(token) => text.push_str(token.text()),
+            }
+        }
+
+        text.replace_range(
+            edit.range.start - start.offset..edit.range.end - start.offset,
+            &edit.replacement,
+        );
+
+        let region = parse(&text);
+
+        // an error could mean the declarations were meant to run into the ones after them
+        if !region.errors.is_empty() {
+            return None;
+        }
+
+        let new_children = children[..start.index]
+            .iter()
+            .chain(region.green.children())
+            .chain(&children[end.index..])
+            .cloned()
+            .collect();
+
+        let shift = |error: &SyntaxError| SyntaxError {
+            message: error.message,
+            range: error.range.start + edit.replacement.len() - edit.range.len()
+                ..error.range.end + edit.replacement.len() - edit.range.len(),
+        };
+
+        // errors before the reparsed declarations can only point as far as their first token,
+        // and the ones after are all past the first token of the declaration following them
+        let errors = self
+            .errors
+            .iter()
+            .filter(|error| start.index > 0 && error.range.start <= start.offset)
+            .cloned()
+            .chain(
+                self.errors
+                    .iter()
+                    .filter(|error| error.range.start > end.offset)
+                    .map(shift),
+            )
+            .collect();
+
+        Some(Parse {
+            green: Rc::new(GreenNode::new(Root, new_children)),
+            errors,
+        })
+    }
+}
+
+// A place the root's children can be split, so the ones after it can be parsed on their own
+struct Boundary {
+    index: usize,
+    offset: usize,
+    first_token_width: usize,
+}
+
+// Declarations starting with `fn` or `export` at the start of a line end any indentation and
+// expression before them, so the parser is always back at the top level when it reaches one
+fn boundaries(root: &GreenNode) -> Vec<Boundary> {
+    let mut boundaries = vec![Boundary {
+        index: 0,
+        offset: 0,
+        first_token_width: 0,
+    }];
+
+    let mut offset = 0;
+
+    for (index, pair) in root.children().windows(2).enumerate() {
+        let (previous, child) = (&pair[0], &pair[1]);
+
+        offset += previous.width();
+
+        let first_token = match child {
+            GreenElement::Node(node) => match node.children().first() {
+                Some(GreenElement::Token(token)) => token,
+                _ => continue,
+            },
+            GreenElement::Token(_) => continue,
+        };
+
+        let starts_line = last_token(previous).is_some_and(|token| token.kind() == Newline);
+
+        if matches!(first_token.kind(), FnKw | ExportKw) && starts_line {
+            boundaries.push(Boundary {
+                index: index + 1,
+                offset,
+                first_token_width: first_token.width(),
+            });
+        }
+    }
+
+    boundaries.push(Boundary {
+        index: root.children().len(),
+        offset: root.width(),
+        first_token_width: 0,
+    });
+
+    boundaries
+}
+
+// The last token with any text in it
+fn last_token(element: &GreenElement) -> Option<&GreenToken> {
+    match element {
+        GreenElement::Token(token) if token.width() > 0 => Some(token),
+        GreenElement::Token(_) => None,
+        GreenElement::Node(node) => node.children().iter().rev().find_map(last_token),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use test_case::test_case;
+
+    fn apply(source: &str, range: Range<usize>, replacement: &str) {
+        let edit = TextEdit {
+            range: range.clone(),
+            replacement: replacement.to_string(),
+        };
+
+        let mut edited = source.to_string();
+
+        edited.replace_range(range, replacement);
+
+        let reparsed = parse(source).reparse(&edit);
+        let expected = parse(&edited);
+
+        assert_eq!(reparsed.green, expected.green, "{:?} in {:?}", edit, source);
+        assert_eq!(
+            reparsed.errors, expected.errors,
+            "{:?} in {:?}",
+            edit, source
+        );
+    }
+
+    // Tries edits at every position, which covers edits inside, between and across declarations
+    #[test_case("example_program")]
+    #[test_case("fibonacci")]
+    #[test_case("unit_functions")]
+    #[test_case("booleans")]
+    #[test_case("int64")]
+    #[test_case("constants")]
+    #[test_case("inlining")]
+    #[test_case("tail_calls")]
+    #[test_case("comments")]
+    fn edits_give_the_same_result_as_a_full_parse(name: &str) {
+        let source = fs::read_to_string(format!("src/fixtures/{}.lang", name)).unwrap();
+
+        let replacements = [
+            "",
+            "x",
+            "1 +",
+            "\n",
+            "    ",
+            "(",
+            "\nfn g()\n    1\n",
+            "export ",
+        ];
+
+        for start in (0..=source.len()).filter(|&i| source.is_char_boundary(i)) {
+            for len in [0, 1, 5] {
+                let end = start + len;
+
+                if end > source.len() || !source.is_char_boundary(end) {
+                    continue;
+                }
+
+                for replacement in replacements.iter() {
+                    apply(&source, start..end, replacement);
+                }
+            }
+        }
+    }
+
+    #[test_case("x = 1\nfn f()\n    1\n", 5..5, "\n- 2"; "continuing an expression")]
+    #[test_case("fn f()\n    1\nfn g()\n    2\n", 13..13, "    3\n"; "indenting into the previous block")]
+    #[test_case("fn f()\n    1\nfn g()\n    2\n", 14..14, "x"; "joining onto a keyword")]
+    #[test_case("fn f()\n    (1\nfn g()\n    2\n", 22..23, "3"; "after an error")]
+    #[test_case("fn f()\n    1\nfn g()\n    2\n", 0..25, ""; "deleting everything")]
+    fn edits_near_declaration_boundaries(source: &str, range: Range<usize>, replacement: &str) {
+        apply(source, range, replacement);
+    }
+
+    #[test]
+    fn unchanged_declarations_are_reused() {
+        let source = "fn f()\n    1\n\nfn g()\n    2\n\nfn h()\n    3\n";
+
+        let before = parse(source);
+
+        let after = before.reparse(&TextEdit {
+            range: 25..26,
+            replacement: "20".to_string(),
+        });
+
+        let shared: Vec<_> = before
+            .green
+            .children()
+            .iter()
+            .zip(after.green.children())
+            .map(|pair| match pair {
+                (GreenElement::Node(a), GreenElement::Node(b)) => Rc::ptr_eq(a, b),
+                _ => false,
+            })
+            .collect();
+
+        assert_eq!(shared, [true, false, true]);
+        assert_eq!(after.syntax().to_string(), source.replace("2\n", "20\n"));
+    }
+}
@@ -0,0 +1,126 @@
+use crate::ast::*;
+use crate::symbol::{Name, Symbol};
+use std::collections::{HashMap, HashSet};
+
+// `main` is kept even when it isn't exported, as that's where a program starts
+const ENTRY_POINT: &str = "main";
+
+// Removes every function that can't be reached by calls from an exported function or `main`
+pub fn remove_unused_functions(ast: Ast) -> Ast {
+    let reachable = reachable_functions(&ast);
+
+    let used = ast.statements.iter().copied().filter(|statement| {
+        let TopLevelStatement::Declaration { decl, .. } = statement;
+
+        match decl {
+            Declaration::FunctionDecl { name, .. } => reachable.contains(&name.symbol),
+            Declaration::Assignment { .. } => true,
+        }
+    });
+
+    Ast::new(ast.arena.alloc_slice(used), ast.arena)
+}
+
+// The functions `remove_unused_functions` would remove, in the order they're declared
+pub fn unused_functions<'a>(ast: &Ast<'a>) -> Vec<Name<'a>> {
+    let reachable = reachable_functions(ast);
+
+    ast.statements
+        .iter()
+        .filter_map(|TopLevelStatement::Declaration { decl, .. }| match decl {
+            Declaration::FunctionDecl { name, .. } if !reachable.contains(&name.symbol) => {
+                Some(*name)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn reachable_functions<'a>(ast: &Ast<'a>) -> HashSet<Symbol<'a>> {
+    let entry_point = ast.arena.intern(ENTRY_POINT);
+    let mut bodies = HashMap::new();
+    let mut reachable = HashSet::new();
+    let mut to_visit = vec![];
+
+    for statement in ast.statements {
+        let TopLevelStatement::Declaration { decl, exported } = statement;
+
+        if let Declaration::FunctionDecl { name, body, .. } = decl {
+            bodies.insert(name.symbol, body);
+
+            if *exported || name.symbol == entry_point {
+                to_visit.push(*name);
+            }
+        }
+    }
+
+    while let Some(name) = to_visit.pop() {
+        if !reachable.insert(name.symbol) {
+            continue;
+        }
+
+        if let Some(body) = bodies.get(&name.symbol) {
+            for statement in body.iter() {
+                statement.calls(&mut to_visit);
+            }
+        }
+    }
+
+    reachable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::ParseSession;
+    use std::fs;
+    use test_case::test_case;
+
+    fn function_names<'a>(ast: &Ast<'a>) -> Vec<&'a str> {
+        ast.statements
+            .iter()
+            .map(|TopLevelStatement::Declaration { decl, .. }| decl.name().as_str())
+            .collect()
+    }
+
+    #[test_case("src/fixtures/example_program.lang", &["add", "main"]; "example program")]
+    #[test_case("src/fixtures/inlining.lang", &["square", "add", "sum_of_squares", "countdown", "main"]; "inlining")]
+    #[test_case("src/fixtures/functions.lang", &["function_with_arguments"]; "functions")]
+    fn fixtures(fixture_file_name: &str, expected: &[&str]) -> std::io::Result<()> {
+        let contents = fs::read_to_string(fixture_file_name)?;
+
+        let session = ParseSession::new();
+
+        let ast = remove_unused_functions(session.parse(&contents).unwrap());
+
+        assert_eq!(function_names(&ast), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn mutually_recursive_functions_are_kept_once_reachable() {
+        let source = "fn a(n)\n    b(n)\n\nfn b(n)\n    a(n)\n\nfn c(n)\n    c(n)\n\nexport fn g(n)\n    a(n)\n";
+
+        let session = ParseSession::new();
+
+        let ast = remove_unused_functions(session.parse(source).unwrap());
+
+        assert_eq!(function_names(&ast), ["a", "b", "g"]);
+    }
+
+    #[test]
+    fn unused_functions_are_the_ones_removed() {
+        let source =
+            "fn a(n)\n    b(n)\n\nfn b(n)\n    a(n)\n\nfn c(n)\n    c(n)\n\nfn main()\n    1\n";
+
+        let session = ParseSession::new();
+
+        let unused: Vec<_> = unused_functions(&session.parse(source).unwrap())
+            .into_iter()
+            .map(Name::as_str)
+            .collect();
+
+        assert_eq!(unused, ["a", "b", "c"]);
+    }
+}
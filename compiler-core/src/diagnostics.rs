@@ -0,0 +1,231 @@
+use crate::analyser::AnalyserError;
+use crate::constant_folding::ConstantFoldingError;
+use crate::cst;
+use crate::parser::ParseError;
+use crate::symbol::Name;
+use crate::tokens::{Integer, Token};
+use crate::CompileError;
+use std::ops::Range;
+
+// An error in a form for showing to people, pointing at the part of the source it's about
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    // `None` when the error isn't about any particular part of the source
+    pub location: Option<Location>,
+}
+
+// Errors stop a module from being generated, warnings don't
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    // in bytes
+    pub range: Range<usize>,
+    pub start: Position,
+    pub end: Position,
+}
+
+// Both count from 1, with columns counted in characters rather than bytes
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+    pub fn new(source: &str, range: Range<usize>) -> Location {
+        Location {
+            start: Position::of(source, range.start),
+            end: Position::of(source, range.end),
+            range,
+        }
+    }
+}
+
+impl Position {
+    // where the character starting at byte `offset` is
+    pub fn of(source: &str, offset: usize) -> Position {
+        let before = source.get(..offset).unwrap_or(source);
+
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+
+        Position {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+// Parse errors are taken from the lossless parser, which carries on after an error, so all of
+// them get reported at once rather than just the first
+pub fn diagnostics(source: &str, error: &CompileError) -> Vec<Diagnostic> {
+    if let CompileError::ParseError(_) = error {
+        let parse = cst::parse(source);
+
+        if !parse.errors().is_empty() {
+            return parse
+                .errors()
+                .iter()
+                .map(|error| Diagnostic {
+                    severity: Severity::Error,
+                    message: error.message.to_string(),
+                    location: Some(Location::new(source, error.range.clone())),
+                })
+                .collect();
+        }
+    }
+
+    vec![diagnostic(source, error)]
+}
+
+pub fn diagnostic(source: &str, error: &CompileError) -> Diagnostic {
+    let range = match error {
+        CompileError::ParseError(error) => parse_error_range(source, error),
+        CompileError::AnalyserError(AnalyserError::IntegerOutOfRange { literal, .. }) => {
+            integer_range(source, *literal)
+        }
+        CompileError::AnalyserError(error) => range_of(source, analyser_error_name(error)),
+        CompileError::ConstantFoldingError(ConstantFoldingError::DivisionByZero { function }) => {
+            range_of(source, *function)
+        }
+        CompileError::CodeGenError(_)
+        | CompileError::ValidationError(_)
+        | CompileError::FmtError(_) => None,
+    };
+
+    Diagnostic {
+        severity: Severity::Error,
+        message: error.to_string(),
+        location: range.map(|range| Location::new(source, range)),
+    }
+}
+
+fn parse_error_range(source: &str, error: &ParseError) -> Option<Range<usize>> {
+    match error {
+        ParseError::UnexpectedToken(Token::Name(name), _) => {
+            written_at(source, name.text, name.offset)
+        }
+        ParseError::UnexpectedEndOfInput => Some(source.len()..source.len()),
+        _ => None,
+    }
+}
+
+// the name each error is about
+fn analyser_error_name<'a>(error: &AnalyserError<'a>) -> Name<'a> {
+    use AnalyserError::*;
+
+    match *error {
+        DuplicateDeclaration(name)
+        | DuplicateVariable(name)
+        | UndefinedVariable(name)
+        | PossiblyUnassignedVariable(name)
+        | UndefinedFunction(name)
+        | UnitAssignment(name)
+        | UnknownType(name) => name,
+        WrongNumberOfArguments { function, .. }
+        | TypeMismatch { function, .. }
+        | IntegerOutOfRange { function, .. } => function,
+    }
+}
+
+pub fn unused_function(source: &str, name: Name) -> Diagnostic {
+    Diagnostic {
+        severity: Severity::Warning,
+        message: format!("`{}` is never called", name),
+        location: range_of(source, name).map(|range| Location::new(source, range)),
+    }
+}
+
+// Names know where they were written, but only in the source they were parsed from, so one
+// that isn't there in this source has no range
+fn range_of(source: &str, name: Name) -> Option<Range<usize>> {
+    written_at(source, name.as_str(), name.offset)
+}
+
+// The digits of an integer, which are only where it says when it came from this source. Ones
+// too big for an `i128` aren't kept as written, so are only checked to be digits
+fn integer_range(source: &str, int: Integer) -> Option<Range<usize>> {
+    if int.value != i128::MAX {
+        return written_at(source, &int.value.to_string(), int.offset);
+    }
+
+    let start = int.offset as usize;
+    let digits = source
+        .get(start..)?
+        .bytes()
+        .take_while(u8::is_ascii_digit)
+        .count();
+
+    (digits > 0).then_some(start..start + digits)
+}
+
+fn written_at(source: &str, text: &str, offset: u32) -> Option<Range<usize>> {
+    let start = offset as usize;
+    let range = start..start + text.len();
+
+    (source.get(range.clone()) == Some(text)).then_some(range)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::ParseSession;
+    use crate::{compile_module, OptimisationLevel};
+    use insta::assert_debug_snapshot;
+    use test_case::test_case;
+
+    #[test_case("fn f(\n    1\n\nfn g() 2\n", OptimisationLevel::Basic; "every parse error")]
+    #[test_case("fn f()\n    x\n", OptimisationLevel::Basic; "undefined variable")]
+    #[test_case("fn f()\n    1\n\nfn f()\n    2\n", OptimisationLevel::Basic; "duplicate declaration")]
+    #[test_case("fn f()\n    1\n\nfn g()\n    f(2)\n", OptimisationLevel::Basic; "wrong number of arguments")]
+    #[test_case("fn f()\n    1\n\nfn g()\n    true + f()\n", OptimisationLevel::Basic; "type mismatch")]
+    #[test_case("export fn main()\n    1 / 0\n", OptimisationLevel::Basic; "division by zero")]
+    #[test_case("export fn main(x)\n    x / (2 - 2)\n", OptimisationLevel::None; "division by zero without optimising")]
+    #[test_case("export fn main()\n    x = -3000000000\n", OptimisationLevel::Basic; "integer out of range")]
+    #[test_case("export fn main()\n    x = 99999999999999999999\n", OptimisationLevel::Basic; "integer too big for int64")]
+    #[test_case("export fn main()\n    x = 1000000000000000000000000000000000000000000\n", OptimisationLevel::Basic; "integer too big for anything")]
+    #[test_case("export fn main()\n    print(\"hi\")\n", OptimisationLevel::Basic; "builtin without wasi")]
+    fn errors(source: &str, optimisation: OptimisationLevel) {
+        let session = ParseSession::new();
+
+        let error = compile_module(&session, source, optimisation).unwrap_err();
+
+        assert_debug_snapshot!(diagnostics(source, &error));
+    }
+
+    #[test_case(0, 1, 1; "start")]
+    #[test_case(3, 1, 4; "same line")]
+    #[test_case(4, 2, 1; "after a newline")]
+    #[test_case(10, 2, 5; "after multibyte characters")]
+    #[test_case(13, 3, 2; "end")]
+    fn positions(offset: usize, line: usize, column: usize) {
+        assert_eq!(
+            Position::of("abc\nsé¢ab\nc", offset),
+            Position { line, column }
+        );
+    }
+
+    #[test]
+    fn names_from_another_source_have_no_range() {
+        let name = |offset| Name::new("ri".into(), offset);
+
+        assert_eq!(range_of("print", name(1)), Some(1..3));
+        assert_eq!(range_of("print", name(2)), None);
+        assert_eq!(range_of("print", name(4)), None);
+    }
+}
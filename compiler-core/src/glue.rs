@@ -0,0 +1,429 @@
+use crate::analyser::Analysis;
+use crate::ast::*;
+use crate::code_gen::CodeGenError;
+use crate::types::Type;
+use crate::CompileError;
+use std::fmt::{self, Write};
+
+// An ES module that loads the compiled wasm and wraps its exports in functions taking and
+// returning JS values, along with TypeScript declarations for it
+#[derive(Debug)]
+pub struct JsGlue {
+    pub js: String,
+    pub declarations: String,
+}
+
+// `wasm_file` is where the binary module is, relative to the JS file.
+// Strings only live in static memory, so they can be returned to JS but not passed in
+pub fn js_glue<'a>(
+    ast: &Ast<'a>,
+    analysis: &Analysis<'a>,
+    wasm_file: &str,
+) -> Result<JsGlue, CompileError<'a>> {
+    let mut exports = Vec::new();
+
+    for statement in ast.statements {
+        if let TopLevelStatement::Declaration {
+            decl: Declaration::FunctionDecl { name, .. },
+            exported: true,
+        } = statement
+        {
+            let function = analysis
+                .functions
+                .get(&name.symbol)
+                .ok_or(CodeGenError::MissingAnalysis)?;
+
+            if function.params.iter().any(|&(_, t)| t == Type::Str) {
+                return Err(CodeGenError::StringsNotSupportedYet.into());
+            }
+
+            exports.push(Export {
+                name: name.as_str(),
+                js_name: js_identifier(name.as_str()),
+                params: function
+                    .params
+                    .iter()
+                    .map(|&(param, t)| (js_identifier(param.as_str()), t))
+                    .collect(),
+                return_type: function.return_type,
+            });
+        }
+    }
+
+    let mut glue = JsGlue {
+        js: String::new(),
+        declarations: String::new(),
+    };
+
+    write_js(&mut glue.js, &exports, wasm_file)?;
+    write_declarations(&mut glue.declarations, &exports)?;
+
+    Ok(glue)
+}
+
+struct Export<'a> {
+    name: &'a str,
+    js_name: String,
+    params: Vec<(String, Type)>,
+    return_type: Type,
+}
+
+const LOADER: &str = r#"// Generated by lang
+
+async function loadWasm(url) {
+  if (typeof process !== "undefined" && process.versions && process.versions.node) {
+    const { readFile } = await import("fs/promises")
+    const { fileURLToPath } = await import("url")
+
+    return readFile(fileURLToPath(url))
+  }
+
+  const response = await fetch(url)
+
+  return response.arrayBuffer()
+}
+"#;
+
+fn write_js(w: &mut String, exports: &[Export], wasm_file: &str) -> fmt::Result {
+    writeln!(w, "{}", LOADER)?;
+
+    let mut checks = vec![];
+
+    for export in exports {
+        for &(_, t) in &export.params {
+            if !checks.contains(&t) {
+                checks.push(t);
+            }
+        }
+    }
+
+    for t in checks {
+        write_check(w, t)?;
+    }
+
+    if exports.iter().any(|export| export.return_type == Type::Str) {
+        writeln!(w, "{}", READ_STRING)?;
+    }
+
+    writeln!(
+        w,
+        "const bytes = await loadWasm(new URL({:?}, import.meta.url))",
+        wasm_file
+    )?;
+    writeln!(
+        w,
+        "const {{ instance }} = await WebAssembly.instantiate(bytes)"
+    )?;
+    writeln!(w, "const wasm = instance.exports")?;
+
+    for export in exports {
+        let params: Vec<_> = export
+            .params
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+
+        writeln!(w)?;
+        writeln!(w, "function {}({}) {{", export.js_name, params.join(", "))?;
+
+        let args: Vec<_> = export
+            .params
+            .iter()
+            .map(|(name, t)| format!("{}({:?}, {})", check_name(*t), name, name))
+            .collect();
+
+        let call = format!("wasm.{}({})", export.name, args.join(", "));
+
+        match export.return_type {
+            Type::Unit => writeln!(w, "  {}", call)?,
+            Type::Bool => writeln!(w, "  return {} !== 0", call)?,
+            Type::Str => writeln!(w, "  return readString({})", call)?,
+            _ => writeln!(w, "  return {}", call)?,
+        }
+
+        writeln!(w, "}}")?;
+    }
+
+    writeln!(w)?;
+
+    write_export_list(w, exports, "")
+}
+
+// Strings are the address of an iovec pointing at their UTF-8 bytes
+const READ_STRING: &str = r#"function readString(address) {
+  const view = new DataView(wasm.memory.buffer)
+  const start = view.getUint32(address, true)
+  const length = view.getUint32(address + 4, true)
+
+  return new TextDecoder().decode(new Uint8Array(wasm.memory.buffer, start, length))
+}
+"#;
+
+// Checks and converts an argument, so mistakes are errors instead of silently wrapping
+fn write_check(w: &mut String, t: Type) -> fmt::Result {
+    let (condition, description, conversion) = match t {
+        Type::Int32 => (
+            "Number.isInteger(value) && value >= -2147483648 && value <= 2147483647",
+            "a 32 bit integer",
+            "value",
+        ),
+        Type::Int64 => (
+            r#"typeof value === "bigint" && BigInt.asIntN(64, value) === value"#,
+            "a 64 bit integer as a BigInt",
+            "value",
+        ),
+        Type::Bool => (
+            r#"typeof value === "boolean""#,
+            "a boolean",
+            "value ? 1 : 0",
+        ),
+        Type::Float => (r#"typeof value === "number""#, "a number", "value"),
+        Type::Unit | Type::Str => return Ok(()),
+    };
+
+    writeln!(w, "function {}(name, value) {{", check_name(t))?;
+    writeln!(w, "  if (!({})) {{", condition)?;
+    writeln!(
+        w,
+        "    throw new TypeError(`${{name}} should be {}, but was ${{value}}`)",
+        description
+    )?;
+    writeln!(w, "  }}")?;
+    writeln!(w)?;
+    writeln!(w, "  return {}", conversion)?;
+    writeln!(w, "}}")?;
+    writeln!(w)
+}
+
+fn check_name(t: Type) -> &'static str {
+    match t {
+        Type::Int32 => "toInt32",
+        Type::Int64 => "toInt64",
+        Type::Bool => "toBool",
+        Type::Float => "toFloat",
+        Type::Unit | Type::Str => "",
+    }
+}
+
+fn write_declarations(w: &mut String, exports: &[Export]) -> fmt::Result {
+    writeln!(w, "// Generated by lang")?;
+
+    for export in exports {
+        let params: Vec<_> = export
+            .params
+            .iter()
+            .map(|(name, t)| format!("{}: {}", name, ts_type(*t)))
+            .collect();
+
+        writeln!(
+            w,
+            "declare function {}({}): {};",
+            export.js_name,
+            params.join(", "),
+            ts_type(export.return_type)
+        )?;
+    }
+
+    writeln!(w)?;
+
+    write_export_list(w, exports, ";")
+}
+
+fn write_export_list(w: &mut String, exports: &[Export], terminator: &str) -> fmt::Result {
+    let names: Vec<_> = exports
+        .iter()
+        .map(|export| match export.js_name == export.name {
+            true => export.name.to_string(),
+            false => format!("{} as {}", export.js_name, export.name),
+        })
+        .collect();
+
+    writeln!(w, "export {{ {} }}{}", names.join(", "), terminator)
+}
+
+fn ts_type(t: Type) -> &'static str {
+    match t {
+        Type::Unit => "void",
+        Type::Int32 | Type::Float => "number",
+        Type::Int64 => "bigint",
+        Type::Bool => "boolean",
+        Type::Str => "string",
+    }
+}
+
+// Names that can't be used for functions or parameters in JS, along with the ones the glue
+// itself uses
+const RESERVED: &[&str] = &[
+    "await",
+    "break",
+    "bytes",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "continue",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "enum",
+    "export",
+    "extends",
+    "false",
+    "finally",
+    "for",
+    "function",
+    "if",
+    "implements",
+    "import",
+    "in",
+    "instance",
+    "instanceof",
+    "interface",
+    "let",
+    "loadWasm",
+    "new",
+    "null",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "readString",
+    "return",
+    "static",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "toBool",
+    "toFloat",
+    "toInt32",
+    "toInt64",
+    "true",
+    "try",
+    "typeof",
+    "var",
+    "void",
+    "wasm",
+    "while",
+    "with",
+    "yield",
+];
+
+fn js_identifier(name: &str) -> String {
+    if RESERVED.contains(&name) {
+        format!("{}_", name)
+    } else {
+        name.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::ParseSession;
+    use crate::wasm::binary::encode_module;
+    use crate::{compile_module, front_end, OptimisationLevel};
+    use insta::assert_snapshot;
+    use std::fs;
+    use std::process::Command;
+
+    const PROGRAM: &str = r#"export fn add(x, y)
+    x + y
+
+export fn double_big(n: Int64)
+    n * 2i64
+
+export fn is_zero(n)
+    n == 0
+
+export fn negate(b: Bool)
+    b == false
+
+export fn identity(x: Float)
+    x
+
+export fn delete(x)
+    y = x
+
+export fn greeting()
+    "héllo"
+"#;
+
+    fn glue() -> JsGlue {
+        let session = ParseSession::new();
+
+        let (ast, analysis) = front_end(&session, PROGRAM, OptimisationLevel::Basic).unwrap();
+
+        js_glue(&ast, &analysis, "out.wasm").unwrap()
+    }
+
+    #[test]
+    fn js() {
+        assert_snapshot!(glue().js);
+    }
+
+    #[test]
+    fn declarations() {
+        assert_snapshot!(glue().declarations);
+    }
+
+    // Runs the glue for real, if there's a node to run it with
+    #[test]
+    fn runs_under_node() {
+        if Command::new("node").arg("--version").output().is_err() {
+            eprintln!("skipping, as node isn't installed");
+            return;
+        }
+
+        let dir = std::env::temp_dir().join(format!("lang-glue-test-{}", std::process::id()));
+
+        fs::create_dir_all(&dir).unwrap();
+
+        let session = ParseSession::new();
+
+        let wasm = compile_module(&session, PROGRAM, OptimisationLevel::Basic).unwrap();
+
+        fs::write(dir.join("out.wasm"), encode_module(&wasm).unwrap()).unwrap();
+        fs::write(dir.join("out.js"), glue().js).unwrap();
+        fs::write(
+            dir.join("test.mjs"),
+            r#"import * as lang from "./out.js"
+
+const results = [
+  lang.add(2, 3),
+  lang.double_big(3000000000n),
+  lang.is_zero(0),
+  lang.negate(true),
+  lang.identity(1.5),
+  lang.delete(1),
+  lang.greeting(),
+]
+
+try {
+  lang.add(1.5, 2)
+} catch (error) {
+  results.push(error.message)
+}
+
+console.log(results.map(String).join("\n"))
+"#,
+        )
+        .unwrap();
+
+        let output = Command::new("node")
+            .arg(dir.join("test.mjs"))
+            .output()
+            .unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "5\n6000000000\ntrue\nfalse\n1.5\nundefined\nhéllo\nx should be a 32 bit integer, but was 1.5\n",
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use crate::keywords::Keyword;
+use crate::tokeniser::{tokenise, TokeniserError};
+use crate::tokens::{Constant, Token};
+use std::ops::Range;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TokenClass {
+    Keyword,
+    Name,
+    Operator,
+    Punctuation,
+    Constant,
+    String,
+}
+
+impl TokenClass {
+    // for CSS classes and the like
+    pub fn name(self) -> &'static str {
+        match self {
+            TokenClass::Keyword => "keyword",
+            TokenClass::Name => "name",
+            TokenClass::Operator => "operator",
+            TokenClass::Punctuation => "punctuation",
+            TokenClass::Constant => "constant",
+            TokenClass::String => "string",
+        }
+    }
+}
+
+// Classifies the source with the tokeniser the compiler uses, so it's coloured exactly as it's
+// read. Whitespace, comments and indentation changes aren't classified, and an unterminated
+// string is still shown as a string, up to the end of the source
+pub fn highlight(source: &str) -> Vec<(Range<usize>, TokenClass)> {
+    tokenise(source)
+        .spanned()
+        .filter_map(|(span, token)| {
+            let class = match token {
+                Ok(token) => class_of(&token)?,
+                Err(TokeniserError::UnterminatedString) => TokenClass::String,
+                Err(TokeniserError::InvalidNumber) => TokenClass::Constant,
+                Err(TokeniserError::UnexpectedChar(_)) => return None,
+            };
+
+            Some((span, class))
+        })
+        .collect()
+}
+
+fn class_of(token: &Token) -> Option<TokenClass> {
+    let class = match token {
+        Token::IndentIncr | Token::IndentDecr => return None,
+        // `true` and `false` are values, even though they're read as keywords
+        Token::Keyword(Keyword::True) | Token::Keyword(Keyword::False) => TokenClass::Constant,
+        Token::Keyword(_) => TokenClass::Keyword,
+        Token::Name(_) => TokenClass::Name,
+        Token::BinOp(_) | Token::Equals | Token::FatRightArrow | Token::Pipe => {
+            TokenClass::Operator
+        }
+        Token::OpenParen | Token::CloseParen | Token::Comma | Token::Colon => {
+            TokenClass::Punctuation
+        }
+        Token::Constant(Constant::Str(_)) => TokenClass::String,
+        Token::Constant(_) => TokenClass::Constant,
+    };
+
+    Some(class)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use insta::assert_debug_snapshot;
+    use std::fs;
+    use test_case::test_case;
+
+    #[test_case("fibonacci")]
+    #[test_case("hello")]
+    fn fixtures(name: &str) {
+        let contents = fs::read_to_string(format!("src/fixtures/{}.lang", name)).unwrap();
+
+        let highlighted: Vec<_> = highlight(&contents)
+            .into_iter()
+            .map(|(span, class)| (&contents[span], class))
+            .collect();
+
+        assert_debug_snapshot!(highlighted);
+    }
+
+    #[test]
+    fn unterminated_strings_run_to_the_end() {
+        let source = "x = \"abc\n  d";
+
+        assert_eq!(
+            highlight(source).last(),
+            Some(&(4..source.len(), TokenClass::String))
+        );
+    }
+}
@@ -0,0 +1,335 @@
+use crate::analyser::argument_types;
+use crate::ast::*;
+use crate::symbol::Symbol;
+use crate::tokens::{Constant, Integer};
+use crate::types::Type;
+use std::collections::HashMap;
+
+// Functions whose body is a single expression with at most this many nodes are worth inlining
+const MAX_INLINE_SIZE: usize = 12;
+
+// Expects an AST that has already passed analysis. Only small functions that don't call
+// anything are inlined, so recursive functions never are, but inlining them can turn their
+// callers into candidates for the next round
+pub fn inline_functions(mut ast: Ast) -> Ast {
+    loop {
+        let candidates = inline_candidates(&ast);
+
+        if candidates.is_empty() {
+            return ast;
+        }
+
+        let mut inliner = Inliner {
+            arena: ast.arena,
+            candidates,
+            changed: false,
+        };
+
+        ast = inliner.ast(ast);
+
+        if !inliner.changed {
+            return ast;
+        }
+    }
+}
+
+struct Candidate<'a> {
+    params: Vec<Symbol<'a>>,
+    param_types: Vec<Type>,
+    body: Expression<'a>,
+}
+
+fn inline_candidates<'a>(ast: &Ast<'a>) -> HashMap<Symbol<'a>, Candidate<'a>> {
+    let mut candidates = HashMap::new();
+
+    for statement in ast.statements {
+        let TopLevelStatement::Declaration { decl, .. } = statement;
+
+        if let Declaration::FunctionDecl {
+            name,
+            arguments,
+            body: [CodeBlockStatement::BareExpression(expr)],
+        } = decl
+        {
+            let mut calls = vec![];
+
+            expr.calls(&mut calls);
+
+            if !calls.is_empty() || size(expr) > MAX_INLINE_SIZE {
+                continue;
+            }
+
+            // the types have already been checked, so are always there
+            if let Ok(params) = argument_types(arguments) {
+                candidates.insert(
+                    name.symbol,
+                    Candidate {
+                        params: params.iter().map(|(param, _)| param.symbol).collect(),
+                        param_types: params.iter().map(|&(_, t)| t).collect(),
+                        body: *expr,
+                    },
+                );
+            }
+        }
+    }
+
+    candidates
+}
+
+fn size(expr: &Expression) -> usize {
+    match expr {
+        Expression::Variable(_) | Expression::Constant(_) => 1,
+        Expression::FunctionCall { args, .. } => 1 + args.iter().map(size).sum::<usize>(),
+        Expression::BinaryOp { left, right, .. } => 1 + size(left) + size(right),
+        Expression::Negation(expr) => 1 + size(expr),
+    }
+}
+
+struct Inliner<'a> {
+    arena: &'a Arena,
+    candidates: HashMap<Symbol<'a>, Candidate<'a>>,
+    changed: bool,
+}
+
+impl<'a> Inliner<'a> {
+    fn ast(&mut self, ast: Ast<'a>) -> Ast<'a> {
+        let arena = self.arena;
+
+        let statements = ast.statements.iter().map(|&statement| {
+            let TopLevelStatement::Declaration { decl, exported } = statement;
+
+            TopLevelStatement::Declaration {
+                decl: self.declaration(decl),
+                exported,
+            }
+        });
+
+        Ast::new(arena.alloc_slice(statements), arena)
+    }
+
+    fn declaration(&mut self, decl: Declaration<'a>) -> Declaration<'a> {
+        match decl {
+            Declaration::Assignment { name, expr } => Declaration::Assignment {
+                name,
+                expr: self.expression(expr),
+            },
+            Declaration::FunctionDecl {
+                name,
+                arguments,
+                body,
+            } => Declaration::FunctionDecl {
+                name,
+                arguments,
+                body: self.block(body),
+            },
+        }
+    }
+
+    fn block(&mut self, block: CodeBlock<'a>) -> CodeBlock<'a> {
+        let arena = self.arena;
+
+        arena.alloc_slice(block.iter().map(|&statement| self.statement(statement)))
+    }
+
+    fn statement(&mut self, statement: CodeBlockStatement<'a>) -> CodeBlockStatement<'a> {
+        match statement {
+            CodeBlockStatement::Declaration(decl) => {
+                CodeBlockStatement::Declaration(self.declaration(decl))
+            }
+            CodeBlockStatement::BareExpression(expr) => {
+                CodeBlockStatement::BareExpression(self.expression(expr))
+            }
+            CodeBlockStatement::IfStatement { cases, else_case } => {
+                let arena = self.arena;
+
+                CodeBlockStatement::IfStatement {
+                    cases: arena.alloc_slice(cases.iter().map(
+                        |&IfStatementCase { condition, block }| IfStatementCase {
+                            condition: self.expression(condition),
+                            block: self.block(block),
+                        },
+                    )),
+                    else_case: else_case.map(|block| self.block(block)),
+                }
+            }
+        }
+    }
+
+    fn expression(&mut self, expr: Expression<'a>) -> Expression<'a> {
+        use Expression::*;
+
+        match expr {
+            FunctionCall { name, args } => {
+                let arena = self.arena;
+
+                let args = arena.alloc_slice(args.iter().map(|&arg| self.expression(arg)));
+
+                match self.candidates.get(&name.symbol) {
+                    Some(candidate) if can_substitute(candidate, args) => {
+                        self.changed = true;
+
+                        let args = arena.alloc_slice(
+                            args.iter()
+                                .zip(&candidate.param_types)
+                                .map(|(&arg, &t)| with_integer_type(arg, t == Type::Int64, arena)),
+                        );
+
+                        substitute(&candidate.body, &candidate.params, args, self.arena)
+                    }
+                    _ => FunctionCall { name, args },
+                }
+            }
+            BinaryOp {
+                left,
+                operator,
+                right,
+            } => BinaryOp {
+                left: self.arena.alloc(self.expression(*left)),
+                operator,
+                right: self.arena.alloc(self.expression(*right)),
+            },
+            Negation(expr) => Negation(self.arena.alloc(self.expression(*expr))),
+            expr => expr,
+        }
+    }
+}
+
+// Arguments are evaluated once each, in order, so substituting them is only safe if they're
+// trivial, or if the body uses every parameter exactly once in the order they're declared
+fn can_substitute(candidate: &Candidate, args: &[Expression]) -> bool {
+    let trivial = args
+        .iter()
+        .all(|arg| matches!(arg, Expression::Variable(_) | Expression::Constant(_)));
+
+    if trivial {
+        return true;
+    }
+
+    let mut uses = vec![];
+
+    parameter_uses(&candidate.body, &candidate.params, &mut uses);
+
+    uses == candidate.params
+}
+
+fn parameter_uses<'a>(expr: &Expression<'a>, params: &[Symbol<'a>], out: &mut Vec<Symbol<'a>>) {
+    match expr {
+        Expression::Variable(name) if params.contains(&name.symbol) => out.push(name.symbol),
+        Expression::Variable(_) | Expression::Constant(_) => {}
+        Expression::FunctionCall { args, .. } => {
+            for arg in *args {
+                parameter_uses(arg, params, out);
+            }
+        }
+        Expression::BinaryOp { left, right, .. } => {
+            parameter_uses(left, params, out);
+            parameter_uses(right, params, out);
+        }
+        Expression::Negation(expr) => parameter_uses(expr, params, out),
+    }
+}
+
+// Integers without a suffix get their type from where they're used, which is somewhere else
+// once they're substituted, so they're given the type of the param they were passed as
+fn with_integer_type<'a>(expr: Expression<'a>, is_int64: bool, arena: &'a Arena) -> Expression<'a> {
+    if !expr.is_untyped_integer() {
+        return expr;
+    }
+
+    match expr {
+        Expression::Constant(Constant::Int(int)) if is_int64 => {
+            Expression::Constant(Constant::Int64(int))
+        }
+        Expression::Constant(Constant::Int(int)) => Expression::Constant(Constant::Int(Integer {
+            has_suffix: true,
+            ..int
+        })),
+        Expression::BinaryOp {
+            left,
+            operator,
+            right,
+        } => Expression::BinaryOp {
+            left: arena.alloc(with_integer_type(*left, is_int64, arena)),
+            operator,
+            right: arena.alloc(with_integer_type(*right, is_int64, arena)),
+        },
+        Expression::Negation(expr) => {
+            Expression::Negation(arena.alloc(with_integer_type(*expr, is_int64, arena)))
+        }
+        expr => expr,
+    }
+}
+
+fn substitute<'a>(
+    body: &Expression<'a>,
+    params: &[Symbol<'a>],
+    args: &[Expression<'a>],
+    arena: &'a Arena,
+) -> Expression<'a> {
+    use Expression::*;
+
+    match *body {
+        Variable(name) => match params.iter().position(|&param| param == name.symbol) {
+            Some(i) => args[i],
+            None => Variable(name),
+        },
+        Constant(c) => Constant(c),
+        FunctionCall {
+            name,
+            args: call_args,
+        } => FunctionCall {
+            name,
+            args: arena.alloc_slice(
+                call_args
+                    .iter()
+                    .map(|arg| substitute(arg, params, args, arena)),
+            ),
+        },
+        BinaryOp {
+            left,
+            operator,
+            right,
+        } => BinaryOp {
+            left: arena.alloc(substitute(left, params, args, arena)),
+            operator,
+            right: arena.alloc(substitute(right, params, args, arena)),
+        },
+        Negation(expr) => Negation(arena.alloc(substitute(expr, params, args, arena))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyser::analyse;
+    use crate::parser::ParseSession;
+    use insta::assert_debug_snapshot;
+    use std::fs;
+    use test_case::test_case;
+
+    fn inline<'a>(session: &'a ParseSession, source: &'a str) -> Ast<'a> {
+        let ast = session.parse(source).unwrap();
+
+        analyse(&ast).unwrap();
+
+        inline_functions(ast)
+    }
+
+    #[test_case("src/fixtures/example_program.lang"; "example program")]
+    #[test_case("src/fixtures/inlining.lang"; "inlining")]
+    fn fixtures(fixture_file_name: &str) -> std::io::Result<()> {
+        let contents = fs::read_to_string(fixture_file_name)?;
+
+        assert_debug_snapshot!(inline(&ParseSession::new(), &contents));
+
+        Ok(())
+    }
+
+    #[test_case("fn f(n)\n    f(n - 1)\n\nfn g(x)\n    f(x)\n"; "recursive functions")]
+    #[test_case("fn sub(x, y)\n    y - x\n\nfn g(x)\n    sub(g(x), g(x))\n"; "arguments out of order")]
+    #[test_case("fn sq(x)\n    x * x\n\nfn g(x)\n    sq(g(x)) + sq(x)\n"; "argument used twice")]
+    #[test_case("fn widen(x: Int64)\n    x * 2\n\nfn g()\n    widen(3)\n"; "integers passed as int64")]
+    fn expressions(source: &str) {
+        assert_debug_snapshot!(inline(&ParseSession::new(), source));
+    }
+}
@@ -8,6 +8,8 @@ pub enum Keyword {
     Function,
     If,
     Else,
+    True,
+    False,
 }
 
 pub fn get_matching_keyword(name: &str) -> Option<Keyword> {
@@ -20,6 +22,8 @@ pub fn get_matching_keyword(name: &str) -> Option<Keyword> {
         "fn" => Function,
         "if" => If,
         "else" => Else,
+        "true" => True,
+        "false" => False,
         _ => return None,
     };
 
@@ -35,12 +39,16 @@ impl From<Keyword> for &'static str {
             Keyword::Function => "fn",
             Keyword::If => "if",
             Keyword::Else => "else",
+            Keyword::True => "true",
+            Keyword::False => "false",
         }
     }
 }
 
 impl fmt::Display for Keyword {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self)
+        let keyword: &'static str = (*self).into();
+
+        write!(f, "{}", keyword)
     }
 }
@@ -1,47 +1,243 @@
+use self::diagnostics::Diagnostic;
 use self::wasm::*;
+use std::fmt;
 
 pub mod analyser;
 pub mod ast;
 pub mod binding_power;
+pub mod builtins;
+pub mod cache;
 pub mod code_gen;
+pub mod compiler;
+pub mod constant_folding;
+pub mod cst;
+pub mod dead_code;
+pub mod diagnostics;
+pub mod glue;
+pub mod highlight;
+pub mod inlining;
 pub mod keywords;
 pub mod operators;
 pub mod parser;
+pub mod scope;
+pub mod symbol;
 pub mod tokeniser;
 pub mod tokens;
+pub mod types;
 pub mod wasm;
 
-pub fn compile(source: &str) -> Result<String, CompileError> {
-    let mut out = String::new();
+// Errors borrow the names in them from `session`, which can be dropped along with them once
+// they've been reported
+pub fn compile<'a>(
+    session: &'a parser::ParseSession,
+    source: &'a str,
+) -> Result<String, CompileError<'a>> {
+    compile_with(session, source, OptimisationLevel::default())
+}
 
-    let ast = self::parser::parse(&source)?;
+pub fn compile_with<'a>(
+    session: &'a parser::ParseSession,
+    source: &'a str,
+    optimisation: OptimisationLevel,
+) -> Result<String, CompileError<'a>> {
+    let mut out = String::new();
 
-    let wasm = self::code_gen::ast_to_wasm(&ast)?;
+    let wasm = compile_module(session, source, optimisation)?;
 
     wasm.write_text(&mut out, WasmIndentation::default())?;
 
     Ok(out)
 }
 
+pub fn compile_module<'a>(
+    session: &'a parser::ParseSession,
+    source: &'a str,
+    optimisation: OptimisationLevel,
+) -> Result<WasmModule<'a>, CompileError<'a>> {
+    let compiler = compiler_for(optimisation);
+
+    let (ast, analysis) = compiler.front_end(session, source)?;
+
+    compiler.back_end(&ast, &analysis)
+}
+
+// Parses and checks the program, then runs the AST passes for the optimisation level
+pub(crate) fn front_end<'a>(
+    session: &'a parser::ParseSession,
+    source: &'a str,
+    optimisation: OptimisationLevel,
+) -> Result<(ast::Ast<'a>, analyser::Analysis<'a>), CompileError<'a>> {
+    compiler_for(optimisation).front_end(session, source)
+}
+
+fn compiler_for(optimisation: OptimisationLevel) -> compiler::Compiler {
+    compiler::Compiler::new(compiler::CompileOptions {
+        optimisation,
+        ..Default::default()
+    })
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptimisationLevel {
+    None,
+    #[default]
+    Basic,
+}
+
+impl std::str::FromStr for OptimisationLevel {
+    type Err = String;
+
+    fn from_str(level: &str) -> Result<Self, Self::Err> {
+        match level {
+            "0" => Ok(OptimisationLevel::None),
+            "1" => Ok(OptimisationLevel::Basic),
+            _ => Err(format!("Unknown optimisation level: {}", level)),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum CompileError<'a> {
     ParseError(parser::ParseError<'a>),
+    AnalyserError(analyser::AnalyserError<'a>),
+    ConstantFoldingError(constant_folding::ConstantFoldingError<'a>),
     CodeGenError(code_gen::CodeGenError),
+    // generated an invalid module, which is a bug in the compiler
+    ValidationError(wasm::validate::ValidationError<'a>),
     FmtError(std::fmt::Error),
 }
 
+impl<'a> CompileError<'a> {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            CompileError::ParseError(_) => ErrorKind::Parse,
+            CompileError::AnalyserError(_) => ErrorKind::Analysis,
+            CompileError::ConstantFoldingError(_) => ErrorKind::ConstantFolding,
+            CompileError::CodeGenError(_) => ErrorKind::CodeGen,
+            CompileError::ValidationError(_) => ErrorKind::Validation,
+            CompileError::FmtError(_) => ErrorKind::Fmt,
+        }
+    }
+}
+
+impl<'a> fmt::Display for CompileError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::ParseError(error) => write!(f, "{}", error),
+            CompileError::AnalyserError(error) => write!(f, "{}", error),
+            CompileError::ConstantFoldingError(error) => write!(f, "{}", error),
+            CompileError::CodeGenError(error) => write!(f, "{}", error),
+            CompileError::ValidationError(error) => {
+                write!(f, "the compiler generated an invalid module: {}", error)
+            }
+            CompileError::FmtError(_) => write!(f, "couldn't write out the module"),
+        }
+    }
+}
+
+impl<'a> std::error::Error for CompileError<'a> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CompileError::CodeGenError(error) => Some(error),
+            CompileError::FmtError(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+// Which stage of compiling went wrong
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    Parse,
+    Analysis,
+    ConstantFolding,
+    CodeGen,
+    Validation,
+    Fmt,
+}
+
+// A `CompileError` that doesn't borrow from the source, so it can outlive it, or be returned
+// through `anyhow` or `Box<dyn Error>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnedCompileError {
+    pub kind: ErrorKind,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl OwnedCompileError {
+    // With the source, the diagnostics can say where the errors are
+    pub fn new(source: &str, error: &CompileError) -> Self {
+        OwnedCompileError {
+            kind: error.kind(),
+            diagnostics: diagnostics::diagnostics(source, error),
+        }
+    }
+}
+
+impl<'a> From<CompileError<'a>> for OwnedCompileError {
+    fn from(error: CompileError<'a>) -> Self {
+        OwnedCompileError {
+            kind: error.kind(),
+            diagnostics: vec![Diagnostic {
+                severity: diagnostics::Severity::Error,
+                message: error.to_string(),
+                location: None,
+            }],
+        }
+    }
+}
+
+// One line for each error, starting with where it is when that's known
+impl fmt::Display for OwnedCompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, diagnostic) in self.diagnostics.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+
+            if let Some(location) = &diagnostic.location {
+                write!(f, "{}:{}: ", location.start.line, location.start.column)?;
+            }
+
+            write!(f, "{}", diagnostic.message)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for OwnedCompileError {}
+
 impl<'a> From<parser::ParseError<'a>> for CompileError<'a> {
     fn from(error: parser::ParseError<'a>) -> Self {
         CompileError::ParseError(error)
     }
 }
 
+impl<'a> From<analyser::AnalyserError<'a>> for CompileError<'a> {
+    fn from(error: analyser::AnalyserError<'a>) -> Self {
+        CompileError::AnalyserError(error)
+    }
+}
+
+impl<'a> From<constant_folding::ConstantFoldingError<'a>> for CompileError<'a> {
+    fn from(error: constant_folding::ConstantFoldingError<'a>) -> Self {
+        CompileError::ConstantFoldingError(error)
+    }
+}
+
 impl<'a> From<code_gen::CodeGenError> for CompileError<'a> {
     fn from(error: code_gen::CodeGenError) -> Self {
         CompileError::CodeGenError(error)
     }
 }
 
+impl<'a> From<wasm::validate::ValidationError<'a>> for CompileError<'a> {
+    fn from(error: wasm::validate::ValidationError<'a>) -> Self {
+        CompileError::ValidationError(error)
+    }
+}
+
 impl<'a> From<std::fmt::Error> for CompileError<'a> {
     fn from(error: std::fmt::Error) -> Self {
         CompileError::FmtError(error)
@@ -50,39 +246,232 @@ impl<'a> From<std::fmt::Error> for CompileError<'a> {
 
 #[cfg(test)]
 mod tests {
+    use super::compiler::{CompileOptions, Compiler, Target};
+    use super::parser::ParseSession;
+    use super::wasm::binary::encode_module;
+    use super::wasm::interpreter::{self, Interpreter, Value};
+    use super::wasm::WasmModule;
     use super::*;
+    use std::cell::RefCell;
     use std::fs;
+    use std::rc::Rc;
     use test_case::test_case;
     use wasmtime::*;
 
+    const HELLO_OUTPUT: &str = "Hello, world!\nHello, WASI!\nStill counting properly\n";
+
+    fn compile_wasi<'a>(
+        session: &'a ParseSession,
+        source: &'a str,
+        optimisation: OptimisationLevel,
+    ) -> WasmModule<'a> {
+        let compiler = Compiler::new(CompileOptions {
+            target: Target::Wasi,
+            optimisation,
+            ..Default::default()
+        });
+
+        let (ast, analysis) = compiler.front_end(session, source).unwrap();
+
+        compiler.back_end(&ast, &analysis).unwrap()
+    }
+
     #[test_case("fibonacci", 0, 0)]
     #[test_case("fibonacci", 1, 1)]
     #[test_case("fibonacci", 10, 55)]
     #[test_case("fibonacci", 12, 144)]
-    fn program<Args>(name: &str, args: Args, expected: i32)
-    where
-        Args: WasmParams,
-    {
-        let code = fs::read_to_string(&format!("src/fixtures/{}.lang", name)).unwrap();
+    #[test_case("unit_functions", 0, 1)]
+    #[test_case("unit_functions", 3, 7)]
+    #[test_case("booleans", 0, 1)]
+    #[test_case("booleans", 4, 40)]
+    #[test_case("int64", 7, 7)]
+    #[test_case("integers", 5, 5)]
+    #[test_case("constants", 5, 20)]
+    #[test_case("constants", 1, 1)]
+    #[test_case("inlining", 0, 9)]
+    #[test_case("inlining", 4, 25)]
+    #[test_case("tail_calls", 5, 5)]
+    #[test_case("tail_calls", 1_000_000, 1_000_000)]
+    #[test_case("comments", 7, 7)]
+    #[test_case("branches", 0, 11)]
+    #[test_case("branches", 4, 9)]
+    fn program(name: &str, arg: i32, expected: i32) {
+        let code = fs::read_to_string(format!("src/fixtures/{}.lang", name)).unwrap();
+
+        for &level in &[OptimisationLevel::None, OptimisationLevel::Basic] {
+            let session = ParseSession::new();
+
+            let wasm = compile_module(&session, &code, level).unwrap();
+
+            let result = Interpreter::new(&wasm).invoke("main", &[Value::I32(arg)]);
+
+            assert_eq!(
+                result,
+                Ok(Some(Value::I32(expected))),
+                "at optimisation level {:?}",
+                level
+            );
+        }
+    }
+
+    // checks the text output is accepted by a real runtime, and agrees with the interpreter
+    #[test_case("fibonacci", 10, 55)]
+    #[test_case("tail_calls", 1_000_000, 1_000_000)]
+    fn program_in_wasmtime(name: &str, arg: i32, expected: i32) {
+        let code = fs::read_to_string(format!("src/fixtures/{}.lang", name)).unwrap();
+
+        for &level in &[OptimisationLevel::None, OptimisationLevel::Basic] {
+            let session = ParseSession::new();
+
+            let wasm = compile_with(&session, &code, level).unwrap();
+
+            let engine = Engine::default();
+
+            let store = Store::new(&engine);
+
+            let module = Module::new(&engine, &wasm).unwrap();
+
+            let instance = Instance::new(&store, &module, &[]).unwrap();
+
+            let main = instance
+                .get_func("main")
+                .expect("`main` was not an exported function");
+
+            let answer = main.typed::<i32, i32>().unwrap();
+
+            let result = answer.call(arg).unwrap();
+
+            assert_eq!(result, expected, "at optimisation level {:?}", level);
+        }
+    }
+
+    fn compile_owned(source: String) -> Result<String, OwnedCompileError> {
+        let session = ParseSession::new();
+
+        compile(&session, &source).map_err(|error| OwnedCompileError::new(&source, &error))
+    }
+
+    #[test]
+    fn owned_errors_outlive_the_source() {
+        let error = compile_owned("export fn main()\n    x\n".to_string()).unwrap_err();
+
+        assert_eq!(error.kind, ErrorKind::Analysis);
+        assert_eq!(error.to_string(), "2:5: `x` isn't defined");
+    }
+
+    #[test]
+    fn owned_errors_work_with_question_mark() -> Result<(), Box<dyn std::error::Error>> {
+        let session = ParseSession::new();
+
+        let unlocated: OwnedCompileError = compile(&session, "fn f(").unwrap_err().into();
 
-        let wasm = compile(&code).unwrap();
+        assert_eq!(unlocated.to_string(), "couldn't parse function arguments");
+
+        compile_owned("export fn main()\n    1\n".to_string())?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn wasi_program() {
+        let code = fs::read_to_string("src/fixtures/hello.lang").unwrap();
+
+        for &level in &[OptimisationLevel::None, OptimisationLevel::Basic] {
+            let session = ParseSession::new();
+
+            let wasm = compile_wasi(&session, &code, level);
+
+            let interpreter = Interpreter::new(&wasm);
+
+            let result = interpreter.invoke("_start", &[]);
+
+            assert_eq!(
+                result,
+                Err(interpreter::Trap::Exit(0)),
+                "at level {:?}",
+                level
+            );
+            assert_eq!(interpreter.stdout(), HELLO_OUTPUT, "at level {:?}", level);
+        }
+    }
+
+    // provides just the WASI functions the compiler imports, so no WASI crate is needed
+    #[test]
+    fn wasi_program_in_wasmtime() {
+        let code = fs::read_to_string("src/fixtures/hello.lang").unwrap();
+
+        let session = ParseSession::new();
+
+        let binary =
+            encode_module(&compile_wasi(&session, &code, OptimisationLevel::Basic)).unwrap();
 
         let engine = Engine::default();
 
         let store = Store::new(&engine);
 
-        let module = Module::new(&engine, &wasm).unwrap();
+        let module = Module::new(&engine, &binary).unwrap();
+
+        let stdout = Rc::new(RefCell::new(Vec::new()));
+
+        let written = stdout.clone();
+
+        let mut linker = Linker::new(&store);
+
+        linker
+            .func(
+                "wasi_snapshot_preview1",
+                "fd_write",
+                move |caller: Caller<'_>, fd: i32, iovs: i32, iovs_len: i32, nwritten: i32| {
+                    assert_eq!(fd, 1, "only writes to stdout");
+
+                    let memory = caller
+                        .get_export("memory")
+                        .and_then(Extern::into_memory)
+                        .unwrap();
+
+                    let read_u32 = |address: i32| {
+                        let mut bytes = [0; 4];
+
+                        memory.read(address as usize, &mut bytes).unwrap();
+
+                        u32::from_le_bytes(bytes)
+                    };
+
+                    let mut total = 0;
+
+                    for iov in (0..iovs_len).map(|i| iovs + i * 8) {
+                        let mut bytes = vec![0; read_u32(iov + 4) as usize];
+
+                        memory.read(read_u32(iov) as usize, &mut bytes).unwrap();
+
+                        total += bytes.len() as u32;
+                        written.borrow_mut().extend(bytes);
+                    }
+
+                    memory
+                        .write(nwritten as usize, &total.to_le_bytes())
+                        .unwrap();
+
+                    0
+                },
+            )
+            .unwrap();
 
-        let instance = Instance::new(&store, &module, &[]).unwrap();
+        linker
+            .func(
+                "wasi_snapshot_preview1",
+                "proc_exit",
+                |code: i32| -> Result<(), Trap> { Err(Trap::i32_exit(code)) },
+            )
+            .unwrap();
 
-        let main = instance
-            .get_func("main")
-            .expect("`main` was not an exported function");
+        let instance = linker.instantiate(&module).unwrap();
 
-        let answer = main.typed::<Args, i32>().unwrap();
+        let start = instance.get_typed_func::<(), ()>("_start").unwrap();
 
-        let result = answer.call(args).unwrap();
+        let trap = start.call(()).unwrap_err();
 
-        assert_eq!(result, expected);
+        assert_eq!(trap.i32_exit_status(), Some(0));
+        assert_eq!(String::from_utf8(stdout.take()).unwrap(), HELLO_OUTPUT);
     }
 }
@@ -2,57 +2,74 @@ use crate::ast::*;
 use crate::binding_power::*;
 use crate::keywords::*;
 use crate::operators::*;
+use crate::symbol::Name;
 use crate::tokeniser::{self, *};
 use crate::tokens::*;
+use std::fmt;
 use std::iter::Peekable;
 
 pub type Result<'a, X> = std::result::Result<X, ParseError<'a>>;
 
-pub fn parse(source: &str) -> Result<Ast> {
-    Parser::of(tokenise(source)).parse()
+// Owns the arena the `Ast`s it parses are allocated in, so they can't outlive it. Errors only
+// borrow the source, so they can
+#[derive(Debug, Default)]
+pub struct ParseSession {
+    arena: Arena,
 }
 
-pub fn parse_iter(source: &str) -> Parser {
-    Parser::of(tokenise(source))
+impl ParseSession {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse<'s: 'a, 'a>(&'a self, source: &'s str) -> Result<'s, Ast<'a>> {
+        self.parse_iter(source).parse()
+    }
+
+    pub fn parse_iter<'s: 'a, 'a>(&'a self, source: &'s str) -> Parser<'s, 'a> {
+        Parser {
+            tokens: tokenise(source).peekable(),
+            arena: &self.arena,
+        }
+    }
+
+    pub fn arena(&self) -> &Arena {
+        &self.arena
+    }
 }
 
 #[derive(Debug)]
-pub struct Parser<'a> {
-    tokens: Peekable<Tokeniser<'a>>,
+pub struct Parser<'s, 'a> {
+    tokens: Peekable<Tokeniser<'s>>,
+    arena: &'a Arena,
 }
 
-impl<'a> Iterator for Parser<'a> {
-    type Item = Result<'a, TopLevelStatement<'a>>;
+impl<'s: 'a, 'a> Iterator for Parser<'s, 'a> {
+    type Item = Result<'s, TopLevelStatement<'a>>;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.top_level_statement(false).transpose()
     }
 }
 
-impl<'a> Parser<'a> {
-    fn of(tokens: Tokeniser<'a>) -> Parser<'a> {
-        Parser {
-            tokens: tokens.peekable(),
-        }
-    }
-
-    fn step(&mut self) -> tokeniser::Result<Option<Token<'a>>> {
+impl<'s: 'a, 'a> Parser<'s, 'a> {
+    fn step(&mut self) -> tokeniser::Result<Option<Token<'s>>> {
         self.tokens.next().transpose()
     }
 
-    fn peek_next_token(&mut self) -> tokeniser::Result<Option<Token<'a>>> {
+    fn peek_next_token(&mut self) -> tokeniser::Result<Option<Token<'s>>> {
         self.tokens.peek().copied().transpose()
     }
 
-    fn parse(&mut self) -> Result<'a, Ast<'a>> {
-        let mut ast = Ast::default();
+    fn parse(&mut self) -> Result<'s, Ast<'a>> {
+        let mut statements = self.arena.vec();
 
         loop {
             let statement = self.top_level_statement(false)?;
 
             match statement {
-                Some(s) => ast.append_statement(s),
-                None => return Ok(ast),
+                Some(s) => statements.push(s),
+                None => return Ok(Ast::new(statements.into_bump_slice(), self.arena)),
             }
         }
     }
@@ -60,11 +77,11 @@ impl<'a> Parser<'a> {
     fn top_level_statement(
         &mut self,
         is_export: bool,
-    ) -> Result<'a, Option<TopLevelStatement<'a>>> {
+    ) -> Result<'s, Option<TopLevelStatement<'a>>> {
         if let Some(token) = self.step()? {
             match token {
                 Token::Name(name) => Ok(Some(TopLevelStatement::Declaration {
-                    decl: self.declaration(name)?,
+                    decl: self.declaration(self.name(name))?,
                     exported: is_export,
                 })),
                 Token::Keyword(Keyword::Function) => Ok(Some(TopLevelStatement::Declaration {
@@ -78,7 +95,6 @@ impl<'a> Parser<'a> {
                         self.top_level_statement(true)
                     }
                 }
-                Token::Keyword(Keyword::If) => todo!(),
                 token => Err(ParseError::UnexpectedToken(token, "top level statement")),
             }
         } else {
@@ -86,7 +102,7 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn func_body_statement(&mut self) -> Result<'a, CodeBlockStatement<'a>> {
+    fn func_body_statement(&mut self) -> Result<'s, CodeBlockStatement<'a>> {
         if let Some(token) = self.step()? {
             match token {
                 Token::Name(name) => self.named_statement(name),
@@ -94,9 +110,9 @@ impl<'a> Parser<'a> {
                     self.function().map(CodeBlockStatement::Declaration)
                 }
                 Token::Keyword(Keyword::If) => self.if_statement(),
-                Token::Constant(_) => Ok(CodeBlockStatement::BareExpression(
-                    self.expression(None, Some(token))?,
-                )),
+                Token::Constant(_) | Token::Keyword(Keyword::True | Keyword::False) => Ok(
+                    CodeBlockStatement::BareExpression(self.expression(None, Some(token))?),
+                ),
                 Token::OpenParen => Ok(CodeBlockStatement::BareExpression(
                     self.expression(None, Some(token))?,
                 )),
@@ -107,7 +123,7 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn declaration(&mut self, name: &'a str) -> Result<'a, Declaration<'a>> {
+    fn declaration(&mut self, name: Name<'a>) -> Result<'s, Declaration<'a>> {
         match self.step()? {
             Some(Token::Equals) => Ok(Declaration::Assignment {
                 name,
@@ -118,7 +134,7 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn named_statement(&mut self, name: &'a str) -> Result<'a, CodeBlockStatement<'a>> {
+    fn named_statement(&mut self, name: Identifier<'s>) -> Result<'s, CodeBlockStatement<'a>> {
         use self::Declaration::*;
         use CodeBlockStatement::*;
 
@@ -126,7 +142,7 @@ impl<'a> Parser<'a> {
             self.step()?;
 
             Ok(Declaration(Assignment {
-                name,
+                name: self.name(name),
                 expr: self.expression(None, None)?,
             }))
         } else {
@@ -136,13 +152,16 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn function_call(&mut self, name: &'a str) -> Result<'a, Expression<'a>> {
-        let mut args = vec![];
+    fn function_call(&mut self, name: Name<'a>) -> Result<'s, Expression<'a>> {
+        let mut args = self.arena.vec();
 
         while let Some(token) = self.step()? {
             match token {
                 Token::CloseParen => {
-                    return Ok(Expression::FunctionCall { name, args });
+                    return Ok(Expression::FunctionCall {
+                        name,
+                        args: args.into_bump_slice(),
+                    });
                 }
                 _ => {
                     args.push(self.expression(None, Some(token))?);
@@ -157,14 +176,14 @@ impl<'a> Parser<'a> {
         Err(ParseError::UnexpectedEndOfInput)
     }
 
-    fn function(&mut self) -> Result<'a, Declaration<'a>> {
+    fn function(&mut self) -> Result<'s, Declaration<'a>> {
         match self.step()? {
             Some(Token::Name(name)) => {
                 let arguments = self.function_arguments_list()?;
                 let body = self.function_body()?;
 
                 Ok(Declaration::FunctionDecl {
-                    name,
+                    name: self.name(name),
                     arguments,
                     body,
                 })
@@ -173,15 +192,19 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn function_arguments_list(&mut self) -> Result<'a, FunctionArgsList<'a>> {
+    fn function_arguments_list(&mut self) -> Result<'s, FunctionArgsList<'a>> {
         match self.step()? {
             Some(Token::OpenParen) => {
-                let mut args = Vec::new();
+                let mut args = self.arena.vec();
 
                 while let Some(token) = self.step()? {
                     match token {
                         Token::Name(name) => args.push(self.func_arg(name)?),
-                        Token::CloseParen => return Ok(FunctionArgsList { args }),
+                        Token::CloseParen => {
+                            return Ok(FunctionArgsList {
+                                args: args.into_bump_slice(),
+                            })
+                        }
                         _ => return Err(ParseError::ErrorParsingFunctionArgs),
                     }
                 }
@@ -192,16 +215,35 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn func_arg(&mut self, name: &'a str) -> Result<'a, FunctionArg<'a>> {
+    fn func_arg(&mut self, name: Identifier<'s>) -> Result<'s, FunctionArg<'a>> {
+        let mut type_name = None;
+
+        if let Some(Token::Colon) = self.peek_next_token()? {
+            self.step()?;
+
+            match self.step()? {
+                Some(Token::Name(name)) => type_name = Some(self.name(name)),
+                _ => return Err(ParseError::ErrorParsingFunctionArgs),
+            }
+        }
+
         if let Some(Token::Comma) = self.peek_next_token()? {
             self.step()?;
         }
 
-        Ok(FunctionArg { name })
+        Ok(FunctionArg {
+            name: self.name(name),
+            type_name,
+        })
     }
 
-    fn function_body(&mut self) -> Result<'a, Vec<CodeBlockStatement<'a>>> {
-        let mut statements = Vec::new();
+    // interned in the session, so the same name is the same symbol everywhere in the program
+    fn name(&self, name: Identifier<'s>) -> Name<'a> {
+        Name::new(self.arena.intern(name.text), name.offset as usize)
+    }
+
+    fn function_body(&mut self) -> Result<'s, CodeBlock<'a>> {
+        let mut statements = self.arena.vec();
 
         match self.step()? {
             Some(Token::IndentIncr) => {}
@@ -212,7 +254,7 @@ impl<'a> Parser<'a> {
             match token {
                 Token::IndentDecr => {
                     self.step()?;
-                    return Ok(statements);
+                    return Ok(statements.into_bump_slice());
                 }
                 _ => statements.push(self.func_body_statement()?),
             }
@@ -221,14 +263,16 @@ impl<'a> Parser<'a> {
         Err(ParseError::UnexpectedEndOfInput)
     }
 
-    fn if_statement(&mut self) -> Result<'a, CodeBlockStatement<'a>> {
+    fn if_statement(&mut self) -> Result<'s, CodeBlockStatement<'a>> {
         // if keyword has already been consumed
 
         let condition = self.expression(None, None)?;
 
         let block = self.function_body()?;
 
-        let mut cases = vec![IfStatementCase { condition, block }];
+        let mut cases = self.arena.vec();
+
+        cases.push(IfStatementCase { condition, block });
 
         let mut else_case = None;
 
@@ -247,34 +291,33 @@ impl<'a> Parser<'a> {
                 _ => {
                     let block = self.function_body()?;
 
-                    else_case = Some(Box::new(block));
+                    else_case = Some(block);
 
                     break;
                 }
             }
         }
 
-        Ok(CodeBlockStatement::IfStatement { cases, else_case })
+        Ok(CodeBlockStatement::IfStatement {
+            cases: cases.into_bump_slice(),
+            else_case,
+        })
     }
 
     fn expression(
         &mut self,
         right_binding_power: Option<BindingPower>,
-        first_token: Option<Token<'a>>,
-    ) -> Result<'a, Expression<'a>> {
+        first_token: Option<Token<'s>>,
+    ) -> Result<'s, Expression<'a>> {
         let mut current_token = match first_token {
             Some(t) => t,
-            None => self
-                .step()?
-                .map_or(Err(ParseError::UnexpectedEndOfInput), Ok)?,
+            None => self.step()?.ok_or(ParseError::UnexpectedEndOfInput)?,
         };
 
         let mut left = self.null_denotation(current_token)?;
 
         while right_binding_power.unwrap_or_default() < self.next_token_binding_power() {
-            current_token = self
-                .step()?
-                .map_or(Err(ParseError::UnexpectedEndOfInput), Ok)?;
+            current_token = self.step()?.ok_or(ParseError::UnexpectedEndOfInput)?;
 
             left = self.left_denotation(current_token, left)?;
         }
@@ -291,37 +334,40 @@ impl<'a> Parser<'a> {
 
     fn left_denotation(
         &mut self,
-        token: Token<'a>,
+        token: Token<'s>,
         left: Expression<'a>,
-    ) -> Result<'a, Expression<'a>> {
+    ) -> Result<'s, Expression<'a>> {
         match token {
             Token::BinOp(operator) => {
                 let right = self.expression(Some(token.binding_power()), None)?;
 
                 Ok(Expression::BinaryOp {
                     operator,
-                    left: Box::new(left),
-                    right: Box::new(right),
+                    left: self.arena.alloc(left),
+                    right: self.arena.alloc(right),
                 })
             }
             _ => Ok(left),
         }
     }
 
-    fn null_denotation(&mut self, token: Token<'a>) -> Result<'a, Expression<'a>> {
+    fn null_denotation(&mut self, token: Token<'s>) -> Result<'s, Expression<'a>> {
         match token {
             Token::Constant(c) => Ok(Expression::Constant(c)),
+            Token::Keyword(Keyword::True) => Ok(Expression::Constant(Constant::Bool(true))),
+            Token::Keyword(Keyword::False) => Ok(Expression::Constant(Constant::Bool(false))),
             Token::Name(name) => {
                 if let Some(Token::OpenParen) = self.peek_next_token()? {
                     self.step()?;
-                    self.function_call(name)
+                    self.function_call(self.name(name))
                 } else {
-                    Ok(Expression::Variable(name))
+                    Ok(Expression::Variable(self.name(name)))
                 }
             }
-            Token::BinOp(BinaryOperator::Minus) => Ok(Expression::Negation(Box::new(
-                self.expression(Some(BindingPower::negation()), None)?,
-            ))),
+            Token::BinOp(BinaryOperator::Minus) => Ok(Expression::Negation(
+                self.arena
+                    .alloc(self.expression(Some(BindingPower::negation()), None)?),
+            )),
             Token::OpenParen => {
                 let expr = self.expression(Some(token.binding_power()), None)?;
 
@@ -359,6 +405,31 @@ impl<'a> From<TokeniserError> for ParseError<'a> {
     }
 }
 
+impl<'a> fmt::Display for ParseError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ParseError::*;
+
+        match self {
+            TokeniserError(error) => write!(f, "{}", error),
+            UnexpectedToken(token, expected) => write!(f, "unexpected {:?} in {}", token, expected),
+            UnexpectedEndOfInput => write!(f, "unexpected end of input"),
+            FunctionParseError => write!(f, "couldn't parse function"),
+            ErrorParsingFunctionArgs => write!(f, "couldn't parse function arguments"),
+            IndentExpectedError => write!(f, "expected an indented block"),
+            IfStatementBodyExpected => write!(f, "expected a body for the if statement"),
+        }
+    }
+}
+
+impl<'a> std::error::Error for ParseError<'a> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseError::TokeniserError(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -370,13 +441,43 @@ mod tests {
     #[test_case("src/fixtures/maths.lang"; "maths")]
     #[test_case("src/fixtures/functions.lang"; "functions")]
     #[test_case("src/fixtures/example_program.lang"; "example program")]
+    #[test_case("src/fixtures/booleans.lang"; "booleans")]
+    #[test_case("src/fixtures/int64.lang"; "int64")]
     fn fixtures(fixture_file_name: &str) -> std::io::Result<()> {
         let contents = fs::read_to_string(fixture_file_name)?;
 
-        let ast = parse(&contents);
+        let session = ParseSession::new();
+
+        let ast = session.parse(&contents);
 
         assert_debug_snapshot!(ast);
 
         Ok(())
     }
+
+    #[test]
+    fn nodes_are_allocated_in_the_session() {
+        let session = ParseSession::new();
+
+        let ast = session.parse("fn f(x)\n    x + 1\n").unwrap();
+
+        assert_eq!(ast.statements.len(), 1);
+        assert!(std::ptr::eq(ast.arena, session.arena()));
+        assert!(session.arena().allocated_bytes() > 0);
+    }
+
+    #[test]
+    fn if_at_the_top_level_is_an_error() {
+        let session = ParseSession::new();
+
+        let result = session.parse("if true\n    1\n");
+
+        assert!(matches!(
+            result,
+            Err(ParseError::UnexpectedToken(
+                Token::Keyword(Keyword::If),
+                "top level statement"
+            ))
+        ));
+    }
 }
@@ -0,0 +1,223 @@
+use crate::symbol::Symbol;
+use std::collections::{HashMap, HashSet};
+
+// The variables visible at some point in a function, with the innermost scope last. Each branch
+// of an `if` gets its own scope, so the branches can use the same name for variables of
+// different types. Once the `if` ends, a name is only visible after it if every case and an
+// `else` assigned the same variable to it. Names only some of them assigned might not have a
+// value after the `if`, so are remembered to say so if they're used
+#[derive(Debug)]
+pub struct Scopes<'a, T> {
+    scopes: Vec<Scope<'a, T>>,
+    // for each `if` being looked at, what its branches so far have declared
+    ifs: Vec<Branches<'a, T>>,
+}
+
+#[derive(Debug)]
+struct Scope<'a, T> {
+    variables: HashMap<Symbol<'a>, T>,
+    possibly_unassigned: HashSet<Symbol<'a>>,
+}
+
+#[derive(Debug)]
+struct Branches<'a, T> {
+    count: usize,
+    declared: HashMap<Symbol<'a>, Vec<T>>,
+    possibly_unassigned: HashSet<Symbol<'a>>,
+}
+
+impl<'a, T> Default for Scopes<'a, T> {
+    fn default() -> Self {
+        Scopes {
+            scopes: vec![Scope::default()],
+            ifs: Vec::new(),
+        }
+    }
+}
+
+impl<'a, T> Default for Scope<'a, T> {
+    fn default() -> Self {
+        Scope {
+            variables: HashMap::new(),
+            possibly_unassigned: HashSet::new(),
+        }
+    }
+}
+
+impl<'a, T: Copy + PartialEq> Scopes<'a, T> {
+    pub fn enter_if(&mut self) {
+        self.ifs.push(Branches {
+            count: 0,
+            declared: HashMap::new(),
+            possibly_unassigned: HashSet::new(),
+        });
+    }
+
+    pub fn exit_if(&mut self, has_else: bool) {
+        let branches = match self.ifs.pop() {
+            Some(branches) => branches,
+            None => return,
+        };
+
+        let scope = match self.scopes.last_mut() {
+            Some(scope) => scope,
+            None => return,
+        };
+
+        scope
+            .possibly_unassigned
+            .extend(branches.possibly_unassigned);
+
+        for (name, variables) in branches.declared {
+            if !has_else || variables.len() < branches.count {
+                scope.possibly_unassigned.insert(name);
+            } else if variables.windows(2).all(|pair| pair[0] == pair[1]) {
+                scope.variables.insert(name, variables[0]);
+            }
+        }
+    }
+
+    pub fn enter_branch(&mut self) {
+        self.scopes.push(Scope::default());
+    }
+
+    pub fn exit_branch(&mut self) {
+        let branch = self.scopes.pop().unwrap_or_default();
+
+        if let Some(branches) = self.ifs.last_mut() {
+            branches.count += 1;
+            branches
+                .possibly_unassigned
+                .extend(branch.possibly_unassigned);
+
+            for (name, variable) in branch.variables {
+                branches.declared.entry(name).or_default().push(variable);
+            }
+        }
+    }
+
+    pub fn get(&self, name: Symbol<'a>) -> Option<&T> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.variables.get(&name))
+    }
+
+    pub fn get_mut(&mut self, name: Symbol<'a>) -> Option<&mut T> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.variables.get_mut(&name))
+    }
+
+    // The variables with the name that earlier branches of the innermost `if` declared, which a
+    // branch assigning the name can carry on using, so it's still there after the `if`
+    pub fn from_earlier_branches(&self, name: Symbol<'a>) -> impl Iterator<Item = &T> {
+        self.ifs
+            .last()
+            .and_then(|branches| branches.declared.get(&name))
+            .into_iter()
+            .flatten()
+    }
+
+    // Whether the name isn't visible because only some branches of an earlier `if` assigned it
+    pub fn possibly_unassigned(&self, name: Symbol<'a>) -> bool {
+        self.scopes
+            .iter()
+            .rev()
+            .find(|scope| {
+                scope.variables.contains_key(&name) || scope.possibly_unassigned.contains(&name)
+            })
+            .is_some_and(|scope| !scope.variables.contains_key(&name))
+    }
+
+    // in the innermost scope, hiding any variable with the same name outside it
+    pub fn declare(&mut self, name: Symbol<'a>, value: T) -> Option<T> {
+        self.scopes.last_mut()?.variables.insert(name, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbol::Interner;
+
+    #[test]
+    fn inner_scopes_hide_outer_ones_until_they_end() {
+        let names = Interner::default();
+        let x = names.intern("x");
+        let mut scopes = Scopes::default();
+
+        scopes.declare(x, 1);
+        scopes.enter_if();
+        scopes.enter_branch();
+
+        assert_eq!(scopes.get(x), Some(&1));
+
+        scopes.declare(x, 2);
+
+        assert_eq!(scopes.get(x), Some(&2));
+
+        scopes.exit_branch();
+
+        assert_eq!(scopes.get(x), Some(&1));
+    }
+
+    #[test]
+    fn branches_that_agree_leave_their_variable_behind() {
+        let names = Interner::default();
+        let (x, y) = (names.intern("x"), names.intern("y"));
+        let mut scopes = Scopes::default();
+
+        scopes.enter_if();
+
+        for (x_value, y_value) in [(1, 3), (1, 4)] {
+            scopes.enter_branch();
+
+            assert_eq!(scopes.get(x), None);
+
+            scopes.declare(x, x_value);
+            scopes.declare(y, y_value);
+            scopes.exit_branch();
+        }
+
+        assert_eq!(
+            scopes.from_earlier_branches(x).collect::<Vec<_>>(),
+            [&1, &1]
+        );
+
+        scopes.exit_if(true);
+
+        assert_eq!(scopes.get(x), Some(&1));
+        assert_eq!(scopes.get(y), None);
+    }
+
+    #[test]
+    fn names_not_every_branch_assigned_might_be_unassigned() {
+        let names = Interner::default();
+        let (x, y) = (names.intern("x"), names.intern("y"));
+        let mut scopes = Scopes::default();
+
+        for has_else in [false, true] {
+            scopes.enter_if();
+            scopes.enter_branch();
+            scopes.declare(x, 1);
+            scopes.exit_branch();
+
+            if has_else {
+                scopes.enter_branch();
+                scopes.exit_branch();
+            }
+
+            scopes.exit_if(has_else);
+
+            assert_eq!(scopes.get(x), None);
+            assert!(scopes.possibly_unassigned(x));
+            assert!(!scopes.possibly_unassigned(y));
+        }
+
+        scopes.declare(x, 2);
+
+        assert!(!scopes.possibly_unassigned(x));
+    }
+}
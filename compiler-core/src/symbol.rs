@@ -0,0 +1,190 @@
+use elsa::FrozenIndexSet;
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+use std::ptr;
+
+// An interned name, so comparing and hashing one is as cheap as for a pointer, and reading it
+// doesn't need the interner at all. It borrows from the interner of the session that parsed it,
+// so the names of a program are freed along with its session
+#[derive(Copy, Clone)]
+pub struct Symbol<'a>(&'a str);
+
+// Each name is only stored once, so two symbols are the same name when they point at the same
+// place. Symbols from different interners are never the same, even with the same name
+#[derive(Default)]
+pub struct Interner {
+    names: FrozenIndexSet<Box<str>>,
+}
+
+impl Interner {
+    pub fn intern(&self, name: &str) -> Symbol<'_> {
+        match self.names.get(name) {
+            Some(interned) => Symbol(interned),
+            None => Symbol(self.names.insert(name.into())),
+        }
+    }
+}
+
+impl fmt::Debug for Interner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Interner").finish_non_exhaustive()
+    }
+}
+
+impl<'a> Symbol<'a> {
+    pub fn as_str(self) -> &'a str {
+        self.0
+    }
+}
+
+// For tests that build wasm by hand, with nothing parsed to get the names from. The names are
+// kept for as long as the test's thread is around
+#[cfg(test)]
+impl From<&str> for Symbol<'static> {
+    fn from(name: &str) -> Self {
+        thread_local! {
+            static NAMES: &'static Interner = Box::leak(Box::default());
+        }
+
+        NAMES.with(|names| names.intern(name))
+    }
+}
+
+impl<'a> PartialEq for Symbol<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self.0, other.0)
+    }
+}
+
+impl<'a> Eq for Symbol<'a> {}
+
+impl<'a> Hash for Symbol<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.as_ptr().hash(state)
+    }
+}
+
+// By name rather than by where they're stored, which depends on what else has been interned,
+// so maps keyed by symbols come out the same every time. Only symbols from different interners
+// can have the same name, which are told apart by where they're stored, so that like `==` only
+// the same symbol is `Equal`
+impl<'a> Ord for Symbol<'a> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match self == other {
+            true => Ordering::Equal,
+            false => self
+                .0
+                .cmp(other.0)
+                .then_with(|| self.0.as_ptr().cmp(&other.0.as_ptr())),
+        }
+    }
+}
+
+impl<'a> PartialOrd for Symbol<'a> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a> fmt::Debug for Symbol<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.0, f)
+    }
+}
+
+impl<'a> fmt::Display for Symbol<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self.0, f)
+    }
+}
+
+// A name along with where it's written in the source, so errors about it can point at it.
+// Two names are the same name when their symbols are, wherever they are
+#[derive(Copy, Clone)]
+pub struct Name<'a> {
+    pub symbol: Symbol<'a>,
+    // in bytes
+    pub offset: u32,
+}
+
+impl<'a> Name<'a> {
+    pub fn new(symbol: Symbol<'a>, offset: usize) -> Name<'a> {
+        Name {
+            symbol,
+            offset: offset as u32,
+        }
+    }
+
+    pub fn as_str(self) -> &'a str {
+        self.symbol.as_str()
+    }
+
+    pub fn range(self) -> Range<usize> {
+        let start = self.offset as usize;
+
+        start..start + self.as_str().len()
+    }
+}
+
+// Leaves out the offset, so moving code around doesn't change things like AST snapshots or
+// cache keys
+impl<'a> fmt::Debug for Name<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.symbol, f)
+    }
+}
+
+impl<'a> fmt::Display for Name<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.symbol, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_same_name_is_the_same_symbol() {
+        let names = Interner::default();
+        let name = String::from("interned");
+
+        assert_eq!(names.intern(&name), names.intern("interned"));
+        assert_ne!(names.intern("interned"), names.intern("other"));
+        assert_eq!(names.intern(&name).as_str(), "interned");
+    }
+
+    #[test]
+    fn each_interner_has_its_own_symbols() {
+        let (first, second) = (Interner::default(), Interner::default());
+
+        assert_ne!(first.intern("x"), second.intern("x"));
+        assert_ne!(first.intern("x").cmp(&second.intern("x")), Ordering::Equal);
+    }
+
+    #[test]
+    fn symbols_are_ordered_by_name() {
+        let names = Interner::default();
+
+        let mut symbols = vec![
+            names.intern("zebra"),
+            names.intern("apple"),
+            names.intern("mango"),
+        ];
+
+        symbols.sort();
+
+        assert_eq!(format!("{:?}", symbols), r#"["apple", "mango", "zebra"]"#);
+    }
+
+    #[test]
+    fn names_know_where_they_are() {
+        let names = Interner::default();
+        let name = Name::new(names.intern("x"), 4);
+
+        assert_eq!(name.range(), 4..5);
+        assert_eq!(format!("{:?} {}", name, name), r#""x" x"#);
+    }
+}
@@ -1,18 +1,21 @@
 use crate::keywords::*;
 use crate::tokens::*;
 use std::cmp::Ordering;
+use std::fmt;
 use std::iter::Peekable;
+use std::ops::Range;
 use std::str::CharIndices;
 use tinyvec::TinyVec;
 
 pub type Result<X> = std::result::Result<X, TokeniserError>;
 
-pub fn tokenise(source: &str) -> Tokeniser {
+pub fn tokenise(source: &str) -> Tokeniser<'_> {
     Tokeniser {
         source,
         chars: source.char_indices().peekable(),
         indent_stack: TinyVec::new(),
         current_line_indent: 0,
+        token_start: 0,
     }
 }
 
@@ -24,6 +27,8 @@ pub struct Tokeniser<'a> {
     chars: Peekable<CharIndices<'a>>,
     indent_stack: TinyVec<[Indent; 14]>,
     current_line_indent: Indent,
+    // byte offset of the start of the last token returned
+    token_start: usize,
 }
 
 impl<'a> Iterator for Tokeniser<'a> {
@@ -35,16 +40,22 @@ impl<'a> Iterator for Tokeniser<'a> {
 
         if self.indent_level() > self.current_line_indent {
             self.indent_stack.pop();
+            self.token_start = self.offset();
 
             return Some(Ok(IndentDecr));
         }
 
         while let Some((i, c)) = self.step() {
+            self.token_start = i;
+
             let token = match c {
                 // handle whitespace
                 ' ' => continue,
                 '\n' | '\r' => match self.newline() {
-                    Some(token) => token,
+                    Some(token) => {
+                        self.token_start = self.offset();
+                        token
+                    }
                     None => continue,
                 },
 
@@ -60,6 +71,10 @@ impl<'a> Iterator for Tokeniser<'a> {
                 '+' => BinOp(Plus),
                 '*' => BinOp(Multiply),
                 '-' => BinOp(Minus),
+                '/' if self.peek_next_char() == Some('/') => {
+                    self.skip_comment();
+                    continue;
+                }
                 '/' => BinOp(Divide),
                 ',' => Comma,
                 '=' => match self.peek_next_char() {
@@ -77,9 +92,12 @@ impl<'a> Iterator for Tokeniser<'a> {
                     if c.is_alphabetic() {
                         self.name(i)
                     } else if c.is_numeric() {
-                        self.number(i)
+                        match self.number(i) {
+                            Ok(number) => number,
+                            Err(error) => return Some(Err(error)),
+                        }
                     } else {
-                        todo!("Unexpected char: {}", c)
+                        return Some(Err(TokeniserError::UnexpectedChar(c)));
                     }
                 }
             };
@@ -87,11 +105,37 @@ impl<'a> Iterator for Tokeniser<'a> {
             return Some(Ok(token));
         }
 
+        self.token_start = self.source.len();
+
         self.indent_stack.pop().map(|_| Ok(IndentDecr))
     }
 }
 
+// Pairs each token with the part of the source it came from. Indentation changes are empty
+// ranges at the start of the line's first token
+#[derive(Debug)]
+pub struct Spanned<'a>(Tokeniser<'a>);
+
+impl<'a> Iterator for Spanned<'a> {
+    type Item = (Range<usize>, Result<Token<'a>>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let token = self.0.next()?;
+
+        let span = match token {
+            Ok(Token::IndentIncr) | Ok(Token::IndentDecr) => self.0.token_start..self.0.token_start,
+            _ => self.0.token_start..self.0.offset(),
+        };
+
+        Some((span, token))
+    }
+}
+
 impl<'a> Tokeniser<'a> {
+    pub fn spanned(self) -> Spanned<'a> {
+        Spanned(self)
+    }
+
     fn step(&mut self) -> Option<(usize, char)> {
         self.chars.next()
     }
@@ -100,6 +144,11 @@ impl<'a> Tokeniser<'a> {
         self.chars.peek().map(|&(_, c)| c)
     }
 
+    // byte offset of the next char
+    fn offset(&mut self) -> usize {
+        self.chars.peek().map_or(self.source.len(), |&(i, _)| i)
+    }
+
     fn indent_level(&self) -> Indent {
         self.indent_stack.last().copied().unwrap_or_default()
     }
@@ -119,6 +168,11 @@ impl<'a> Tokeniser<'a> {
                     // ignore blank lines
                     self.current_line_indent = 0;
                 }
+                // and lines with only a comment on them
+                '/' if self.source[self.offset()..].starts_with("//") => {
+                    self.skip_comment();
+                    continue;
+                }
                 _ => {
                     return match self.current_line_indent.cmp(&self.indent_level()) {
                         Ordering::Greater => {
@@ -140,6 +194,17 @@ impl<'a> Tokeniser<'a> {
         None
     }
 
+    // comments run until the end of the line
+    fn skip_comment(&mut self) {
+        while let Some(c) = self.peek_next_char() {
+            if c == '\n' || c == '\r' {
+                break;
+            }
+
+            self.step();
+        }
+    }
+
     // TODO: single quoted strings
     fn string_constant(&mut self, start: usize) -> Result<Token<'a>> {
         while let Some((i, c)) = self.step() {
@@ -152,32 +217,28 @@ impl<'a> Tokeniser<'a> {
     }
 
     fn name(&mut self, start: usize) -> Token<'a> {
-        let mut end = start + 1;
-
-        while let Some(&(i, c)) = self.chars.peek() {
-            end = i;
-
-            if c.is_alphabetic() || c == '_' {
+        while let Some(c) = self.peek_next_char() {
+            if c.is_alphanumeric() || c == '_' {
                 self.step();
             } else {
                 break;
             }
         }
 
-        let name = &self.source[start..end];
+        let name = &self.source[start..self.offset()];
 
         get_matching_keyword(name)
             .map(Token::Keyword)
-            .unwrap_or(Token::Name(name))
+            .unwrap_or(Token::Name(Identifier {
+                text: name,
+                offset: start as u32,
+            }))
     }
 
-    fn number(&mut self, start: usize) -> Token<'a> {
-        let mut end = start + 1;
+    fn number(&mut self, start: usize) -> Result<Token<'a>> {
         let mut is_float = false;
 
-        while let Some(&(i, c)) = self.chars.peek() {
-            end = i;
-
+        while let Some(c) = self.peek_next_char() {
             if c.is_numeric() {
                 self.step();
             } else if !is_float && c == '.' {
@@ -188,23 +249,76 @@ impl<'a> Tokeniser<'a> {
             }
         }
 
+        let end = self.offset();
+
         let num = &self.source[start..end];
 
         let constant = if is_float {
-            Constant::Float(num.parse().unwrap())
+            num.parse().map(Constant::Float).ok()
         } else {
-            Constant::Int(num.parse().unwrap())
+            let suffix = self.integer_suffix(end);
+
+            integer_value(num).map(|value| {
+                let int = Integer {
+                    value,
+                    has_suffix: suffix.is_some(),
+                    ..Integer::new(0, start)
+                };
+
+                match suffix {
+                    Some("i64") => Constant::Int64(int),
+                    _ => Constant::Int(int),
+                }
+            })
         };
 
-        Token::Constant(constant)
+        constant
+            .map(Token::Constant)
+            .ok_or(TokeniserError::InvalidNumber)
+    }
+
+    // consumes an `i32` or `i64` suffix directly after an integer
+    fn integer_suffix(&mut self, start: usize) -> Option<&'a str> {
+        let suffix = ["i32", "i64"]
+            .iter()
+            .find(|suffix| self.source[start..].starts_with(*suffix))?;
+
+        for _ in 0..suffix.len() {
+            self.step();
+        }
+
+        Some(suffix)
     }
 }
 
+// Too big to fit anything is left for the analyser to say so, as it can point at the integer
+fn integer_value(digits: &str) -> Option<i128> {
+    if !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    Some(digits.parse().unwrap_or(i128::MAX))
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum TokeniserError {
     UnterminatedString,
+    InvalidNumber,
+    UnexpectedChar(char),
+}
+
+impl fmt::Display for TokeniserError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokeniserError::UnterminatedString => write!(f, "unterminated string"),
+            TokeniserError::InvalidNumber => write!(f, "invalid number"),
+            TokeniserError::UnexpectedChar(c) => write!(f, "unexpected character {:?}", c),
+        }
+    }
 }
 
+impl std::error::Error for TokeniserError {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -216,11 +330,65 @@ mod tests {
     #[test_case("maths")]
     #[test_case("functions")]
     #[test_case("fibonacci")]
+    #[test_case("int64")]
+    #[test_case("comments")]
     fn fixtures(name: &str) {
-        let contents = fs::read_to_string(&format!("src/fixtures/{}.lang", name)).unwrap();
+        let contents = fs::read_to_string(format!("src/fixtures/{}.lang", name)).unwrap();
 
         let tokens = tokenise(&contents).collect::<Vec<_>>();
 
         assert_debug_snapshot!(tokens);
     }
+
+    #[test_case("12 3.5 7i32 42i64", &[12, 7, 42]; "integer suffixes")]
+    #[test_case("2147483648", &[2147483648]; "large integers")]
+    #[test_case("9223372036854775808i64 9223372036854775808", &[1 << 63, 1 << 63]; "integers too big for int64")]
+    #[test_case("999999999999999999999999999999999999999999", &[i128::MAX]; "integers too big for anything")]
+    fn integers(source: &str, expected: &[i128]) {
+        let ints: Vec<_> = tokenise(source)
+            .filter_map(|token| match token {
+                Ok(Token::Constant(Constant::Int(Integer { value, .. })))
+                | Ok(Token::Constant(Constant::Int64(Integer { value, .. }))) => Some(value),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(ints, expected);
+    }
+
+    #[test]
+    fn spans() {
+        let source = "fn f(x)\n    x + 2i64 // two\n\"s\"";
+
+        let spans: Vec<_> = tokenise(source)
+            .spanned()
+            .map(|(span, _)| &source[span])
+            .collect();
+
+        assert_eq!(
+            spans,
+            ["fn", "f", "(", "x", ")", "", "x", "+", "2i64", "", "\"s\""]
+        );
+    }
+
+    #[test]
+    fn numbers_with_other_digits_are_an_error() {
+        let tokens: Vec<_> = tokenise("1²").collect();
+
+        assert!(matches!(tokens[..], [Err(TokeniserError::InvalidNumber)]));
+    }
+
+    #[test]
+    fn unexpected_characters_are_an_error() {
+        let tokens: Vec<_> = tokenise("x ! y").collect();
+
+        assert!(matches!(
+            tokens[..],
+            [
+                Ok(Token::Name(_)),
+                Err(TokeniserError::UnexpectedChar('!')),
+                Ok(Token::Name(_))
+            ]
+        ));
+    }
 }
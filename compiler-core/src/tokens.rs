@@ -1,5 +1,6 @@
 use super::keywords::Keyword;
 use super::operators::BinaryOperator;
+use std::fmt;
 
 #[derive(Debug, Copy, Clone)]
 pub enum Token<'a> {
@@ -12,7 +13,7 @@ pub enum Token<'a> {
     CloseParen,
     IndentIncr,
     IndentDecr,
-    Name(&'a str),
+    Name(Identifier<'a>),
     Keyword(Keyword),
     BinOp(BinaryOperator),
     Constant(Constant<'a>),
@@ -22,5 +23,52 @@ pub enum Token<'a> {
 pub enum Constant<'a> {
     Str(&'a str),
     Float(f64),
-    Int(i64),
+    Int(Integer),
+    Int64(Integer),
+    Bool(bool),
+}
+
+// A name as it's written, before the session parsing it interns it, so tokenising on its own,
+// like for highlighting, doesn't keep anything. Knows where it's written, like an `Integer`
+#[derive(Copy, Clone)]
+pub struct Identifier<'a> {
+    pub text: &'a str,
+    // in bytes
+    pub offset: u32,
+}
+
+// Leaves out where it is, like a `Name` does
+impl<'a> fmt::Debug for Identifier<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.text, f)
+    }
+}
+
+// An integer, which for an `Int` without a suffix is an `Int32` only until it's used alongside or
+// passed as an `Int64`. Knows where it's written, like a `Name`, so errors about it can point at
+// it. Written integers are only checked to fit their type once it's known, so can be bigger than
+// any of them, though ones too big even for an `i128` are kept as `i128::MAX`
+#[derive(Copy, Clone)]
+pub struct Integer {
+    pub value: i128,
+    // in bytes
+    pub offset: u32,
+    pub has_suffix: bool,
+}
+
+impl Integer {
+    pub fn new(value: i64, offset: usize) -> Integer {
+        Integer {
+            value: value.into(),
+            offset: offset as u32,
+            has_suffix: false,
+        }
+    }
+}
+
+// Leaves out where it is, like a `Name` does
+impl fmt::Debug for Integer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.value, f)
+    }
 }
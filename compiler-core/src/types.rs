@@ -0,0 +1,43 @@
+use super::wasm::WasmType;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Type {
+    Unit,
+    Int32,
+    Int64,
+    Bool,
+    Float,
+    // the address of a string's iovec in static memory
+    Str,
+}
+
+impl Type {
+    // `None` for values that don't exist at runtime (i.e. they leave nothing on the stack)
+    pub fn wasm_type(self) -> Option<WasmType> {
+        match self {
+            Type::Unit => None,
+            Type::Int32 => Some(WasmType::I32),
+            Type::Int64 => Some(WasmType::I64),
+            Type::Bool => Some(WasmType::I32),
+            Type::Float => Some(WasmType::F32),
+            Type::Str => Some(WasmType::I32),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Type> {
+        let t = match name {
+            "Int32" => Type::Int32,
+            "Int64" => Type::Int64,
+            "Bool" => Type::Bool,
+            "Float" => Type::Float,
+            "Str" => Type::Str,
+            _ => return None,
+        };
+
+        Some(t)
+    }
+
+    pub fn is_unit(self) -> bool {
+        self == Type::Unit
+    }
+}
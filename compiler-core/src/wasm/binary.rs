@@ -0,0 +1,619 @@
+use super::validate::{Result, ValidationError};
+use super::{WasmBlock, WasmExport, WasmFunction, WasmInstr, WasmModule, WasmType};
+use crate::symbol::Symbol;
+use std::collections::HashMap;
+
+// Encodes a module in the binary format runtimes load, as opposed to the text format
+pub fn encode_module<'a>(module: &WasmModule<'a>) -> Result<'a, Vec<u8>> {
+    encode(module, false)
+}
+
+// Also keeps the names of functions and locals, which the text format always has, so debuggers
+// and stack traces can show them
+pub fn encode_module_with_names<'a>(module: &WasmModule<'a>) -> Result<'a, Vec<u8>> {
+    encode(module, true)
+}
+
+fn encode<'a>(module: &WasmModule<'a>, names: bool) -> Result<'a, Vec<u8>> {
+    let mut out = b"\0asm".to_vec();
+
+    out.extend_from_slice(&1u32.to_le_bytes());
+
+    // functions with the same signature share a type
+    let mut types: Vec<(Vec<WasmType>, Option<WasmType>)> = Vec::new();
+    let mut type_index = |signature: (Vec<WasmType>, Option<WasmType>)| match types
+        .iter()
+        .position(|t| *t == signature)
+    {
+        Some(index) => index,
+        None => {
+            types.push(signature);
+            types.len() - 1
+        }
+    };
+
+    let import_type_indices: Vec<_> = module
+        .imports
+        .iter()
+        .map(|import| type_index((import.params.clone(), import.return_type)))
+        .collect();
+
+    let type_indices: Vec<_> = module
+        .functions
+        .iter()
+        .map(|func| {
+            type_index((
+                func.params.iter().map(|&(_, t)| t).collect(),
+                func.return_type,
+            ))
+        })
+        .collect();
+
+    section(&mut out, TYPE_SECTION, types.len(), |s| {
+        for (params, result) in &types {
+            s.push(0x60);
+            vector(s, params.iter().map(|&t| value_type(t)));
+            vector(s, result.iter().map(|&t| value_type(t)));
+        }
+    });
+
+    section(&mut out, IMPORT_SECTION, module.imports.len(), |s| {
+        for (import, &index) in module.imports.iter().zip(&import_type_indices) {
+            name(s, import.module);
+            name(s, import.field);
+            s.push(EXTERNAL_FUNCTION);
+            unsigned(s, index as u64);
+        }
+    });
+
+    section(&mut out, FUNCTION_SECTION, type_indices.len(), |s| {
+        for &index in &type_indices {
+            unsigned(s, index as u64);
+        }
+    });
+
+    if let Some(memory) = &module.memory {
+        section(&mut out, MEMORY_SECTION, 1, |s| {
+            // limits with only a minimum
+            s.push(0x00);
+            unsigned(s, memory.pages.into());
+        });
+    }
+
+    let function_indices = function_indices(module);
+
+    let mut exports = Vec::new();
+
+    for export in &module.exports {
+        exports.push(match *export {
+            WasmExport::Function {
+                wasm_name,
+                exported_name,
+            } => (
+                exported_name.as_str(),
+                EXTERNAL_FUNCTION,
+                *function_indices
+                    .get(&wasm_name)
+                    .ok_or(ValidationError::UndefinedExport(wasm_name.as_str()))?,
+            ),
+            WasmExport::Memory { exported_name } => (exported_name, EXTERNAL_MEMORY, 0),
+        });
+    }
+
+    section(&mut out, EXPORT_SECTION, exports.len(), |s| {
+        for &(exported_name, kind, index) in &exports {
+            name(s, exported_name);
+            s.push(kind);
+            unsigned(s, index as u64);
+        }
+    });
+
+    let mut code = Vec::new();
+
+    for func in &module.functions {
+        let body = FunctionEncoder::new(&function_indices, func).encode()?;
+
+        unsigned(&mut code, body.len() as u64);
+        code.extend(body);
+    }
+
+    section(&mut out, CODE_SECTION, module.functions.len(), |s| {
+        s.extend(code)
+    });
+
+    if let Some(memory) = module.memory.as_ref().filter(|m| !m.data.is_empty()) {
+        section(&mut out, DATA_SECTION, 1, |s| {
+            // an active segment for memory 0, placed by a constant expression
+            s.push(0x00);
+            s.push(0x41);
+            signed(s, memory.data_offset.into());
+            s.push(END);
+            unsigned(s, memory.data.len() as u64);
+            s.extend_from_slice(&memory.data);
+        });
+    }
+
+    if names {
+        name_section(&mut out, module);
+    }
+
+    Ok(out)
+}
+
+// The custom "name" section, with a subsection naming every function, and one naming the locals
+// of the functions defined in the module
+fn name_section(out: &mut Vec<u8>, module: &WasmModule) {
+    let imported = module.imports.len();
+
+    let mut function_names = Vec::new();
+
+    unsigned(
+        &mut function_names,
+        (imported + module.functions.len()) as u64,
+    );
+
+    let all_names = module.imports.iter().map(|import| import.name);
+
+    for (index, function) in all_names
+        .chain(module.functions.iter().map(|func| func.name))
+        .enumerate()
+    {
+        unsigned(&mut function_names, index as u64);
+        name(&mut function_names, function.as_str());
+    }
+
+    let mut local_names = Vec::new();
+
+    unsigned(&mut local_names, module.functions.len() as u64);
+
+    for (index, func) in module.functions.iter().enumerate() {
+        let locals: Vec<_> = all_locals(func).collect();
+
+        unsigned(&mut local_names, (imported + index) as u64);
+        unsigned(&mut local_names, locals.len() as u64);
+
+        for (index, local) in locals.into_iter().enumerate() {
+            unsigned(&mut local_names, index as u64);
+            name(&mut local_names, local.as_str());
+        }
+    }
+
+    let mut body = Vec::new();
+
+    name(&mut body, "name");
+
+    for (id, subsection) in [(FUNCTION_NAMES, function_names), (LOCAL_NAMES, local_names)] {
+        body.push(id);
+        unsigned(&mut body, subsection.len() as u64);
+        body.extend(subsection);
+    }
+
+    out.push(CUSTOM_SECTION);
+    unsigned(out, body.len() as u64);
+    out.extend(body);
+}
+
+const CUSTOM_SECTION: u8 = 0;
+const TYPE_SECTION: u8 = 1;
+const IMPORT_SECTION: u8 = 2;
+const FUNCTION_SECTION: u8 = 3;
+const MEMORY_SECTION: u8 = 5;
+const EXPORT_SECTION: u8 = 7;
+const CODE_SECTION: u8 = 10;
+const DATA_SECTION: u8 = 11;
+
+const FUNCTION_NAMES: u8 = 1;
+const LOCAL_NAMES: u8 = 2;
+
+const EXTERNAL_FUNCTION: u8 = 0x00;
+const EXTERNAL_MEMORY: u8 = 0x02;
+const EMPTY_BLOCK_TYPE: u8 = 0x40;
+const END: u8 = 0x0b;
+
+// Sections are left out when they'd be empty
+fn section(out: &mut Vec<u8>, id: u8, count: usize, contents: impl FnOnce(&mut Vec<u8>)) {
+    if count == 0 {
+        return;
+    }
+
+    let mut body = Vec::new();
+
+    unsigned(&mut body, count as u64);
+    contents(&mut body);
+
+    out.push(id);
+    unsigned(out, body.len() as u64);
+    out.extend(body);
+}
+
+fn name(out: &mut Vec<u8>, name: &str) {
+    unsigned(out, name.len() as u64);
+    out.extend_from_slice(name.as_bytes());
+}
+
+fn vector(out: &mut Vec<u8>, items: impl ExactSizeIterator<Item = u8>) {
+    unsigned(out, items.len() as u64);
+    out.extend(items);
+}
+
+fn value_type(wasm_type: WasmType) -> u8 {
+    match wasm_type {
+        WasmType::I32 => 0x7f,
+        WasmType::I64 => 0x7e,
+        WasmType::F32 => 0x7d,
+    }
+}
+
+fn block_type(result_type: Option<WasmType>) -> u8 {
+    result_type.map_or(EMPTY_BLOCK_TYPE, value_type)
+}
+
+// LEB128, which all integers in the binary format use
+fn unsigned(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+
+        value >>= 7;
+
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+
+        out.push(byte | 0x80);
+    }
+}
+
+fn signed(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+
+        value >>= 7;
+
+        // done once the rest is all sign bits, and the sign bit of this byte agrees
+        let sign_bit = byte & 0x40 != 0;
+
+        if (value == 0 && !sign_bit) || (value == -1 && sign_bit) {
+            out.push(byte);
+            return;
+        }
+
+        out.push(byte | 0x80);
+    }
+}
+
+// Imported functions come first in the index space
+fn function_indices<'a>(module: &WasmModule<'a>) -> HashMap<Symbol<'a>, usize> {
+    let imported = module.imports.iter().map(|import| import.name);
+    let defined = module.functions.iter().map(|func| func.name);
+
+    imported
+        .chain(defined)
+        .enumerate()
+        .map(|(index, name)| (name, index))
+        .collect()
+}
+
+// The params then the locals, which is the order they're numbered in
+fn all_locals<'m, 'a>(func: &'m WasmFunction<'a>) -> impl Iterator<Item = Symbol<'a>> + 'm {
+    func.params
+        .iter()
+        .map(|&(name, _)| name)
+        .chain(func.local_variables.keys().copied())
+}
+
+struct FunctionEncoder<'m, 'a> {
+    function_indices: &'m HashMap<Symbol<'a>, usize>,
+    func: &'m WasmFunction<'a>,
+    locals: HashMap<Symbol<'a>, usize>,
+    // the innermost block is last, with only loops having names
+    labels: Vec<Option<Symbol<'a>>>,
+    out: Vec<u8>,
+}
+
+impl<'m, 'a> FunctionEncoder<'m, 'a> {
+    fn new(function_indices: &'m HashMap<Symbol<'a>, usize>, func: &'m WasmFunction<'a>) -> Self {
+        let locals = all_locals(func)
+            .enumerate()
+            .map(|(index, name)| (name, index))
+            .collect();
+
+        FunctionEncoder {
+            function_indices,
+            func,
+            locals,
+            labels: Vec::new(),
+            out: Vec::new(),
+        }
+    }
+
+    fn encode(mut self) -> Result<'a, Vec<u8>> {
+        // locals are declared in runs of the same type
+        let mut runs: Vec<(u32, WasmType)> = Vec::new();
+
+        for &wasm_type in self.func.local_variables.values() {
+            match runs.last_mut() {
+                Some((count, t)) if *t == wasm_type => *count += 1,
+                _ => runs.push((1, wasm_type)),
+            }
+        }
+
+        unsigned(&mut self.out, runs.len() as u64);
+
+        for &(count, wasm_type) in &runs {
+            unsigned(&mut self.out, count.into());
+            self.out.push(value_type(wasm_type));
+        }
+
+        self.block(&self.func.body)?;
+        self.out.push(END);
+
+        Ok(self.out)
+    }
+
+    fn block(&mut self, block: &'m WasmBlock<'a>) -> Result<'a, ()> {
+        for instr in block {
+            self.instruction(instr)?;
+        }
+
+        Ok(())
+    }
+
+    fn instruction(&mut self, instr: &'m WasmInstr<'a>) -> Result<'a, ()> {
+        use WasmInstr::*;
+
+        match instr {
+            GetLocal(name) => self.local(0x20, *name)?,
+            SetLocal(name) => self.local(0x21, *name)?,
+            TeeLocal(name) => self.local(0x22, *name)?,
+            ConstI32(value) => {
+                self.out.push(0x41);
+                signed(&mut self.out, (*value).into());
+            }
+            ConstI64(value) => {
+                self.out.push(0x42);
+                signed(&mut self.out, *value);
+            }
+            ConstF32(value) => {
+                self.out.push(0x43);
+                self.out.extend_from_slice(&value.to_le_bytes());
+            }
+            AddI32 => self.out.push(0x6a),
+            MinusI32 => self.out.push(0x6b),
+            MultiplyI32 => self.out.push(0x6c),
+            SignedDivideI32 => self.out.push(0x6d),
+            EqualI32 => self.out.push(0x46),
+            AddI64 => self.out.push(0x7c),
+            MinusI64 => self.out.push(0x7d),
+            MultiplyI64 => self.out.push(0x7e),
+            SignedDivideI64 => self.out.push(0x7f),
+            EqualI64 => self.out.push(0x51),
+            Call(name) => self.call(0x10, *name)?,
+            ReturnCall(name) => self.call(0x12, *name)?,
+            Branch(label) => {
+                let depth = self
+                    .labels
+                    .iter()
+                    .rev()
+                    .position(|l| *l == Some(*label))
+                    .ok_or(ValidationError::UndefinedLabel {
+                        function: self.func.name,
+                        label: *label,
+                    })?;
+
+                self.out.push(0x0c);
+                unsigned(&mut self.out, depth as u64);
+            }
+            Drop => self.out.push(0x1a),
+            Return => self.out.push(0x0f),
+            Unreachable => self.out.push(0x00),
+            If {
+                result_type,
+                condition,
+                then,
+                else_,
+            } => {
+                self.block(condition)?;
+
+                self.out.push(0x04);
+                self.out.push(block_type(*result_type));
+                self.labels.push(None);
+
+                self.block(then)?;
+
+                if let Some(else_) = else_ {
+                    self.out.push(0x05);
+                    self.block(else_)?;
+                }
+
+                self.labels.pop();
+                self.out.push(END);
+            }
+            Loop {
+                label,
+                result_type,
+                body,
+            } => {
+                self.out.push(0x03);
+                self.out.push(block_type(*result_type));
+                self.labels.push(Some(*label));
+
+                self.block(body)?;
+
+                self.labels.pop();
+                self.out.push(END);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn local(&mut self, opcode: u8, local: Symbol<'a>) -> Result<'a, ()> {
+        let index = *self
+            .locals
+            .get(&local)
+            .ok_or(ValidationError::UndefinedLocal {
+                function: self.func.name,
+                local,
+            })?;
+
+        self.out.push(opcode);
+        unsigned(&mut self.out, index as u64);
+
+        Ok(())
+    }
+
+    fn call(&mut self, opcode: u8, called: Symbol<'a>) -> Result<'a, ()> {
+        let index =
+            *self
+                .function_indices
+                .get(&called)
+                .ok_or(ValidationError::UndefinedFunction {
+                    function: self.func.name,
+                    called,
+                })?;
+
+        self.out.push(opcode);
+        unsigned(&mut self.out, index as u64);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile_module;
+    use crate::parser::ParseSession;
+    use crate::OptimisationLevel;
+    use std::collections::BTreeMap;
+    use std::fs;
+    use test_case::test_case;
+    use wasmtime::*;
+    use WasmInstr::*;
+    use WasmType::*;
+
+    #[test_case(0, &[0x00]; "zero")]
+    #[test_case(63, &[0x3f]; "largest single byte")]
+    #[test_case(64, &[0xc0, 0x00]; "needs a sign byte")]
+    #[test_case(-1, &[0x7f]; "minus one")]
+    #[test_case(-65, &[0xbf, 0x7f]; "negative two bytes")]
+    #[test_case(624485, &[0xe5, 0x8e, 0x26]; "three bytes")]
+    fn signed_integers(value: i64, expected: &[u8]) {
+        let mut out = Vec::new();
+
+        signed(&mut out, value);
+
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn unsigned_integers() {
+        let mut out = Vec::new();
+
+        unsigned(&mut out, 624485);
+
+        assert_eq!(out, [0xe5, 0x8e, 0x26]);
+    }
+
+    #[test]
+    fn encodes_a_small_module() {
+        let mut module = WasmModule::default();
+
+        module.add_function(
+            WasmFunction::new(
+                "double".into(),
+                vec![("n".into(), I32)],
+                BTreeMap::new(),
+                Some(I32),
+                vec![GetLocal("n".into()), ConstI32(2), MultiplyI32],
+            ),
+            true,
+        );
+
+        let expected = [
+            b"\0asm".as_ref(),
+            &[1, 0, 0, 0],
+            // one type, (i32) -> i32
+            &[1, 6, 1, 0x60, 1, 0x7f, 1, 0x7f],
+            // one function, using it
+            &[3, 2, 1, 0],
+            // exported as "double"
+            &[7, 10, 1, 6],
+            b"double",
+            &[0, 0],
+            // no locals, then the body
+            &[10, 9, 1, 7, 0, 0x20, 0, 0x41, 2, 0x6c, 0x0b],
+        ]
+        .concat();
+
+        assert_eq!(encode_module(&module).unwrap(), expected);
+    }
+
+    #[test]
+    fn names_functions_and_locals() {
+        let mut module = WasmModule::default();
+
+        module.add_function(
+            WasmFunction::new(
+                "id".into(),
+                vec![("n".into(), I32)],
+                BTreeMap::new(),
+                Some(I32),
+                vec![GetLocal("n".into())],
+            ),
+            false,
+        );
+
+        let without_names = encode_module(&module).unwrap();
+        let with_names = encode_module_with_names(&module).unwrap();
+
+        let name_section = [
+            &[0, 20, 4],
+            b"name".as_ref(),
+            // function 0 is "id"
+            &[1, 5, 1, 0, 2],
+            b"id",
+            // function 0 has local 0 "n"
+            &[2, 6, 1, 0, 1, 0, 1],
+            b"n",
+        ]
+        .concat();
+
+        assert_eq!(with_names, [without_names, name_section].concat());
+    }
+
+    // checks the binary is accepted by a real runtime, and gives the same results as the text
+    #[test_case("fibonacci", 10, 55)]
+    #[test_case("unit_functions", 3, 7)]
+    #[test_case("booleans", 4, 40)]
+    #[test_case("int64", 7, 7)]
+    #[test_case("tail_calls", 1_000_000, 1_000_000)]
+    fn programs_run_in_wasmtime(name: &str, arg: i32, expected: i32) {
+        let code = fs::read_to_string(format!("src/fixtures/{}.lang", name)).unwrap();
+
+        for &level in &[OptimisationLevel::None, OptimisationLevel::Basic] {
+            let session = ParseSession::new();
+
+            let wasm = compile_module(&session, &code, level).unwrap();
+
+            for &encode in &[encode_module, encode_module_with_names] {
+                let binary = encode(&wasm).unwrap();
+
+                let engine = Engine::default();
+
+                let store = Store::new(&engine);
+
+                let module = Module::new(&engine, &binary).unwrap();
+
+                let instance = Instance::new(&store, &module, &[]).unwrap();
+
+                let main = instance
+                    .get_typed_func::<i32, i32>("main")
+                    .expect("`main` was not an exported function");
+
+                assert_eq!(main.call(arg).unwrap(), expected, "at level {:?}", level);
+            }
+        }
+    }
+}
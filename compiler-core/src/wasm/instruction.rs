@@ -1,26 +1,44 @@
 use super::format::{Wasm, WasmIndentation};
+use crate::symbol::Symbol;
 use std::fmt::{self, Write};
 
 pub type WasmBlock<'a> = Vec<WasmInstr<'a>>;
 
 #[derive(Debug)]
 pub enum WasmInstr<'a> {
-    GetLocal(&'a str),
-    SetLocal(&'a str),
+    GetLocal(Symbol<'a>),
+    SetLocal(Symbol<'a>),
+    TeeLocal(Symbol<'a>),
     ConstI32(i32),
+    ConstI64(i64),
     ConstF32(f32),
     AddI32,
     MinusI32,
     MultiplyI32,
     SignedDivideI32,
     EqualI32,
-    Call(&'a str),
+    AddI64,
+    MinusI64,
+    MultiplyI64,
+    SignedDivideI64,
+    EqualI64,
+    Call(Symbol<'a>),
+    ReturnCall(Symbol<'a>),
+    Branch(Symbol<'a>),
+    Drop,
+    Return,
+    Unreachable,
     If {
-        result_type: WasmType,
+        result_type: Option<WasmType>,
         condition: WasmBlock<'a>,
         then: WasmBlock<'a>,
         else_: Option<WasmBlock<'a>>,
     },
+    Loop {
+        label: Symbol<'a>,
+        result_type: Option<WasmType>,
+        body: WasmBlock<'a>,
+    },
 }
 
 impl<'a, Writer: Write> Wasm<Writer> for WasmInstr<'a> {
@@ -30,14 +48,26 @@ impl<'a, Writer: Write> Wasm<Writer> for WasmInstr<'a> {
         match self {
             WasmInstr::GetLocal(name) => write!(w, "local.get ${}", name),
             WasmInstr::SetLocal(name) => write!(w, "local.set ${}", name),
+            WasmInstr::TeeLocal(name) => write!(w, "local.tee ${}", name),
             WasmInstr::ConstI32(value) => write!(w, "i32.const {}", value),
+            WasmInstr::ConstI64(value) => write!(w, "i64.const {}", value),
             WasmInstr::ConstF32(value) => write!(w, "f32.const {}", value),
             WasmInstr::AddI32 => write!(w, "i32.add"),
             WasmInstr::MinusI32 => write!(w, "i32.sub"),
             WasmInstr::MultiplyI32 => write!(w, "i32.mul"),
             WasmInstr::SignedDivideI32 => write!(w, "i32.div_s"),
             WasmInstr::EqualI32 => write!(w, "i32.eq"),
+            WasmInstr::AddI64 => write!(w, "i64.add"),
+            WasmInstr::MinusI64 => write!(w, "i64.sub"),
+            WasmInstr::MultiplyI64 => write!(w, "i64.mul"),
+            WasmInstr::SignedDivideI64 => write!(w, "i64.div_s"),
+            WasmInstr::EqualI64 => write!(w, "i64.eq"),
             WasmInstr::Call(name) => write!(w, "call ${}", name),
+            WasmInstr::ReturnCall(name) => write!(w, "return_call ${}", name),
+            WasmInstr::Branch(label) => write!(w, "br ${}", label),
+            WasmInstr::Drop => write!(w, "drop"),
+            WasmInstr::Return => write!(w, "return"),
+            WasmInstr::Unreachable => write!(w, "unreachable"),
             WasmInstr::If {
                 result_type,
                 condition,
@@ -49,7 +79,11 @@ impl<'a, Writer: Write> Wasm<Writer> for WasmInstr<'a> {
                 }
 
                 format.new_line_with_indent(w)?;
-                write!(w, " (if (result {})", result_type.to_wasm_text())?;
+                write!(w, " (if")?;
+
+                if let Some(wasm_type) = result_type {
+                    write!(w, " (result {})", wasm_type.to_wasm_text())?;
+                }
 
                 let indent_1 = format.increase_indent();
 
@@ -77,21 +111,43 @@ impl<'a, Writer: Write> Wasm<Writer> for WasmInstr<'a> {
                     write!(w, ")")?;
                 }
 
+                write!(w, ")")
+            }
+            WasmInstr::Loop {
+                label,
+                result_type,
+                body,
+            } => {
+                write!(w, "(loop ${}", label)?;
+
+                if let Some(wasm_type) = result_type {
+                    write!(w, " (result {})", wasm_type.to_wasm_text())?;
+                }
+
+                let body_format = format.increase_indent();
+                for instruction in body {
+                    instruction.write_text(w, body_format)?;
+                }
+
                 write!(w, ")")
             }
         }
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum WasmType {
     I32,
+    I64,
+    F32,
 }
 
 impl WasmType {
     pub fn to_wasm_text(self) -> &'static str {
         match self {
             WasmType::I32 => "i32",
+            WasmType::I64 => "i64",
+            WasmType::F32 => "f32",
         }
     }
 }
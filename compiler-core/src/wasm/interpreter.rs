@@ -0,0 +1,647 @@
+use super::{
+    WasmBlock, WasmExport, WasmFunction, WasmImport, WasmInstr, WasmModule, WasmType, PAGE_SIZE,
+};
+use crate::symbol::Symbol;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+
+pub type Result<X> = std::result::Result<X, Trap>;
+
+// Frames are kept on the heap rather than the native stack, so this is only there to stop runaway
+// recursion using up all the memory
+const MAX_CALL_DEPTH: usize = 100_000;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Value {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+}
+
+impl Value {
+    fn zero(wasm_type: WasmType) -> Value {
+        match wasm_type {
+            WasmType::I32 => Value::I32(0),
+            WasmType::I64 => Value::I64(0),
+            WasmType::F32 => Value::F32(0.0),
+        }
+    }
+}
+
+impl From<i32> for Value {
+    fn from(value: i32) -> Self {
+        Value::I32(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::I64(value)
+    }
+}
+
+impl From<f32> for Value {
+    fn from(value: f32) -> Self {
+        Value::F32(value)
+    }
+}
+
+// Runs a module directly, following the same semantics as a wasm runtime would.
+// Expects a module that passes validation, but reports a `Trap` rather than panicking if not.
+// Provides the parts of WASI the compiler imports, with anything written to stdout kept in memory
+pub struct Interpreter<'m> {
+    module: &'m WasmModule<'m>,
+    functions: HashMap<Symbol<'m>, &'m WasmFunction<'m>>,
+    imports: HashMap<Symbol<'m>, &'m WasmImport<'m>>,
+    memory: RefCell<Vec<u8>>,
+    stdout: RefCell<Vec<u8>>,
+}
+
+// How a frame stops running
+enum Exit<'m> {
+    Call(Frame<'m>),
+    // the callee replaces the caller's frame instead of nesting inside it
+    TailCall(Frame<'m>),
+    Return(Option<Value>),
+}
+
+struct Frame<'m> {
+    function: &'m WasmFunction<'m>,
+    locals: HashMap<Symbol<'m>, Value>,
+    stack: Vec<Value>,
+    // the blocks being run, innermost last
+    controls: Vec<Control<'m>>,
+}
+
+// A block, and how far through it the frame has got
+struct Control<'m> {
+    instrs: &'m [WasmInstr<'m>],
+    next: usize,
+    kind: ControlKind<'m>,
+}
+
+enum ControlKind<'m> {
+    // the body of a function, or a branch of an `If`
+    Block,
+    // the condition of an `If`, which picks the branch to run when it ends
+    Condition {
+        then: &'m WasmBlock<'m>,
+        else_: Option<&'m WasmBlock<'m>>,
+    },
+    // branching to `label` starts the body again, with the stack back to `height`
+    Loop {
+        label: Symbol<'m>,
+        height: usize,
+    },
+}
+
+impl<'m> Interpreter<'m> {
+    pub fn new(module: &'m WasmModule<'m>) -> Self {
+        let functions = module.functions.iter().map(|f| (f.name, f)).collect();
+        let imports = module.imports.iter().map(|i| (i.name, i)).collect();
+
+        let memory = match &module.memory {
+            Some(memory) => {
+                let mut bytes = vec![0; memory.pages as usize * PAGE_SIZE];
+                let start = memory.data_offset as usize;
+
+                if let Some(range) = bytes.get_mut(start..start + memory.data.len()) {
+                    range.copy_from_slice(&memory.data);
+                }
+
+                bytes
+            }
+            None => vec![],
+        };
+
+        Interpreter {
+            module,
+            functions,
+            imports,
+            memory: RefCell::new(memory),
+            stdout: RefCell::new(vec![]),
+        }
+    }
+
+    pub fn invoke(&self, export: &str, args: &[Value]) -> Result<Option<Value>> {
+        let function = self
+            .module
+            .exports
+            .iter()
+            .find_map(|export_| match *export_ {
+                WasmExport::Function {
+                    wasm_name,
+                    exported_name,
+                } if exported_name.as_str() == export => Some(wasm_name),
+                _ => None,
+            })
+            .ok_or(Trap::UndefinedExport)?;
+
+        self.call(function, args.to_vec())
+    }
+
+    // Everything written to stdout so far
+    pub fn stdout(&self) -> String {
+        String::from_utf8_lossy(&self.stdout.borrow()).into_owned()
+    }
+
+    // Callers' frames are kept in a stack rather than on the native one, so how deep a program can
+    // recurse doesn't depend on the thread the interpreter is run on
+    fn call(&self, name: Symbol<'m>, args: Vec<Value>) -> Result<Option<Value>> {
+        if let Some(import) = self.imports.get(&name) {
+            return self.call_host(import, &args);
+        }
+
+        let mut frame = self.frame(name, args)?;
+        let mut callers = vec![];
+
+        loop {
+            match self.run(&mut frame)? {
+                Exit::Call(callee) => {
+                    if callers.len() + 1 >= MAX_CALL_DEPTH {
+                        return Err(Trap::CallStackExhausted);
+                    }
+
+                    callers.push(std::mem::replace(&mut frame, callee));
+                }
+                Exit::TailCall(callee) => frame = callee,
+                Exit::Return(result) => match callers.pop() {
+                    Some(caller) => {
+                        frame = caller;
+                        frame.stack.extend(result);
+                    }
+                    None => return Ok(result),
+                },
+            }
+        }
+    }
+
+    fn frame(&self, name: Symbol<'m>, args: Vec<Value>) -> Result<Frame<'m>> {
+        let function = self.function(name)?;
+
+        if args.len() != function.params.len() {
+            return Err(Trap::InvalidModule);
+        }
+
+        let locals = function
+            .params
+            .iter()
+            .zip(args)
+            .map(|(&(param, _), value)| (param, value))
+            .chain(
+                function
+                    .local_variables
+                    .iter()
+                    .map(|(&local, &t)| (local, Value::zero(t))),
+            )
+            .collect();
+
+        Ok(Frame {
+            function,
+            locals,
+            stack: vec![],
+            controls: vec![Control::new(&function.body, ControlKind::Block)],
+        })
+    }
+
+    fn function(&self, name: Symbol<'m>) -> Result<&'m WasmFunction<'m>> {
+        self.functions
+            .get(&name)
+            .copied()
+            .ok_or(Trap::InvalidModule)
+    }
+
+    fn param_count(&self, name: Symbol<'m>) -> Result<usize> {
+        match self.imports.get(&name) {
+            Some(import) => Ok(import.params.len()),
+            None => Ok(self.function(name)?.params.len()),
+        }
+    }
+
+    fn call_host(&self, import: &WasmImport<'m>, args: &[Value]) -> Result<Option<Value>> {
+        let args = args
+            .iter()
+            .map(|arg| match arg {
+                Value::I32(value) => Ok(*value),
+                _ => Err(Trap::InvalidModule),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        match (import.module, import.field, args.as_slice()) {
+            ("wasi_snapshot_preview1", "fd_write", &[fd, iovs, iovs_len, nwritten]) => {
+                self.fd_write(fd, iovs, iovs_len, nwritten).map(Some)
+            }
+            ("wasi_snapshot_preview1", "proc_exit", &[code]) => Err(Trap::Exit(code)),
+            _ => Err(Trap::UndefinedImport),
+        }
+    }
+
+    // Writes each (base, length) buffer in the iovec array, returning an errno
+    fn fd_write(&self, fd: i32, iovs: i32, iovs_len: i32, nwritten: i32) -> Result<Value> {
+        const SUCCESS: i32 = 0;
+        const BAD_FILE_DESCRIPTOR: i32 = 8;
+
+        if fd != 1 {
+            return Ok(Value::I32(BAD_FILE_DESCRIPTOR));
+        }
+
+        let mut memory = self.memory.borrow_mut();
+        let mut written = 0u32;
+
+        for i in 0..iovs_len as u32 {
+            let iov = iovs as u32 + i * 8;
+            let base = load_u32(&memory, iov)?;
+            let len = load_u32(&memory, iov + 4)?;
+
+            let bytes = memory
+                .get(base as usize..(base as usize).saturating_add(len as usize))
+                .ok_or(Trap::MemoryOutOfBounds)?;
+
+            self.stdout.borrow_mut().extend_from_slice(bytes);
+            written += len;
+        }
+
+        memory
+            .get_mut(nwritten as u32 as usize..nwritten as u32 as usize + 4)
+            .ok_or(Trap::MemoryOutOfBounds)?
+            .copy_from_slice(&written.to_le_bytes());
+
+        Ok(Value::I32(SUCCESS))
+    }
+
+    // Runs the frame's function until it calls another one or returns
+    fn run(&self, frame: &mut Frame<'m>) -> Result<Exit<'m>> {
+        use WasmInstr::*;
+
+        loop {
+            let control = match frame.controls.last_mut() {
+                Some(control) => control,
+                None => return frame.finish(),
+            };
+
+            let instr = match control.instrs.get(control.next) {
+                Some(instr) => instr,
+                None => {
+                    frame.end_block()?;
+
+                    continue;
+                }
+            };
+
+            control.next += 1;
+
+            match instr {
+                GetLocal(name) => {
+                    let value = *frame.locals.get(name).ok_or(Trap::InvalidModule)?;
+
+                    frame.stack.push(value);
+                }
+                SetLocal(name) => {
+                    let value = frame.pop()?;
+
+                    frame.set_local(*name, value)?;
+                }
+                TeeLocal(name) => {
+                    let value = frame.pop()?;
+
+                    frame.set_local(*name, value)?;
+                    frame.stack.push(value);
+                }
+                ConstI32(value) => frame.stack.push(Value::I32(*value)),
+                ConstI64(value) => frame.stack.push(Value::I64(*value)),
+                ConstF32(value) => frame.stack.push(Value::F32(*value)),
+                AddI32 | MinusI32 | MultiplyI32 | SignedDivideI32 | EqualI32 => {
+                    let right = frame.pop_i32()?;
+                    let left = frame.pop_i32()?;
+
+                    let result = match instr {
+                        AddI32 => left.wrapping_add(right),
+                        MinusI32 => left.wrapping_sub(right),
+                        MultiplyI32 => left.wrapping_mul(right),
+                        SignedDivideI32 => divide(left, right, i32::checked_div)?,
+                        _ => (left == right) as i32,
+                    };
+
+                    frame.stack.push(Value::I32(result));
+                }
+                AddI64 | MinusI64 | MultiplyI64 | SignedDivideI64 => {
+                    let right = frame.pop_i64()?;
+                    let left = frame.pop_i64()?;
+
+                    let result = match instr {
+                        AddI64 => left.wrapping_add(right),
+                        MinusI64 => left.wrapping_sub(right),
+                        MultiplyI64 => left.wrapping_mul(right),
+                        _ => divide(left, right, i64::checked_div)?,
+                    };
+
+                    frame.stack.push(Value::I64(result));
+                }
+                EqualI64 => {
+                    let right = frame.pop_i64()?;
+                    let left = frame.pop_i64()?;
+
+                    frame.stack.push(Value::I32((left == right) as i32));
+                }
+                Call(name) => {
+                    let args = frame.pop_n(self.param_count(*name)?)?;
+
+                    match self.imports.get(name) {
+                        Some(import) => frame.stack.extend(self.call_host(import, &args)?),
+                        None => return self.frame(*name, args).map(Exit::Call),
+                    }
+                }
+                ReturnCall(name) => {
+                    let args = frame.pop_n(self.param_count(*name)?)?;
+
+                    return match self.imports.get(name) {
+                        Some(import) => self.call_host(import, &args).map(Exit::Return),
+                        None => self.frame(*name, args).map(Exit::TailCall),
+                    };
+                }
+                Branch(label) => frame.branch(*label)?,
+                Drop => {
+                    frame.pop()?;
+                }
+                Return => return frame.finish(),
+                Unreachable => return Err(Trap::Unreachable),
+                If {
+                    condition,
+                    then,
+                    else_,
+                    ..
+                } => frame.controls.push(Control::new(
+                    condition,
+                    ControlKind::Condition {
+                        then,
+                        else_: else_.as_ref(),
+                    },
+                )),
+                Loop { label, body, .. } => {
+                    let height = frame.stack.len();
+
+                    frame.controls.push(Control::new(
+                        body,
+                        ControlKind::Loop {
+                            label: *label,
+                            height,
+                        },
+                    ));
+                }
+            }
+        }
+    }
+}
+
+impl<'m> Control<'m> {
+    fn new(instrs: &'m [WasmInstr<'m>], kind: ControlKind<'m>) -> Self {
+        Control {
+            instrs,
+            next: 0,
+            kind,
+        }
+    }
+}
+
+fn load_u32(memory: &[u8], address: u32) -> Result<u32> {
+    let address = address as usize;
+
+    memory
+        .get(address..address + 4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or(Trap::MemoryOutOfBounds)
+}
+
+// Signed division traps on both dividing by zero and overflowing
+fn divide<T: Default + PartialEq>(
+    left: T,
+    right: T,
+    checked_div: fn(T, T) -> Option<T>,
+) -> Result<T> {
+    if right == T::default() {
+        return Err(Trap::DivisionByZero);
+    }
+
+    checked_div(left, right).ok_or(Trap::IntegerOverflow)
+}
+
+impl<'m> Frame<'m> {
+    fn finish(&mut self) -> Result<Exit<'m>> {
+        match self.function.return_type {
+            Some(_) => self.pop().map(|value| Exit::Return(Some(value))),
+            None => Ok(Exit::Return(None)),
+        }
+    }
+
+    // Leaves the innermost block, running a branch if it was the condition of an `If`
+    fn end_block(&mut self) -> Result<()> {
+        let control = self.controls.pop().ok_or(Trap::InvalidModule)?;
+
+        if let ControlKind::Condition { then, else_ } = control.kind {
+            let branch = if self.pop_i32()? != 0 {
+                Some(then)
+            } else {
+                else_
+            };
+
+            if let Some(branch) = branch {
+                self.controls.push(Control::new(branch, ControlKind::Block));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Leaves every block inside the loop with the label, and starts the loop again
+    fn branch(&mut self, label: Symbol<'m>) -> Result<()> {
+        loop {
+            let control = self.controls.last_mut().ok_or(Trap::InvalidModule)?;
+
+            if let ControlKind::Loop {
+                label: target,
+                height,
+            } = control.kind
+            {
+                if target == label {
+                    control.next = 0;
+                    self.stack.truncate(height);
+
+                    return Ok(());
+                }
+            }
+
+            self.controls.pop();
+        }
+    }
+
+    fn pop(&mut self) -> Result<Value> {
+        self.stack.pop().ok_or(Trap::InvalidModule)
+    }
+
+    fn pop_i32(&mut self) -> Result<i32> {
+        match self.pop()? {
+            Value::I32(value) => Ok(value),
+            _ => Err(Trap::InvalidModule),
+        }
+    }
+
+    fn pop_i64(&mut self) -> Result<i64> {
+        match self.pop()? {
+            Value::I64(value) => Ok(value),
+            _ => Err(Trap::InvalidModule),
+        }
+    }
+
+    fn pop_n(&mut self, count: usize) -> Result<Vec<Value>> {
+        let start = self
+            .stack
+            .len()
+            .checked_sub(count)
+            .ok_or(Trap::InvalidModule)?;
+
+        Ok(self.stack.split_off(start))
+    }
+
+    fn set_local(&mut self, name: Symbol<'m>, value: Value) -> Result<()> {
+        let local = self.locals.get_mut(&name).ok_or(Trap::InvalidModule)?;
+
+        *local = value;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Trap {
+    DivisionByZero,
+    IntegerOverflow,
+    Unreachable,
+    CallStackExhausted,
+    MemoryOutOfBounds,
+    UndefinedExport,
+    // a host function the interpreter doesn't provide
+    UndefinedImport,
+    // the program called `proc_exit` with this code
+    Exit(i32),
+    // the module would have failed validation
+    InvalidModule,
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Trap::*;
+
+        match self {
+            DivisionByZero => write!(f, "integer divide by zero"),
+            IntegerOverflow => write!(f, "integer overflow"),
+            Unreachable => write!(f, "unreachable code was executed"),
+            CallStackExhausted => write!(f, "call stack exhausted"),
+            MemoryOutOfBounds => write!(f, "out of bounds memory access"),
+            UndefinedExport => write!(f, "there's no exported function with that name"),
+            UndefinedImport => write!(f, "called a host function the interpreter doesn't provide"),
+            Exit(code) => write!(f, "exited with code {}", code),
+            InvalidModule => write!(f, "the module is invalid"),
+        }
+    }
+}
+
+impl std::error::Error for Trap {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use super::*;
+    use std::collections::BTreeMap;
+    use test_case::test_case;
+    use WasmInstr::*;
+    use WasmType::*;
+
+    fn run(body: WasmBlock, x: i32) -> Result<Option<Value>> {
+        let mut module = WasmModule::default();
+
+        module.add_function(
+            WasmFunction::new(
+                "double".into(),
+                vec![("n".into(), I32)],
+                BTreeMap::new(),
+                Some(I32),
+                vec![GetLocal("n".into()), ConstI32(2), MultiplyI32],
+            ),
+            false,
+        );
+
+        module.add_function(
+            WasmFunction::new(
+                "f".into(),
+                vec![("x".into(), I32)],
+                BTreeMap::new(),
+                Some(I32),
+                body,
+            ),
+            true,
+        );
+
+        Interpreter::new(&module).invoke("f", &[Value::I32(x)])
+    }
+
+    #[test_case(vec![GetLocal("x".into()), ConstI32(3), AddI32], 4, Ok(7); "addition")]
+    #[test_case(vec![GetLocal("x".into()), ConstI32(1), AddI32], i32::MAX, Ok(i32::MIN); "addition wraps")]
+    #[test_case(vec![GetLocal("x".into()), Call("double".into()), ConstI32(1), MinusI32], 5, Ok(9); "call")]
+    #[test_case(vec![ConstI32(7), GetLocal("x".into()), SignedDivideI32], 0, Err(Trap::DivisionByZero); "division by zero")]
+    #[test_case(vec![GetLocal("x".into()), ConstI32(-1), SignedDivideI32], i32::MIN, Err(Trap::IntegerOverflow); "division overflow")]
+    #[test_case(vec![If { result_type: Some(I32), condition: vec![GetLocal("x".into())], then: vec![ConstI32(10)], else_: Some(vec![ConstI32(20)]) }], 1, Ok(10); "if true")]
+    #[test_case(vec![If { result_type: Some(I32), condition: vec![GetLocal("x".into())], then: vec![ConstI32(10)], else_: Some(vec![ConstI32(20)]) }], 0, Ok(20); "if false")]
+    #[test_case(vec![Unreachable], 0, Err(Trap::Unreachable); "unreachable")]
+    #[test_case(vec![GetLocal("x".into()), Return, Unreachable], 3, Ok(3); "early return")]
+    #[test_case(vec![GetLocal("x".into()), ReturnCall("double".into())], 3, Ok(6); "tail call")]
+    #[test_case(vec![GetLocal("x".into()), Call("f".into())], 0, Err(Trap::CallStackExhausted); "infinite recursion")]
+    #[test_case(vec![If { result_type: Some(I32), condition: vec![GetLocal("x".into())], then: vec![GetLocal("x".into()), ConstI32(1), MinusI32, Call("f".into()), ConstI32(1), AddI32], else_: Some(vec![ConstI32(0)]) }], 50_000, Ok(50_000); "deep recursion")]
+    fn bodies(body: WasmBlock, x: i32, expected: Result<i32>) {
+        assert_eq!(run(body, x), expected.map(|value| Some(Value::I32(value))));
+    }
+
+    #[test]
+    fn loops_until_there_is_no_branch() {
+        // counts x down to 0, adding 2 to the total each time
+        let body = vec![Loop {
+            label: "f".into(),
+            result_type: Some(I32),
+            body: vec![If {
+                result_type: Some(I32),
+                condition: vec![GetLocal("x".into()), ConstI32(0), EqualI32],
+                then: vec![GetLocal("total".into())],
+                else_: Some(vec![
+                    GetLocal("x".into()),
+                    ConstI32(1),
+                    MinusI32,
+                    SetLocal("x".into()),
+                    GetLocal("total".into()),
+                    ConstI32(2),
+                    AddI32,
+                    SetLocal("total".into()),
+                    Branch("f".into()),
+                ]),
+            }],
+        }];
+
+        let mut module = WasmModule::default();
+
+        let mut func = WasmFunction::new(
+            "f".into(),
+            vec![("x".into(), I32)],
+            BTreeMap::new(),
+            Some(I32),
+            body,
+        );
+
+        func.add_local_variable("total".into(), I32);
+
+        module.add_function(func, true);
+
+        assert_eq!(
+            Interpreter::new(&module).invoke("f", &[Value::I32(100_000)]),
+            Ok(Some(Value::I32(200_000)))
+        );
+    }
+}
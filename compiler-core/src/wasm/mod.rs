@@ -1,13 +1,20 @@
+use crate::symbol::Symbol;
 pub use format::{Wasm, WasmIndentation};
 pub use instruction::{WasmBlock, WasmInstr, WasmType};
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::{self, Write};
 
+pub mod binary;
 mod format;
 mod instruction;
+pub mod interpreter;
+pub mod peephole;
+pub mod validate;
 
 #[derive(Debug, Default)]
 pub struct WasmModule<'a> {
+    imports: Vec<WasmImport<'a>>,
+    memory: Option<WasmMemory>,
     functions: Vec<WasmFunction<'a>>,
     exports: Vec<WasmExport<'a>>,
 }
@@ -25,16 +32,60 @@ impl<'a> WasmModule<'a> {
             })
         }
     }
-}
 
-impl<'a, Writer: Write> Wasm<Writer> for WasmModule<'a> {
-    fn write_text(&self, w: &mut Writer, format: WasmIndentation) -> fmt::Result {
+    pub fn add_import(&mut self, import: WasmImport<'a>) {
+        self.imports.push(import);
+    }
+
+    // The memory is always exported as "memory", which is where WASI runtimes look for it
+    pub fn set_memory(&mut self, memory: WasmMemory) {
+        self.memory = Some(memory);
+
+        self.exports.push(WasmExport::Memory {
+            exported_name: "memory",
+        });
+    }
+
+    pub fn imports(&self) -> &[WasmImport<'a>] {
+        &self.imports
+    }
+
+    pub fn memory(&self) -> Option<&WasmMemory> {
+        self.memory.as_ref()
+    }
+
+    pub fn functions(&self) -> &[WasmFunction<'a>] {
+        &self.functions
+    }
+
+    pub fn exports(&self) -> &[WasmExport<'a>] {
+        &self.exports
+    }
+
+    // Like `write_text`, but uses already formatted text for the functions in `function_text`
+    pub fn write_text_with<Writer: Write>(
+        &self,
+        w: &mut Writer,
+        format: WasmIndentation,
+        function_text: &HashMap<Symbol<'a>, String>,
+    ) -> fmt::Result {
         write!(w, "(module")?;
 
         let body_format = format.increase_indent();
 
+        for import in &self.imports {
+            import.write_text(w, body_format)?;
+        }
+
+        if let Some(memory) = &self.memory {
+            memory.write_text(w, body_format)?;
+        }
+
         for func in &self.functions {
-            func.write_text(w, body_format)?;
+            match function_text.get(&func.name) {
+                Some(text) => w.write_str(text)?,
+                None => func.write_text(w, body_format)?,
+            }
         }
 
         for export in &self.exports {
@@ -45,20 +96,94 @@ impl<'a, Writer: Write> Wasm<Writer> for WasmModule<'a> {
     }
 }
 
+impl<'a, Writer: Write> Wasm<Writer> for WasmModule<'a> {
+    fn write_text(&self, w: &mut Writer, format: WasmIndentation) -> fmt::Result {
+        self.write_text_with(w, format, &HashMap::new())
+    }
+}
+
+// A function provided by the host
+#[derive(Debug)]
+pub struct WasmImport<'a> {
+    pub module: &'static str,
+    pub field: &'static str,
+    pub name: Symbol<'a>,
+    pub params: Vec<WasmType>,
+    pub return_type: Option<WasmType>,
+}
+
+impl<'a, Writer: Write> Wasm<Writer> for WasmImport<'a> {
+    fn write_text(&self, w: &mut Writer, format: WasmIndentation) -> fmt::Result {
+        format.new_line_with_indent(w)?;
+
+        write!(
+            w,
+            "(import \"{}\" \"{}\" (func ${}",
+            self.module, self.field, self.name
+        )?;
+
+        for wasm_type in &self.params {
+            write!(w, " (param {})", wasm_type.to_wasm_text())?;
+        }
+
+        if let Some(wasm_type) = self.return_type {
+            write!(w, " (result {})", wasm_type.to_wasm_text())?;
+        }
+
+        write!(w, "))")
+    }
+}
+
+pub const PAGE_SIZE: usize = 65536;
+
+// Linear memory, with `data` copied in at `data_offset` when the module is instantiated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WasmMemory {
+    pub pages: u32,
+    pub data_offset: u32,
+    pub data: Vec<u8>,
+}
+
+impl<Writer: Write> Wasm<Writer> for WasmMemory {
+    fn write_text(&self, w: &mut Writer, format: WasmIndentation) -> fmt::Result {
+        format.new_line_with_indent(w)?;
+
+        write!(w, "(memory {})", self.pages)?;
+
+        if self.data.is_empty() {
+            return Ok(());
+        }
+
+        format.new_line_with_indent(w)?;
+
+        write!(w, "(data (i32.const {}) \"", self.data_offset)?;
+
+        for &byte in &self.data {
+            match byte {
+                b'"' | b'\\' => write!(w, "\\{:02x}", byte)?,
+                b' '..=b'~' => w.write_char(byte as char)?,
+                _ => write!(w, "\\{:02x}", byte)?,
+            }
+        }
+
+        write!(w, "\")")
+    }
+}
+
 #[derive(Debug)]
 pub struct WasmFunction<'a> {
-    name: &'a str,
-    params: Vec<&'a str>,
-    local_variables: BTreeSet<&'a str>,
+    name: Symbol<'a>,
+    params: Vec<(Symbol<'a>, WasmType)>,
+    local_variables: BTreeMap<Symbol<'a>, WasmType>,
     return_type: Option<WasmType>,
     body: WasmBlock<'a>,
 }
 
 impl<'a> WasmFunction<'a> {
     pub fn new(
-        name: &'a str,
-        params: Vec<&'a str>,
-        local_variables: BTreeSet<&'a str>,
+        name: Symbol<'a>,
+        params: Vec<(Symbol<'a>, WasmType)>,
+        local_variables: BTreeMap<Symbol<'a>, WasmType>,
         return_type: Option<WasmType>,
         body: WasmBlock<'a>,
     ) -> WasmFunction<'a> {
@@ -71,8 +196,20 @@ impl<'a> WasmFunction<'a> {
         }
     }
 
-    pub fn add_local_variable(&mut self, name: &'a str) {
-        self.local_variables.insert(name);
+    pub fn name(&self) -> Symbol<'a> {
+        self.name
+    }
+
+    pub fn params(&self) -> &[(Symbol<'a>, WasmType)] {
+        &self.params
+    }
+
+    pub fn return_type(&self) -> Option<WasmType> {
+        self.return_type
+    }
+
+    pub fn add_local_variable(&mut self, name: Symbol<'a>, wasm_type: WasmType) {
+        self.local_variables.insert(name, wasm_type);
     }
 }
 
@@ -82,16 +219,16 @@ impl<'a, Writer: Write> Wasm<Writer> for WasmFunction<'a> {
 
         write!(w, "(func ${}", self.name)?;
 
-        for param in &self.params {
-            write!(w, " (param ${} i32)", param)?;
+        for (param, wasm_type) in &self.params {
+            write!(w, " (param ${} {})", param, wasm_type.to_wasm_text())?;
         }
 
         if let Some(wasm_type) = self.return_type {
             write!(w, " (result {})", wasm_type.to_wasm_text())?;
         }
 
-        for local in &self.local_variables {
-            write!(w, " (local ${} i32)", local)?;
+        for (local, wasm_type) in &self.local_variables {
+            write!(w, " (local ${} {})", local, wasm_type.to_wasm_text())?;
         }
 
         let body_format = format.increase_indent();
@@ -107,8 +244,11 @@ impl<'a, Writer: Write> Wasm<Writer> for WasmFunction<'a> {
 #[derive(Debug, Copy, Clone)]
 pub enum WasmExport<'a> {
     Function {
-        wasm_name: &'a str,
-        exported_name: &'a str,
+        wasm_name: Symbol<'a>,
+        exported_name: Symbol<'a>,
+    },
+    Memory {
+        exported_name: &'static str,
     },
 }
 
@@ -123,6 +263,9 @@ impl<'a, Writer: Write> Wasm<Writer> for WasmExport<'a> {
                 wasm_name,
                 exported_name,
             } => write!(w, "(export \"{}\" (func ${}))", exported_name, wasm_name),
+            Memory { exported_name } => {
+                write!(w, "(export \"{}\" (memory 0))", exported_name)
+            }
         }
     }
 }
@@ -150,21 +293,49 @@ mod tests {
         assert_snapshot!(snapshot, out);
     }
 
+    #[test]
+    fn formats_import() {
+        assert_wasm_output_matches(
+            WasmImport {
+                module: "env",
+                field: "log",
+                name: "_log".into(),
+                params: vec![WasmType::I32],
+                return_type: Some(WasmType::I64),
+            },
+            "(import \"env\" \"log\" (func $_log (param i32) (result i64)))",
+        );
+    }
+
+    #[test]
+    fn formats_memory_with_escaped_data() {
+        assert_wasm_output_matches(
+            WasmMemory {
+                pages: 1,
+                data_offset: 8,
+                data: b"a\"\\\n\x00".to_vec(),
+            },
+            "(memory 1)(data (i32.const 8) \"a\\22\\5c\\0a\\00\")",
+        );
+    }
+
     #[test]
     fn formats_empty_function() {
         assert_wasm_output_matches(
-            WasmFunction::new("f", vec![], BTreeSet::new(), None, vec![]),
+            WasmFunction::new("f".into(), vec![], BTreeMap::new(), None, vec![]),
             "(func $f)",
         );
     }
 
     #[test]
     fn formats_single_arg_function() {
+        use WasmType::*;
+
         let func = WasmFunction::new(
-            "my_func",
-            vec!["arg_1", "arg_2"],
-            BTreeSet::new(),
-            Some(WasmType::I32),
+            "my_func".into(),
+            vec![("arg_1".into(), I32), ("arg_2".into(), I32)],
+            BTreeMap::new(),
+            Some(I32),
             vec![],
         );
 
@@ -183,9 +354,9 @@ mod tests {
 
         module.add_function(
             WasmFunction::new(
-                "get_magic_number",
+                "get_magic_number".into(),
                 vec![],
-                BTreeSet::new(),
+                BTreeMap::new(),
                 Some(I32),
                 vec![ConstI32(10), ConstI32(5), AddI32],
             ),
@@ -194,11 +365,11 @@ mod tests {
 
         module.add_function(
             WasmFunction::new(
-                "add",
-                vec!["arg_1", "arg_2"],
-                BTreeSet::new(),
+                "add".into(),
+                vec![("arg_1".into(), I32), ("arg_2".into(), I32)],
+                BTreeMap::new(),
                 Some(I32),
-                vec![GetLocal("arg_1"), GetLocal("arg_2"), AddI32],
+                vec![GetLocal("arg_1".into()), GetLocal("arg_2".into()), AddI32],
             ),
             true,
         );
@@ -206,12 +377,33 @@ mod tests {
         assert_wasm_snapshot_matches("module with two simple functions", module);
     }
 
+    #[test]
+    fn formats_function_with_typed_locals() {
+        use WasmInstr::*;
+        use WasmType::*;
+
+        let mut func = WasmFunction::new(
+            "widen".into(),
+            vec![("x".into(), I64)],
+            BTreeMap::new(),
+            Some(I64),
+            vec![GetLocal("x".into()), ConstI64(3_000_000_000), MultiplyI64],
+        );
+
+        func.add_local_variable("y".into(), I32);
+
+        assert_wasm_output_matches(
+            func,
+            "(func $widen (param $x i64) (result i64) (local $y i32)\n  local.get $x\n  i64.const 3000000000\n  i64.mul)",
+        );
+    }
+
     #[test]
     fn formats_simple_export() {
         assert_wasm_output_matches(
             WasmExport::Function {
-                exported_name: "add",
-                wasm_name: "add_em",
+                exported_name: "add".into(),
+                wasm_name: "add_em".into(),
             },
             "(export \"add\" (func $add_em))",
         );
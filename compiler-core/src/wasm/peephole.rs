@@ -0,0 +1,265 @@
+use super::{WasmBlock, WasmInstr, WasmModule};
+use crate::symbol::Symbol;
+use std::collections::HashMap;
+
+// param count and whether there's a result, for working out the stack effect of calls
+type Signatures<'a> = HashMap<Symbol<'a>, (usize, bool)>;
+
+pub fn optimise_module(module: &mut WasmModule) {
+    let imported = module
+        .imports
+        .iter()
+        .map(|i| (i.name, (i.params.len(), i.return_type.is_some())));
+
+    let signatures: Signatures = module
+        .functions
+        .iter()
+        .map(|f| (f.name, (f.params.len(), f.return_type.is_some())))
+        .chain(imported)
+        .collect();
+
+    for func in &mut module.functions {
+        optimise_block(&mut func.body, &signatures);
+    }
+}
+
+fn optimise_block(block: &mut WasmBlock, signatures: &Signatures) {
+    for instr in block.iter_mut() {
+        if let WasmInstr::If {
+            condition,
+            then,
+            else_,
+            ..
+        } = instr
+        {
+            optimise_block(condition, signatures);
+            optimise_block(then, signatures);
+
+            if let Some(else_) = else_ {
+                optimise_block(else_, signatures);
+            }
+        } else if let WasmInstr::Loop { body, .. } = instr {
+            optimise_block(body, signatures);
+        }
+    }
+
+    remove_dead_code(block);
+    tee_locals(block);
+    negations(block, signatures);
+}
+
+// Nothing after a `return`, `unreachable` or branch in the same block can ever run
+fn remove_dead_code(block: &mut WasmBlock) {
+    use WasmInstr::*;
+
+    let end = block
+        .iter()
+        .position(|instr| matches!(instr, Return | Unreachable | Branch(_) | ReturnCall(_)));
+
+    if let Some(end) = end {
+        block.truncate(end + 1);
+    }
+}
+
+// `local.set $x; local.get $x` -> `local.tee $x`
+fn tee_locals(block: &mut WasmBlock) {
+    let mut i = 0;
+
+    while i + 1 < block.len() {
+        match (&block[i], &block[i + 1]) {
+            (WasmInstr::SetLocal(set), WasmInstr::GetLocal(get)) if set == get => {
+                block[i] = WasmInstr::TeeLocal(*set);
+                block.remove(i + 1);
+            }
+            _ => {}
+        }
+
+        i += 1;
+    }
+}
+
+// `<x>; i32.const -1; i32.mul` -> `i32.const 0; <x>; i32.sub`
+fn negations(block: &mut WasmBlock, signatures: &Signatures) {
+    use WasmInstr::*;
+
+    let mut i = 0;
+
+    while i + 1 < block.len() {
+        let replacement = match (&block[i], &block[i + 1]) {
+            (ConstI32(-1), MultiplyI32) => Some((ConstI32(0), MinusI32)),
+            (ConstI64(-1), MultiplyI64) => Some((ConstI64(0), MinusI64)),
+            _ => None,
+        };
+
+        if let Some((zero, subtract)) = replacement {
+            if let Some(start) = operand_start(&block[..i], signatures) {
+                block[i + 1] = subtract;
+                block.remove(i);
+                block.insert(start, zero);
+            }
+        }
+
+        i += 1;
+    }
+}
+
+// Works backwards to find where the instructions producing the value on top of the stack begin
+fn operand_start(block: &[WasmInstr], signatures: &Signatures) -> Option<usize> {
+    let mut needed = 1;
+
+    for (i, instr) in block.iter().enumerate().rev() {
+        let (pops, pushes) = stack_effect(instr, signatures)?;
+
+        needed = (needed + pops).checked_sub(pushes)?;
+
+        if needed == 0 {
+            return Some(i);
+        }
+    }
+
+    None
+}
+
+fn stack_effect(instr: &WasmInstr, signatures: &Signatures) -> Option<(usize, usize)> {
+    use WasmInstr::*;
+
+    let effect = match instr {
+        GetLocal(_) | ConstI32(_) | ConstI64(_) | ConstF32(_) => (0, 1),
+        SetLocal(_) | Drop => (1, 0),
+        TeeLocal(_) => (1, 1),
+        AddI32 | MinusI32 | MultiplyI32 | SignedDivideI32 | EqualI32 | AddI64 | MinusI64
+        | MultiplyI64 | SignedDivideI64 | EqualI64 => (2, 1),
+        Call(name) => {
+            let &(params, has_result) = signatures.get(name)?;
+
+            (params, has_result as usize)
+        }
+        // the condition is nested inside the if, so is already balanced
+        If { result_type, .. } | Loop { result_type, .. } => (0, result_type.is_some() as usize),
+        Return | Unreachable | Branch(_) | ReturnCall(_) => return None,
+    };
+
+    Some(effect)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use super::*;
+    use insta::assert_snapshot;
+    use std::collections::BTreeMap;
+    use test_case::test_case;
+    use WasmInstr::*;
+    use WasmType::*;
+
+    #[test_case(
+        vec![
+            GetLocal("x".into()),
+            ConstI32(2),
+            AddI32,
+            SetLocal("y".into()),
+            GetLocal("y".into()),
+            SetLocal("z".into()),
+            GetLocal("x".into()),
+        ];
+        "set then get becomes tee"
+    )]
+    #[test_case(
+        vec![
+            GetLocal("x".into()),
+            SetLocal("y".into()),
+            GetLocal("x".into()),
+        ];
+        "set then get of another local is unchanged"
+    )]
+    #[test_case(
+        vec![
+            GetLocal("x".into()),
+            GetLocal("x".into()),
+            ConstI32(3),
+            AddI32,
+            Call("double".into()),
+            ConstI32(-1),
+            MultiplyI32,
+            AddI32,
+        ];
+        "multiply by minus one becomes subtraction"
+    )]
+    #[test_case(
+        vec![If {
+            result_type: Some(I32),
+            condition: vec![GetLocal("x".into()), ConstI32(0), EqualI32],
+            then: vec![ConstI32(1)],
+            else_: Some(vec![GetLocal("x".into()), ConstI32(-1), MultiplyI32]),
+        }];
+        "multiply by minus one in if becomes subtraction"
+    )]
+    #[test_case(
+        vec![
+            GetLocal("x".into()),
+            Return,
+            GetLocal("x".into()),
+            ConstI32(1),
+            AddI32,
+        ];
+        "code after return is removed"
+    )]
+    #[test_case(
+        vec![Loop {
+            label: "f".into(),
+            result_type: Some(I32),
+            body: vec![
+                GetLocal("x".into()),
+                SetLocal("x".into()),
+                GetLocal("x".into()),
+                Branch("f".into()),
+                ConstI32(1),
+            ],
+        }];
+        "code after branch is removed"
+    )]
+    #[test_case(
+        vec![If {
+            result_type: Some(I32),
+            condition: vec![GetLocal("x".into()), ConstI32(0), EqualI32],
+            then: vec![Unreachable, ConstI32(1)],
+            else_: Some(vec![GetLocal("x".into())]),
+        }];
+        "code after unreachable is removed"
+    )]
+    fn optimised(body: WasmBlock) {
+        let mut module = WasmModule::default();
+
+        module.add_function(
+            WasmFunction::new(
+                "double".into(),
+                vec![("n".into(), I32)],
+                BTreeMap::new(),
+                Some(I32),
+                vec![],
+            ),
+            false,
+        );
+
+        module.add_function(
+            WasmFunction::new(
+                "f".into(),
+                vec![("x".into(), I32)],
+                BTreeMap::new(),
+                Some(I32),
+                body,
+            ),
+            true,
+        );
+
+        optimise_module(&mut module);
+
+        let mut out = String::new();
+
+        module
+            .write_text(&mut out, WasmIndentation::default())
+            .unwrap();
+
+        assert_snapshot!(out);
+    }
+}
@@ -0,0 +1,513 @@
+use super::{WasmBlock, WasmExport, WasmFunction, WasmInstr, WasmModule, WasmType};
+use crate::symbol::Symbol;
+use std::collections::HashMap;
+use std::fmt;
+
+pub type Result<'a, X> = std::result::Result<X, ValidationError<'a>>;
+
+struct Signature {
+    params: Vec<WasmType>,
+    result: Option<WasmType>,
+}
+
+// Checks the module is one a wasm runtime would accept, so codegen bugs are caught here
+// instead of when the text is loaded
+pub fn validate_module<'a>(module: &WasmModule<'a>) -> Result<'a, ()> {
+    let mut signatures = HashMap::with_capacity(module.imports.len() + module.functions.len());
+
+    for import in &module.imports {
+        let signature = Signature {
+            params: import.params.clone(),
+            result: import.return_type,
+        };
+
+        if signatures.insert(import.name, signature).is_some() {
+            return Err(ValidationError::DuplicateFunction(import.name));
+        }
+    }
+
+    for func in &module.functions {
+        let signature = Signature {
+            params: func.params.iter().map(|&(_, t)| t).collect(),
+            result: func.return_type,
+        };
+
+        if signatures.insert(func.name, signature).is_some() {
+            return Err(ValidationError::DuplicateFunction(func.name));
+        }
+    }
+
+    for func in &module.functions {
+        FunctionValidator::new(func, &signatures)?.validate()?;
+    }
+
+    for export in &module.exports {
+        match *export {
+            WasmExport::Function { wasm_name, .. } => {
+                if !signatures.contains_key(&wasm_name) {
+                    return Err(ValidationError::UndefinedExport(wasm_name.as_str()));
+                }
+            }
+            WasmExport::Memory { exported_name } => {
+                if module.memory.is_none() {
+                    return Err(ValidationError::UndefinedExport(exported_name));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+struct FunctionValidator<'m, 'a> {
+    func: &'m WasmFunction<'a>,
+    signatures: &'m HashMap<Symbol<'a>, Signature>,
+    locals: HashMap<Symbol<'a>, WasmType>,
+    labels: Vec<Symbol<'a>>,
+}
+
+// The values on the stack in the current block. After an instruction that never falls through
+// the stack is polymorphic, so popping from it is always allowed
+#[derive(Default)]
+struct OperandStack {
+    values: Vec<WasmType>,
+    unreachable: bool,
+}
+
+impl<'m, 'a> FunctionValidator<'m, 'a> {
+    fn new(
+        func: &'m WasmFunction<'a>,
+        signatures: &'m HashMap<Symbol<'a>, Signature>,
+    ) -> Result<'a, Self> {
+        let mut locals = HashMap::new();
+
+        let all_locals = func
+            .params
+            .iter()
+            .copied()
+            .chain(func.local_variables.iter().map(|(&name, &t)| (name, t)));
+
+        for (local, wasm_type) in all_locals {
+            if locals.insert(local, wasm_type).is_some() {
+                return Err(ValidationError::DuplicateLocal {
+                    function: func.name,
+                    local,
+                });
+            }
+        }
+
+        Ok(FunctionValidator {
+            func,
+            signatures,
+            locals,
+            labels: vec![],
+        })
+    }
+
+    fn validate(&mut self) -> Result<'a, ()> {
+        self.block(&self.func.body, self.func.return_type)
+    }
+
+    fn block(&mut self, block: &'m WasmBlock<'a>, result: Option<WasmType>) -> Result<'a, ()> {
+        let mut stack = OperandStack::default();
+
+        for instr in block {
+            self.instruction(instr, &mut stack)?;
+        }
+
+        if let Some(wasm_type) = result {
+            self.pop(&mut stack, Some(wasm_type))?;
+        }
+
+        match stack.values.len() {
+            0 => Ok(()),
+            count => Err(ValidationError::UnusedValues {
+                function: self.func.name,
+                count,
+            }),
+        }
+    }
+
+    fn instruction(
+        &mut self,
+        instr: &'m WasmInstr<'a>,
+        stack: &mut OperandStack,
+    ) -> Result<'a, ()> {
+        use WasmInstr::*;
+        use WasmType::*;
+
+        match instr {
+            GetLocal(name) => {
+                let wasm_type = self.local(*name)?;
+
+                stack.values.push(wasm_type);
+            }
+            SetLocal(name) => {
+                let wasm_type = self.local(*name)?;
+
+                self.pop(stack, Some(wasm_type))?;
+            }
+            TeeLocal(name) => {
+                let wasm_type = self.local(*name)?;
+
+                self.pop(stack, Some(wasm_type))?;
+                stack.values.push(wasm_type);
+            }
+            ConstI32(_) => stack.values.push(I32),
+            ConstI64(_) => stack.values.push(I64),
+            ConstF32(_) => stack.values.push(F32),
+            AddI32 | MinusI32 | MultiplyI32 | SignedDivideI32 | EqualI32 => {
+                self.binary_op(stack, I32, I32)?
+            }
+            AddI64 | MinusI64 | MultiplyI64 | SignedDivideI64 => self.binary_op(stack, I64, I64)?,
+            EqualI64 => self.binary_op(stack, I64, I32)?,
+            Call(name) => {
+                let result = self.call(stack, *name)?;
+
+                stack.values.extend(result);
+            }
+            ReturnCall(name) => {
+                let result = self.call(stack, *name)?;
+
+                self.expect(self.func.return_type, result)?;
+                self.end_reachable(stack);
+            }
+            Branch(label) => {
+                // the only blocks with labels are loops, which don't take any values
+                if !self.labels.contains(label) {
+                    return Err(ValidationError::UndefinedLabel {
+                        function: self.func.name,
+                        label: *label,
+                    });
+                }
+
+                self.end_reachable(stack);
+            }
+            Drop => {
+                self.pop(stack, None)?;
+            }
+            Return => {
+                if let Some(wasm_type) = self.func.return_type {
+                    self.pop(stack, Some(wasm_type))?;
+                }
+
+                self.end_reachable(stack);
+            }
+            Unreachable => self.end_reachable(stack),
+            If {
+                result_type,
+                condition,
+                then,
+                else_,
+            } => {
+                self.block(condition, Some(I32))?;
+                self.block(then, *result_type)?;
+
+                match else_ {
+                    Some(else_) => self.block(else_, *result_type)?,
+                    None if result_type.is_some() => {
+                        return Err(ValidationError::MissingElse {
+                            function: self.func.name,
+                        })
+                    }
+                    None => {}
+                }
+
+                stack.values.extend(*result_type);
+            }
+            Loop {
+                label,
+                result_type,
+                body,
+            } => {
+                self.labels.push(*label);
+                self.block(body, *result_type)?;
+                self.labels.pop();
+
+                stack.values.extend(*result_type);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn local(&self, local: Symbol<'a>) -> Result<'a, WasmType> {
+        self.locals
+            .get(&local)
+            .copied()
+            .ok_or(ValidationError::UndefinedLocal {
+                function: self.func.name,
+                local,
+            })
+    }
+
+    fn binary_op(
+        &self,
+        stack: &mut OperandStack,
+        operand: WasmType,
+        result: WasmType,
+    ) -> Result<'a, ()> {
+        self.pop(stack, Some(operand))?;
+        self.pop(stack, Some(operand))?;
+
+        stack.values.push(result);
+
+        Ok(())
+    }
+
+    fn call(&self, stack: &mut OperandStack, called: Symbol<'a>) -> Result<'a, Option<WasmType>> {
+        let signature = self
+            .signatures
+            .get(&called)
+            .ok_or(ValidationError::UndefinedFunction {
+                function: self.func.name,
+                called,
+            })?;
+
+        for &wasm_type in signature.params.iter().rev() {
+            self.pop(stack, Some(wasm_type))?;
+        }
+
+        Ok(signature.result)
+    }
+
+    // Pops a value, checking its type if one is expected.
+    // Returns `None` when popping from the polymorphic stack of unreachable code
+    fn pop(
+        &self,
+        stack: &mut OperandStack,
+        expected: Option<WasmType>,
+    ) -> Result<'a, Option<WasmType>> {
+        let found = match stack.values.pop() {
+            Some(found) => found,
+            None if stack.unreachable => return Ok(None),
+            None => {
+                return Err(ValidationError::StackUnderflow {
+                    function: self.func.name,
+                })
+            }
+        };
+
+        if let Some(expected) = expected {
+            self.expect(Some(expected), Some(found))?;
+        }
+
+        Ok(Some(found))
+    }
+
+    fn expect(&self, expected: Option<WasmType>, found: Option<WasmType>) -> Result<'a, ()> {
+        if expected == found {
+            Ok(())
+        } else {
+            Err(ValidationError::TypeMismatch {
+                function: self.func.name,
+                expected,
+                found,
+            })
+        }
+    }
+
+    fn end_reachable(&self, stack: &mut OperandStack) {
+        stack.values.clear();
+        stack.unreachable = true;
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum ValidationError<'a> {
+    DuplicateFunction(Symbol<'a>),
+    DuplicateLocal {
+        function: Symbol<'a>,
+        local: Symbol<'a>,
+    },
+    UndefinedExport(&'a str),
+    UndefinedLocal {
+        function: Symbol<'a>,
+        local: Symbol<'a>,
+    },
+    UndefinedFunction {
+        function: Symbol<'a>,
+        called: Symbol<'a>,
+    },
+    UndefinedLabel {
+        function: Symbol<'a>,
+        label: Symbol<'a>,
+    },
+    StackUnderflow {
+        function: Symbol<'a>,
+    },
+    TypeMismatch {
+        function: Symbol<'a>,
+        expected: Option<WasmType>,
+        found: Option<WasmType>,
+    },
+    UnusedValues {
+        function: Symbol<'a>,
+        count: usize,
+    },
+    MissingElse {
+        function: Symbol<'a>,
+    },
+}
+
+impl<'a> fmt::Display for ValidationError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ValidationError::*;
+
+        // `None` is an empty stack or block type
+        let type_text = |t: &Option<WasmType>| t.map_or("nothing", WasmType::to_wasm_text);
+
+        match self {
+            DuplicateFunction(name) => write!(f, "there's more than one function `{}`", name),
+            DuplicateLocal { function, local } => {
+                write!(f, "`{}` has more than one local `{}`", function, local)
+            }
+            UndefinedExport(name) => write!(f, "there's no function `{}` to export", name),
+            UndefinedLocal { function, local } => {
+                write!(f, "`{}` uses an undefined local `{}`", function, local)
+            }
+            UndefinedFunction { function, called } => {
+                write!(f, "`{}` calls an undefined function `{}`", function, called)
+            }
+            UndefinedLabel { function, label } => {
+                write!(
+                    f,
+                    "`{}` branches to an undefined label `{}`",
+                    function, label
+                )
+            }
+            StackUnderflow { function } => {
+                write!(f, "`{}` uses more values than are on the stack", function)
+            }
+            TypeMismatch {
+                function,
+                expected,
+                found,
+            } => write!(
+                f,
+                "expected {} but found {} in `{}`",
+                type_text(expected),
+                type_text(found),
+                function
+            ),
+            UnusedValues { function, count } => {
+                write!(
+                    f,
+                    "`{}` leaves {} unused values on the stack",
+                    function, count
+                )
+            }
+            MissingElse { function } => {
+                write!(f, "an `if` with a result in `{}` has no `else`", function)
+            }
+        }
+    }
+}
+
+impl<'a> std::error::Error for ValidationError<'a> {}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use super::*;
+    use crate::analyser::analyse;
+    use crate::code_gen::ast_to_wasm;
+    use crate::parser::ParseSession;
+    use insta::assert_debug_snapshot;
+    use std::collections::BTreeMap;
+    use std::fs;
+    use test_case::test_case;
+    use WasmInstr::*;
+    use WasmType::*;
+
+    #[test_case("example_program")]
+    #[test_case("fibonacci")]
+    #[test_case("unit_functions")]
+    #[test_case("booleans")]
+    #[test_case("int64")]
+    #[test_case("constants")]
+    #[test_case("inlining")]
+    #[test_case("tail_calls")]
+    fn generated_modules_are_valid(name: &str) {
+        let contents = fs::read_to_string(format!("src/fixtures/{}.lang", name)).unwrap();
+
+        let session = ParseSession::new();
+
+        let ast = session.parse(&contents).unwrap();
+
+        let analysis = analyse(&ast).unwrap();
+
+        let mut module = ast_to_wasm(&ast, &analysis).unwrap();
+
+        validate_module(&module).unwrap();
+
+        peephole::optimise_module(&mut module);
+
+        validate_module(&module).unwrap();
+    }
+
+    fn validate_body(body: WasmBlock) -> Result<()> {
+        let mut module = WasmModule::default();
+
+        module.add_function(
+            WasmFunction::new(
+                "double".into(),
+                vec![("n".into(), I32)],
+                BTreeMap::new(),
+                Some(I32),
+                vec![GetLocal("n".into()), ConstI32(2), MultiplyI32],
+            ),
+            false,
+        );
+
+        module.add_function(
+            WasmFunction::new(
+                "f".into(),
+                vec![("x".into(), I32)],
+                BTreeMap::new(),
+                Some(I32),
+                body,
+            ),
+            true,
+        );
+
+        validate_module(&module)
+    }
+
+    #[test_case(vec![GetLocal("x".into()), AddI32]; "stack underflow")]
+    #[test_case(vec![GetLocal("x".into()), GetLocal("x".into())]; "unused values")]
+    #[test_case(vec![ConstF32(1.0), GetLocal("x".into()), AddI32]; "float fed to int op")]
+    #[test_case(vec![ConstI64(1)]; "wrong result type")]
+    #[test_case(vec![GetLocal("y".into())]; "undefined local")]
+    #[test_case(vec![GetLocal("x".into()), Call("triple".into())]; "undefined function")]
+    #[test_case(vec![Branch("f".into())]; "undefined label")]
+    #[test_case(vec![If { result_type: Some(I32), condition: vec![GetLocal("x".into())], then: vec![GetLocal("x".into())], else_: None }]; "if without else")]
+    #[test_case(vec![If { result_type: Some(I32), condition: vec![ConstI64(0)], then: vec![GetLocal("x".into())], else_: Some(vec![GetLocal("x".into())]) }]; "non i32 condition")]
+    #[test_case(vec![GetLocal("x".into()), Call("double".into()), Return, AddI32]; "unreachable code")]
+    #[test_case(vec![Loop { label: "f".into(), result_type: Some(I32), body: vec![GetLocal("x".into()), Call("double".into()), SetLocal("x".into()), Branch("f".into())] }]; "branch to loop")]
+    fn bodies(body: WasmBlock) {
+        assert_debug_snapshot!(validate_body(body));
+    }
+
+    #[test_case(vec![GetLocal("x".into()), AddI32], "`f` uses more values than are on the stack")]
+    #[test_case(vec![ConstI64(1)], "expected i32 but found i64 in `f`")]
+    #[test_case(vec![GetLocal("x".into()), Call("triple".into())], "`f` calls an undefined function `triple`")]
+    fn messages(body: WasmBlock, expected: &str) {
+        assert_eq!(validate_body(body).unwrap_err().to_string(), expected);
+    }
+
+    #[test]
+    fn exports_must_exist() {
+        let mut module = WasmModule::default();
+
+        module.exports.push(WasmExport::Function {
+            wasm_name: "missing".into(),
+            exported_name: "missing".into(),
+        });
+
+        assert!(matches!(
+            validate_module(&module),
+            Err(ValidationError::UndefinedExport(name)) if name == "missing"
+        ));
+    }
+}
@@ -1,37 +1,338 @@
-use clap::{App, Arg};
-use compiler_core::code_gen::*;
-use compiler_core::parser::parse;
-use compiler_core::wasm::*;
+use anyhow::{anyhow, bail};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use compiler_core::cache::CompileCache;
+use compiler_core::compiler::{CompileOptions, Compiler, Target};
+use compiler_core::diagnostics::{diagnostics, Diagnostic, Severity};
+use compiler_core::parser::ParseSession;
+use compiler_core::wasm::interpreter::{Interpreter, Trap, Value};
+use compiler_core::OptimisationLevel;
 use std::fs::{self, create_dir_all};
+use std::time::{Duration, SystemTime};
+
+// How often a watched file is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// How long a watched file has to stay the same before it's rebuilt, so a burst of saves only
+// causes one build
+const DEBOUNCE: Duration = Duration::from_millis(200);
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let matches = App::new("Lang")
         .version("0.1.0")
         .about("Rust version of lang")
-        .arg(
-            Arg::with_name("file")
-                .takes_value(true)
-                .required(true)
-                .help("A cool file"),
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .args(&compile_args())
+        .subcommand(
+            SubCommand::with_name("build")
+                .about("Compiles the file to dist/out.wat, the same as giving no subcommand")
+                .args(&compile_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Compiles the file and prints what its main function returns")
+                .args(&compile_args())
+                .arg(
+                    Arg::with_name("args")
+                        .multiple(true)
+                        .allow_hyphen_values(true)
+                        .help("Arguments for main, which are i32s unless they end in i64"),
+                ),
         )
         .get_matches();
 
+    let (command, matches) = match matches.subcommand() {
+        ("run", Some(run)) => (Command::Run, run),
+        ("build", Some(build)) => (Command::Build, build),
+        _ => (Command::Build, &matches),
+    };
+
     let file = matches.value_of("file").unwrap();
 
+    // the cache only knows the standard pipeline for each optimisation level
+    let customised = options(matches)
+        != CompileOptions {
+            optimisation: optimisation(matches),
+            ..Default::default()
+        };
+
+    let mut cache = if command == Command::Build && !customised && !matches.is_present("no-cache") {
+        Some(CompileCache::persistent(
+            matches.value_of("cache-dir").unwrap(),
+        )?)
+    } else {
+        None
+    };
+
+    if matches.is_present("watch") {
+        return watch(file, command, matches, cache.as_mut()).await;
+    }
+
     let source = tokio::fs::read_to_string(file).await?;
 
-    let ast = parse(&source).unwrap();
+    execute(command, &source, matches, cache.as_mut())
+}
+
+fn compile_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("file")
+            .takes_value(true)
+            .required(true)
+            .help("A cool file"),
+        Arg::with_name("opt-level")
+            .short("O")
+            .long("opt-level")
+            .takes_value(true)
+            .possible_values(&["0", "1"])
+            .default_value("1")
+            .help("How much to optimise the generated wasm"),
+        Arg::with_name("target")
+            .long("target")
+            .takes_value(true)
+            .possible_values(&["wat", "wasm", "wasi", "js"])
+            .default_value("wat")
+            .help("What to output besides dist/out.wat. wasm adds the binary module, wasi a binary that starts from main and can print, and js a binary with an ES module and TypeScript declarations for loading it"),
+        Arg::with_name("no-inline")
+            .long("no-inline")
+            .help("Don't inline small functions into their callers"),
+        Arg::with_name("enable-tail-call")
+            .long("enable-tail-call")
+            .help("Use `return_call` for tail calls, which needs a runtime supporting it"),
+        Arg::with_name("keep-unused")
+            .long("keep-unused")
+            .help("Keep functions that can't be reached from an export or main"),
+        Arg::with_name("debug-info")
+            .short("g")
+            .long("debug-info")
+            .help("Keep the names of functions and locals in the binary module"),
+        Arg::with_name("warnings-as-errors")
+            .long("warnings-as-errors")
+            .help("Fail to compile if there are any warnings"),
+        Arg::with_name("cache-dir")
+            .long("cache-dir")
+            .takes_value(true)
+            .default_value("target/lang-cache")
+            .help("Where to keep compiled code for reuse in later builds"),
+        Arg::with_name("no-cache")
+            .long("no-cache")
+            .help("Compile everything from scratch, without reading or writing the cache"),
+        Arg::with_name("watch")
+            .short("w")
+            .long("watch")
+            .help("Keep going, and compile again whenever the file changes"),
+    ]
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Command {
+    Build,
+    Run,
+}
 
-    let wasm = ast_to_wasm(&ast).unwrap();
+fn execute(
+    command: Command,
+    source: &str,
+    matches: &ArgMatches,
+    cache: Option<&mut CompileCache>,
+) -> anyhow::Result<()> {
+    match command {
+        Command::Build => build(source, matches, cache),
+        Command::Run => run(source, matches),
+    }
+}
 
+fn build(
+    source: &str,
+    matches: &ArgMatches,
+    cache: Option<&mut CompileCache>,
+) -> anyhow::Result<()> {
     create_dir_all("dist")?;
 
-    let mut out = String::new();
+    let compiler = Compiler::new(options(matches));
+
+    // only lives for this build, so watching doesn't keep the names from every earlier one
+    let session = ParseSession::new();
+
+    if let Some(cache) = cache {
+        report(matches, &compiler.warnings(source))?;
+
+        let out = match cache.compile(&session, source, optimisation(matches)) {
+            Ok(out) => out,
+            Err(error) => return report(matches, &diagnostics(source, &error)),
+        };
 
-    wasm.write_text(&mut out, WasmIndentation::default())?;
+        fs::write("dist/out.wat", out)?;
 
-    fs::write("dist/out.wat", out)?;
+        return Ok(());
+    }
+
+    let output = compiler.compile(&session, source);
+
+    report(matches, &output.diagnostics)?;
+
+    let artifacts = output
+        .artifacts
+        .ok_or_else(|| anyhow!("no output was generated"))?;
+
+    fs::write("dist/out.wat", artifacts.wat)?;
+
+    if let Some(binary) = artifacts.wasm {
+        fs::write("dist/out.wasm", binary)?;
+    }
+
+    if let Some(glue) = artifacts.glue {
+        fs::write("dist/out.js", glue.js)?;
+        fs::write("dist/out.d.ts", glue.declarations)?;
+    }
 
     Ok(())
 }
+
+fn run(source: &str, matches: &ArgMatches) -> anyhow::Result<()> {
+    let compiler = Compiler::new(options(matches));
+
+    let session = ParseSession::new();
+
+    let output = compiler.compile(&session, source);
+
+    report(matches, &output.diagnostics)?;
+
+    let wasm = match output.artifacts {
+        Some(artifacts) => artifacts.module,
+        None => bail!("no output was generated"),
+    };
+
+    if compiler.options().target == Target::Wasi {
+        let interpreter = Interpreter::new(&wasm);
+
+        let result = interpreter.invoke("_start", &[]);
+
+        print!("{}", interpreter.stdout());
+
+        return match result {
+            Ok(_) | Err(Trap::Exit(0)) => Ok(()),
+            Err(Trap::Exit(code)) => std::process::exit(code),
+            Err(trap) => bail!("_start trapped: {}", trap),
+        };
+    }
+
+    let args = matches
+        .values_of("args")
+        .into_iter()
+        .flatten()
+        .map(|arg| match arg.strip_suffix("i64") {
+            Some(int) => int.parse().map(Value::I64),
+            None => arg.parse().map(Value::I32),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match Interpreter::new(&wasm).invoke("main", &args) {
+        Ok(Some(Value::I32(value))) => println!("{}", value),
+        Ok(Some(Value::I64(value))) => println!("{}", value),
+        Ok(Some(Value::F32(value))) => println!("{}", value),
+        Ok(None) => {}
+        Err(trap) => bail!("main trapped: {}", trap),
+    }
+
+    Ok(())
+}
+
+// Prints each diagnostic as `file:line:column: severity: message`, failing if any were errors
+fn report(matches: &ArgMatches, diagnostics: &[Diagnostic]) -> anyhow::Result<()> {
+    let file = matches.value_of("file").unwrap();
+
+    for diagnostic in diagnostics {
+        match &diagnostic.location {
+            Some(location) => eprint!(
+                "{}:{}:{}: ",
+                file, location.start.line, location.start.column
+            ),
+            None => eprint!("{}: ", file),
+        }
+
+        eprintln!("{}: {}", diagnostic.severity.name(), diagnostic.message);
+    }
+
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .count();
+
+    match errors {
+        0 => Ok(()),
+        1 => bail!("couldn't compile {} due to an error", file),
+        _ => bail!("couldn't compile {} due to {} errors", file, errors),
+    }
+}
+
+fn optimisation(matches: &ArgMatches) -> OptimisationLevel {
+    matches.value_of("opt-level").unwrap().parse().unwrap()
+}
+
+fn options(matches: &ArgMatches) -> CompileOptions {
+    CompileOptions {
+        target: matches.value_of("target").unwrap().parse().unwrap(),
+        optimisation: optimisation(matches),
+        inline_functions: !matches.is_present("no-inline"),
+        remove_unused_functions: !matches.is_present("keep-unused"),
+        tail_call: matches.is_present("enable-tail-call"),
+        debug_info: matches.is_present("debug-info"),
+        warnings_as_errors: matches.is_present("warnings-as-errors"),
+        ..Default::default()
+    }
+}
+
+// The language doesn't have imports yet, so the file itself is all there is to watch
+async fn watch(
+    file: &str,
+    command: Command,
+    matches: &ArgMatches<'_>,
+    mut cache: Option<&mut CompileCache>,
+) -> anyhow::Result<()> {
+    // the first poll always builds, so a file that's missing from the start is reported
+    let mut built = None;
+
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let mut stamp = file_stamp(file).await;
+
+        if built == Some(stamp) {
+            continue;
+        }
+
+        loop {
+            tokio::time::sleep(DEBOUNCE).await;
+
+            let latest = file_stamp(file).await;
+
+            if latest == stamp {
+                break;
+            }
+
+            stamp = latest;
+        }
+
+        built = Some(stamp);
+
+        let result = match tokio::fs::read_to_string(file).await {
+            Ok(source) => execute(command, &source, matches, cache.as_deref_mut()),
+            Err(error) => Err(anyhow!("couldn't read {}: {}", file, error)),
+        };
+
+        match result {
+            Ok(()) if command == Command::Build => println!("Built {}", file),
+            Ok(()) => {}
+            Err(error) => eprintln!("Error: {}", error),
+        }
+    }
+}
+
+// Changes when the file is written to, as long as it still exists
+async fn file_stamp(file: &str) -> Option<(SystemTime, u64)> {
+    let metadata = tokio::fs::metadata(file).await.ok()?;
+
+    Some((metadata.modified().ok()?, metadata.len()))
+}
@@ -0,0 +1,45 @@
+#![no_main]
+use compiler_core::compiler::{CompileOptions, Compiler, Target};
+use compiler_core::parser::ParseSession;
+use compiler_core::OptimisationLevel;
+use libfuzzer_sys::fuzz_target;
+use wasmtime::{Engine, Module};
+
+// the first byte picks the options, the rest is the source
+fuzz_target!(|data: &[u8]| {
+    let (&options, source) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+
+    let source = match std::str::from_utf8(source) {
+        Ok(source) => source,
+        Err(_) => return,
+    };
+
+    let compiler = Compiler::new(CompileOptions {
+        target: match options & 0b11 {
+            0 => Target::Wat,
+            1 => Target::Wasm,
+            2 => Target::Wasi,
+            _ => Target::Js,
+        },
+        optimisation: match options & 0b100 {
+            0 => OptimisationLevel::None,
+            _ => OptimisationLevel::Basic,
+        },
+        debug_info: options & 0b1000 != 0,
+        ..Default::default()
+    });
+
+    let session = ParseSession::new();
+
+    let output = compiler.compile(&session, source);
+
+    // whatever the compiler gives back, the runtime has to accept
+    if let Some(wasm) = output.artifacts.and_then(|artifacts| artifacts.wasm) {
+        if let Err(error) = Module::validate(&Engine::default(), &wasm) {
+            panic!("invalid module for {:?}: {}", source, error);
+        }
+    }
+});
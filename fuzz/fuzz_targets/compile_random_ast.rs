@@ -0,0 +1,60 @@
+#![no_main]
+use compiler_core::ast::Arena;
+use compiler_core::compiler::{CompileOptions, Compiler};
+use compiler_core::constant_folding::ConstantFoldingError;
+use compiler_core::wasm::binary::{encode_module, encode_module_with_names};
+use compiler_core::{CompileError, OptimisationLevel};
+use libfuzzer_sys::fuzz_target;
+use wasmtime::{Engine, Module};
+
+mod random_ast;
+
+// the first byte picks the options, the rest makes the program
+fuzz_target!(|data: &[u8]| {
+    let (&options, data) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+
+    // the target only changes what's made from the module, and WASI needs a `main`
+    let options = CompileOptions {
+        optimisation: match options & 0b1 {
+            0 => OptimisationLevel::None,
+            _ => OptimisationLevel::Basic,
+        },
+        inline_functions: options & 0b10 != 0,
+        remove_unused_functions: options & 0b100 != 0,
+        tail_call: options & 0b1000 != 0,
+        debug_info: options & 0b1_0000 != 0,
+        ..Default::default()
+    };
+
+    let arena = Arena::default();
+
+    let ast = random_ast::random_ast(data, &arena);
+
+    // Every program the generator makes is well typed, so all of it should make it to the
+    // runtime, apart from dividing by a constant zero, which the compiler is meant to find
+    let module = match Compiler::new(options.clone()).compile_ast(ast) {
+        Ok(module) => module,
+        Err(CompileError::ConstantFoldingError(ConstantFoldingError::DivisionByZero {
+            ..
+        })) => return,
+        Err(error) => panic!("{:?} with {:?} in {:?}", error, options, ast),
+    };
+
+    let wasm = match options.debug_info {
+        false => encode_module(&module),
+        true => encode_module_with_names(&module),
+    }
+    .unwrap_or_else(|error| panic!("{:?} with {:?} in {:?}", error, options, ast));
+
+    // this version of wasmtime doesn't know about `return_call`
+    if options.tail_call {
+        return;
+    }
+
+    if let Err(error) = Module::validate(&Engine::default(), &wasm) {
+        panic!("invalid module with {:?} for {:?}: {}", options, ast, error);
+    }
+});
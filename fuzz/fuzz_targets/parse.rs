@@ -0,0 +1,19 @@
+#![no_main]
+use compiler_core::cst;
+use compiler_core::parser::ParseSession;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(source) = std::str::from_utf8(data) {
+        let session = ParseSession::new();
+
+        let _ = session.parse(source);
+
+        let parse = cst::parse(source);
+
+        // the tree keeps everything, so it always gives back the source
+        assert_eq!(parse.syntax().text(), source);
+
+        let _ = parse.to_ast(&session);
+    }
+});
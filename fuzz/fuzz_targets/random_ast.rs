@@ -0,0 +1,374 @@
+// Builds a well typed `Ast` from the fuzzer's bytes, so the passes after the parser get
+// programs they should always be able to compile. Once the bytes run out every choice is the
+// first one, which keeps the program small
+use compiler_core::ast::*;
+use compiler_core::operators::BinaryOperator;
+use compiler_core::symbol::Name;
+use compiler_core::tokens::{Constant, Integer};
+use compiler_core::types::Type;
+
+const VALUE_TYPES: [Type; 4] = [Type::Int32, Type::Int64, Type::Float, Type::Bool];
+// code gen can't do anything with floats yet apart from passing them around
+const COMPARABLE_TYPES: [Type; 3] = [Type::Int32, Type::Int64, Type::Bool];
+
+const MAX_FUNCTIONS: usize = 4;
+const MAX_PARAMS: usize = 3;
+const MAX_BLOCK_DEPTH: usize = 3;
+const MAX_EXPRESSION_DEPTH: usize = 4;
+
+pub fn random_ast<'a>(data: &[u8], arena: &'a Arena) -> Ast<'a> {
+    let mut generator = Generator {
+        input: Input(data),
+        arena,
+        functions: Vec::new(),
+        scopes: Vec::new(),
+    };
+
+    let count = 1 + generator.input.below(MAX_FUNCTIONS);
+
+    let statements = arena.alloc_slice((0..count).map(|i| {
+        let last = i + 1 == count;
+
+        // something has to be exported, or there's nothing to keep
+        let exported = last || generator.input.bool();
+
+        TopLevelStatement::Declaration {
+            decl: generator.function(i),
+            exported,
+        }
+    }));
+
+    Ast::new(statements, arena)
+}
+
+struct Input<'d>(&'d [u8]);
+
+impl<'d> Input<'d> {
+    fn byte(&mut self) -> u8 {
+        match self.0.split_first() {
+            Some((&byte, rest)) => {
+                self.0 = rest;
+                byte
+            }
+            None => 0,
+        }
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        self.byte() as usize % n
+    }
+
+    fn bool(&mut self) -> bool {
+        self.byte() & 1 == 1
+    }
+
+    fn bytes<const N: usize>(&mut self) -> [u8; N] {
+        let mut bytes = [0; N];
+
+        for byte in &mut bytes {
+            *byte = self.byte();
+        }
+
+        bytes
+    }
+
+    fn value_type(&mut self) -> Type {
+        VALUE_TYPES[self.below(VALUE_TYPES.len())]
+    }
+}
+
+// What the last statement of a block is, which decides the block's type
+#[derive(Copy, Clone)]
+enum End {
+    Value(Type),
+    // an assignment, a call to a function without a result, or an `if` without one either
+    Unit,
+    // any statement at all, for the branches of an `if` without an else
+    Anything,
+}
+
+struct Generator<'a, 'd> {
+    input: Input<'d>,
+    arena: &'a Arena,
+    // the ones declared so far, which are the only ones that can be called
+    functions: Vec<(Name<'a>, &'a [Type], Type)>,
+    // the variables in scope in the function being generated, innermost last
+    scopes: Vec<Vec<(Name<'a>, Type)>>,
+}
+
+impl<'a, 'd> Generator<'a, 'd> {
+    fn function(&mut self, index: usize) -> Declaration<'a> {
+        let name = name(self.arena, &format!("f{}", index));
+        let param_count = self.input.below(MAX_PARAMS + 1);
+
+        let params: Vec<_> = (0..param_count)
+            .map(|i| (name_of_param(self.arena, i), self.input.value_type()))
+            .collect();
+
+        let args = self
+            .arena
+            .alloc_slice(params.iter().map(|&(name, t)| FunctionArg {
+                name,
+                // untyped arguments are Int32
+                type_name: match t {
+                    Type::Int32 if self.input.bool() => None,
+                    t => Some(type_name(self.arena, t)),
+                },
+            }));
+
+        // some functions don't return anything, which makes their tail calls differ
+        let (return_type, end) = match self.input.below(5) {
+            0 => (Type::Unit, End::Unit),
+            _ => {
+                let t = self.input.value_type();
+
+                (t, End::Value(t))
+            }
+        };
+
+        self.scopes = vec![params.clone()];
+
+        let body = self.block(end, 0);
+
+        let param_types = self.arena.alloc_slice(params.iter().map(|&(_, t)| t));
+
+        self.functions.push((name, param_types, return_type));
+
+        Declaration::FunctionDecl {
+            name,
+            arguments: FunctionArgsList { args },
+            body,
+        }
+    }
+
+    fn block(&mut self, end: End, depth: usize) -> CodeBlock<'a> {
+        let mut statements = Vec::new();
+
+        for _ in 0..self.input.below(4) {
+            statements.push(self.statement(depth));
+        }
+
+        statements.push(match end {
+            End::Value(t) if depth < MAX_BLOCK_DEPTH && self.input.below(4) == 0 => {
+                self.if_statement(Some(t), depth)
+            }
+            End::Value(t) => CodeBlockStatement::BareExpression(self.expression(t, 0)),
+            End::Unit => self.unit_statement(depth),
+            End::Anything => self.statement(depth),
+        });
+
+        self.arena.alloc_slice(statements)
+    }
+
+    fn scoped_block(&mut self, end: End, depth: usize) -> CodeBlock<'a> {
+        self.scopes.push(Vec::new());
+
+        let block = self.block(end, depth);
+
+        self.scopes.pop();
+
+        block
+    }
+
+    fn statement(&mut self, depth: usize) -> CodeBlockStatement<'a> {
+        match self.input.below(2) {
+            0 => self.unit_statement(depth),
+            _ => {
+                let t = self.input.value_type();
+
+                CodeBlockStatement::BareExpression(self.expression(t, 0))
+            }
+        }
+    }
+
+    fn unit_statement(&mut self, depth: usize) -> CodeBlockStatement<'a> {
+        match self.input.below(3) {
+            1 if depth < MAX_BLOCK_DEPTH => self.if_statement(None, depth),
+            2 => match self.call(Type::Unit, 0) {
+                Some(call) => CodeBlockStatement::BareExpression(call),
+                None => self.assignment(),
+            },
+            _ => self.assignment(),
+        }
+    }
+
+    // Either a new local, or a new value for one already in scope
+    fn assignment(&mut self) -> CodeBlockStatement<'a> {
+        let existing = self.variables(None);
+
+        let (name, t, is_new) = if existing.is_empty() || self.input.bool() {
+            // the first name that isn't already a variable in scope
+            let name = (0..)
+                .map(|i| name(self.arena, &format!("x{}", i)))
+                .find(|name| existing.iter().all(|(var, _)| var.symbol != name.symbol))
+                .unwrap();
+
+            (name, self.input.value_type(), true)
+        } else {
+            let (name, t) = existing[self.input.below(existing.len())];
+
+            (name, t, false)
+        };
+
+        let expr = self.expression(t, 0);
+
+        // the value can't refer to the variable it's creating
+        if is_new {
+            self.scopes.last_mut().unwrap().push((name, t));
+        }
+
+        CodeBlockStatement::Declaration(Declaration::Assignment { name, expr })
+    }
+
+    fn if_statement(&mut self, result: Option<Type>, depth: usize) -> CodeBlockStatement<'a> {
+        // there's only a value when every branch has one
+        let has_else = result.is_some() || self.input.bool();
+
+        let end = match (result, has_else) {
+            (Some(t), _) => End::Value(t),
+            (None, true) => End::Unit,
+            // the value is thrown away, so the branches don't have to agree
+            (None, false) => End::Anything,
+        };
+
+        let count = 1 + self.input.below(3);
+
+        let mut cases = Vec::with_capacity(count);
+
+        for _ in 0..count {
+            let condition = self.expression(Type::Bool, 0);
+            let block = self.scoped_block(end, depth + 1);
+
+            cases.push(IfStatementCase { condition, block });
+        }
+
+        let else_case = match has_else {
+            true => Some(self.scoped_block(end, depth + 1)),
+            false => None,
+        };
+
+        CodeBlockStatement::IfStatement {
+            cases: self.arena.alloc_slice(cases),
+            else_case,
+        }
+    }
+
+    fn expression(&mut self, t: Type, depth: usize) -> Expression<'a> {
+        if depth >= MAX_EXPRESSION_DEPTH {
+            return self.leaf(t);
+        }
+
+        match self.input.below(6) {
+            0 => self.call(t, depth).unwrap_or_else(|| self.leaf(t)),
+            1 if t == Type::Bool => {
+                let operand_type = COMPARABLE_TYPES[self.input.below(COMPARABLE_TYPES.len())];
+
+                self.binary_op(operand_type, BinaryOperator::DoubleEquals, depth)
+            }
+            1 if t != Type::Float => {
+                const ARITHMETIC: [BinaryOperator; 4] = [
+                    BinaryOperator::Plus,
+                    BinaryOperator::Minus,
+                    BinaryOperator::Multiply,
+                    BinaryOperator::Divide,
+                ];
+
+                let operator = ARITHMETIC[self.input.below(ARITHMETIC.len())];
+
+                self.binary_op(t, operator, depth)
+            }
+            2 if t == Type::Int32 || t == Type::Int64 => {
+                Expression::Negation(self.arena.alloc(self.expression(t, depth + 1)))
+            }
+            _ => self.leaf(t),
+        }
+    }
+
+    fn binary_op(&mut self, t: Type, operator: BinaryOperator, depth: usize) -> Expression<'a> {
+        let left = self.expression(t, depth + 1);
+        let right = self.expression(t, depth + 1);
+
+        Expression::BinaryOp {
+            left: self.arena.alloc(left),
+            operator,
+            right: self.arena.alloc(right),
+        }
+    }
+
+    fn call(&mut self, t: Type, depth: usize) -> Option<Expression<'a>> {
+        let candidates: Vec<_> = self
+            .functions
+            .iter()
+            .filter(|&&(_, _, return_type)| return_type == t)
+            .copied()
+            .collect();
+
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let (name, params, _) = candidates[self.input.below(candidates.len())];
+
+        let args: Vec<_> = params
+            .iter()
+            .map(|&param| self.expression(param, depth + 1))
+            .collect();
+
+        Some(Expression::FunctionCall {
+            name,
+            args: self.arena.alloc_slice(args),
+        })
+    }
+
+    fn leaf(&mut self, t: Type) -> Expression<'a> {
+        let variables = self.variables(Some(t));
+
+        if !variables.is_empty() && self.input.bool() {
+            let (name, _) = variables[self.input.below(variables.len())];
+
+            return Expression::Variable(name);
+        }
+
+        let constant = match t {
+            Type::Int32 => Constant::Int(Integer::new(
+                i32::from_le_bytes(self.input.bytes()).into(),
+                0,
+            )),
+            Type::Int64 => Constant::Int64(Integer::new(i64::from_le_bytes(self.input.bytes()), 0)),
+            Type::Float => Constant::Float(f32::from_le_bytes(self.input.bytes()).into()),
+            _ => Constant::Bool(self.input.bool()),
+        };
+
+        Expression::Constant(constant)
+    }
+
+    fn variables(&self, t: Option<Type>) -> Vec<(Name<'a>, Type)> {
+        self.scopes
+            .iter()
+            .flatten()
+            .filter(|&&(_, var_type)| t.is_none_or(|t| t == var_type))
+            .copied()
+            .collect()
+    }
+}
+
+fn name<'a>(arena: &'a Arena, name: &str) -> Name<'a> {
+    Name::new(arena.intern(name), 0)
+}
+
+fn name_of_param(arena: &Arena, index: usize) -> Name<'_> {
+    name(arena, &format!("p{}", index))
+}
+
+fn type_name(arena: &Arena, t: Type) -> Name<'_> {
+    name(
+        arena,
+        match t {
+            Type::Int32 => "Int32",
+            Type::Int64 => "Int64",
+            Type::Bool => "Bool",
+            Type::Float => "Float",
+            Type::Unit | Type::Str => unreachable!("only values have names here"),
+        },
+    )
+}
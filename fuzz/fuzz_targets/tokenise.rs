@@ -0,0 +1,11 @@
+#![no_main]
+use compiler_core::tokeniser::tokenise;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(source) = std::str::from_utf8(data) {
+        for (span, _) in tokenise(source).spanned() {
+            assert!(source.get(span).is_some());
+        }
+    }
+});
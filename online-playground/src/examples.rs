@@ -0,0 +1,40 @@
+// The fixtures the compiler is tested against, so the examples can't drift out of date. The ones
+// that need the WASI target or use features code generation doesn't support yet are left out
+pub const EXAMPLES: &[(&str, &str)] = &[
+    (
+        "Fibonacci",
+        include_str!("../../compiler-core/src/fixtures/fibonacci.lang"),
+    ),
+    (
+        "Booleans",
+        include_str!("../../compiler-core/src/fixtures/booleans.lang"),
+    ),
+    (
+        "Comments",
+        include_str!("../../compiler-core/src/fixtures/comments.lang"),
+    ),
+    (
+        "Constants",
+        include_str!("../../compiler-core/src/fixtures/constants.lang"),
+    ),
+    (
+        "Example program",
+        include_str!("../../compiler-core/src/fixtures/example_program.lang"),
+    ),
+    (
+        "Inlining",
+        include_str!("../../compiler-core/src/fixtures/inlining.lang"),
+    ),
+    (
+        "Int64",
+        include_str!("../../compiler-core/src/fixtures/int64.lang"),
+    ),
+    (
+        "Tail calls",
+        include_str!("../../compiler-core/src/fixtures/tail_calls.lang"),
+    ),
+    (
+        "Unit functions",
+        include_str!("../../compiler-core/src/fixtures/unit_functions.lang"),
+    ),
+];
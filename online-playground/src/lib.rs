@@ -1,20 +1,25 @@
-use compiler_core::code_gen::*;
-use compiler_core::parser::{parse, ParseError};
-use compiler_core::wasm::*;
+use compiler_core::compiler::{CompileOptions, CompileOutput, Compiler, Target};
+use compiler_core::diagnostics::Diagnostic;
+use compiler_core::parser::ParseSession;
+use std::ops::Range;
+use views::escape_html;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 
-fn compile(source: &str) -> Result<String, ParseError> {
-    let ast = parse(&source)?;
+pub mod examples;
+pub mod run;
+pub mod share;
+pub mod views;
 
-    let wasm = ast_to_wasm(&ast).unwrap();
+// The binary is always generated, so its size can be shown and it can be run. Each edit gets
+// its own session, so the names in the last version of the program go along with it
+pub fn compile<'a>(session: &'a ParseSession, source: &'a str) -> CompileOutput<'a> {
+    let compiler = Compiler::new(CompileOptions {
+        target: Target::Wasm,
+        ..Default::default()
+    });
 
-    let mut output = String::new();
-
-    wasm.write_text(&mut output, WasmIndentation::default())
-        .unwrap();
-
-    Ok(output)
+    compiler.compile(session, source)
 }
 
 #[wasm_bindgen(start)]
@@ -27,6 +32,10 @@ pub fn run() -> Result<(), JsValue> {
         .style()
         .set_property("display", "none")?;
 
+    restore_shared_source()?;
+
+    add_examples()?;
+
     let on_input = Closure::wrap(Box::new(move |_event: web_sys::InputEvent| {
         update_output().unwrap();
     }) as Box<dyn FnMut(_)>);
@@ -35,39 +44,268 @@ pub fn run() -> Result<(), JsValue> {
 
     on_input.forget();
 
+    // the highlights and syntax colours sit behind the textarea, so have to scroll along with it
+    let on_scroll = Closure::wrap(Box::new(move || {
+        let input = get_input_el();
+
+        for id in &["highlights", "syntax"] {
+            let layer = get_element(id);
+
+            layer.set_scroll_top(input.scroll_top());
+            layer.set_scroll_left(input.scroll_left());
+        }
+    }) as Box<dyn FnMut()>);
+
+    get_input_el()
+        .add_event_listener_with_callback("scroll", on_scroll.as_ref().unchecked_ref())?;
+
+    on_scroll.forget();
+
+    let on_run = Closure::wrap(Box::new(move || {
+        wasm_bindgen_futures::spawn_local(run_program());
+    }) as Box<dyn FnMut()>);
+
+    get_element("run")
+        .add_event_listener_with_callback("click", on_run.as_ref().unchecked_ref())?;
+
+    on_run.forget();
+
+    let on_share = Closure::wrap(Box::new(move || {
+        share().unwrap();
+    }) as Box<dyn FnMut()>);
+
+    get_element("share")
+        .add_event_listener_with_callback("click", on_share.as_ref().unchecked_ref())?;
+
+    on_share.forget();
+
     update_output()?;
 
     Ok(())
 }
 
+fn get_location() -> web_sys::Location {
+    web_sys::window()
+        .expect("no global `window` exists")
+        .location()
+}
+
+fn restore_shared_source() -> Result<(), JsValue> {
+    let hash = get_location().hash()?;
+    let fragment = hash.strip_prefix('#').unwrap_or(&hash);
+
+    if let Some(source) = share::decode_source(fragment) {
+        get_input_el().set_value(&source);
+    }
+
+    Ok(())
+}
+
+fn share() -> Result<(), JsValue> {
+    let location = get_location();
+
+    location.set_hash(&share::encode_source(&get_input_value()))?;
+
+    let link = get_share_link_el();
+
+    link.set_value(&location.href()?);
+    link.style().set_property("display", "block")?;
+    link.select();
+
+    Ok(())
+}
+
+fn add_examples() -> Result<(), JsValue> {
+    let select = get_examples_el();
+
+    for (name, _) in examples::EXAMPLES {
+        let option = web_sys::HtmlOptionElement::new_with_text(name)?;
+
+        select.add_with_html_option_element(&option)?;
+    }
+
+    let on_change = Closure::wrap(Box::new(move || {
+        let select = get_examples_el();
+
+        // the first option is the "Examples" placeholder
+        if let Some((_, source)) = select
+            .selected_index()
+            .checked_sub(1)
+            .and_then(|index| examples::EXAMPLES.get(index as usize))
+        {
+            get_input_el().set_value(source.trim_start());
+            update_output().unwrap();
+        }
+
+        select.set_selected_index(0);
+    }) as Box<dyn FnMut()>);
+
+    select.add_event_listener_with_callback("change", on_change.as_ref().unchecked_ref())?;
+
+    on_change.forget();
+
+    Ok(())
+}
+
 fn update_output() -> Result<(), JsValue> {
     let input = get_input_value();
 
-    let error_el = get_document()
-        .get_element_by_id("error")
-        .expect("should have #error on the page")
-        .dyn_into::<web_sys::HtmlElement>()
-        .expect("#error should be an `HtmlElement`");
+    let error_el = get_element("error");
+
+    get_element("tokens").set_inner_text(&views::tokens_text(&input));
+    get_element("ast").set_inner_html(&views::ast_html(&input).unwrap_or_default());
+
+    let session = ParseSession::new();
 
-    match compile(&input) {
-        Ok(compiled) => {
-            set_output_text(&compiled);
-            error_el.style().set_property("display", "none")?;
+    let output = compile(&session, &input);
+
+    match output.artifacts {
+        Some(artifacts) => {
+            set_output_text(&artifacts.wat);
+            get_element("size").set_inner_text(&format!(
+                "{} bytes",
+                artifacts.wasm.map_or(0, |wasm| wasm.len())
+            ));
         }
-        Err(_err) => {
+        None => {
             set_output_text("");
-            error_el.style().set_property("display", "block")?;
+            get_element("size").set_inner_text("");
         }
     }
 
+    if output.diagnostics.is_empty() {
+        error_el.style().set_property("display", "none")?;
+    } else {
+        error_el.set_inner_html(&diagnostics_html(&output.diagnostics));
+        error_el.style().set_property("display", "block")?;
+    }
+
+    let ranges = output
+        .diagnostics
+        .into_iter()
+        .filter_map(|diagnostic| diagnostic.location)
+        .map(|location| location.range)
+        .collect();
+
+    get_element("highlights").set_inner_html(&highlighted_html(&input, ranges));
+    get_element("syntax").set_inner_html(&views::syntax_html(&input));
+
     Ok(())
 }
 
+async fn run_program() {
+    let output = get_element("run-output");
+
+    let args = get_document()
+        .get_element_by_id("args")
+        .expect("should have #args on the page")
+        .dyn_into::<web_sys::HtmlInputElement>()
+        .expect("#args should be an `HtmlInputElement`")
+        .value();
+
+    let source = get_input_value();
+    let session = ParseSession::new();
+    let binary = compile(&session, &source)
+        .artifacts
+        .and_then(|artifacts| artifacts.wasm);
+
+    let result = match (binary, views::parse_args(&args)) {
+        (Some(binary), Ok(args)) => run::run_main(&binary, &args).await,
+        (None, _) => Err("the program doesn't compile".to_string()),
+        (_, Err(error)) => Err(error),
+    };
+
+    match result {
+        Ok(value) => output.set_inner_text(&value),
+        Err(error) => output.set_inner_text(&format!("⚠️ {}", error)),
+    }
+}
+
+fn diagnostics_html(diagnostics: &[Diagnostic]) -> String {
+    let mut html = String::from("<ul>");
+
+    for diagnostic in diagnostics {
+        html.push_str(&format!("<li class=\"{}\">⚠️ ", diagnostic.severity.name()));
+
+        if let Some(location) = &diagnostic.location {
+            html.push_str(&format!(
+                "{}:{}: ",
+                location.start.line, location.start.column
+            ));
+        }
+
+        html.push_str(&escape_html(&diagnostic.message));
+        html.push_str("</li>");
+    }
+
+    html.push_str("</ul>");
+
+    html
+}
+
+// The source with each range wrapped in a <mark>. The text itself is transparent, so only the
+// marks show through the textarea on top
+fn highlighted_html(source: &str, mut ranges: Vec<Range<usize>>) -> String {
+    ranges.sort_by_key(|range| (range.start, range.end));
+    ranges.dedup();
+
+    let mut html = String::new();
+    let mut end = 0;
+
+    for mut range in ranges {
+        // overlapping ranges become one mark
+        if range.start < end {
+            if range.end <= end {
+                continue;
+            }
+
+            range.start = end;
+        }
+
+        html.push_str(&escape_html(&source[end..range.start]));
+
+        // errors between characters still need something to see, so cover the next one, or
+        // add a space when they're at the end of a line
+        if range.is_empty() {
+            match source[range.start..].chars().next() {
+                Some(c) if c != '\n' => range.end += c.len_utf8(),
+                _ => {
+                    html.push_str("<mark> </mark>");
+                    end = range.end;
+
+                    continue;
+                }
+            }
+        }
+
+        html.push_str("<mark>");
+        html.push_str(&escape_html(&source[range.clone()]));
+        html.push_str("</mark>");
+
+        end = range.end;
+    }
+
+    html.push_str(&escape_html(&source[end..]));
+
+    // a textarea shows a trailing newline as an empty line, but a div doesn't
+    html.push('\n');
+
+    html
+}
+
 fn get_document() -> web_sys::Document {
     let window = web_sys::window().expect("no global `window` exists");
     window.document().expect("should have a document on window")
 }
 
+fn get_element(id: &str) -> web_sys::HtmlElement {
+    get_document()
+        .get_element_by_id(id)
+        .unwrap_or_else(|| panic!("should have #{} on the page", id))
+        .dyn_into::<web_sys::HtmlElement>()
+        .unwrap_or_else(|_| panic!("#{} should be an `HtmlElement`", id))
+}
+
 fn get_input_el() -> web_sys::HtmlTextAreaElement {
     get_document()
         .get_element_by_id("input")
@@ -76,6 +314,22 @@ fn get_input_el() -> web_sys::HtmlTextAreaElement {
         .expect("#input should be an `HtmlTextAreaElement`")
 }
 
+fn get_examples_el() -> web_sys::HtmlSelectElement {
+    get_document()
+        .get_element_by_id("examples")
+        .expect("should have #examples on the page")
+        .dyn_into::<web_sys::HtmlSelectElement>()
+        .expect("#examples should be an `HtmlSelectElement`")
+}
+
+fn get_share_link_el() -> web_sys::HtmlInputElement {
+    get_document()
+        .get_element_by_id("share-link")
+        .expect("should have #share-link on the page")
+        .dyn_into::<web_sys::HtmlInputElement>()
+        .expect("#share-link should be an `HtmlInputElement`")
+}
+
 fn get_input_value() -> String {
     get_input_el().value()
 }
@@ -0,0 +1,58 @@
+use compiler_core::wasm::interpreter::Value;
+use js_sys::{Array, BigInt, Function, Object, Reflect, WebAssembly};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+
+// Runs the module in the browser's own engine, rather than the compiler's interpreter, so the
+// result is what anyone loading the module would get. Traps come back as their messages
+pub async fn run_main(binary: &[u8], args: &[Value]) -> Result<String, String> {
+    let instantiated = JsFuture::from(WebAssembly::instantiate_buffer(binary, &Object::new()))
+        .await
+        .map_err(error_message)?;
+
+    let instance: WebAssembly::Instance = Reflect::get(&instantiated, &"instance".into())
+        .and_then(JsCast::dyn_into)
+        .map_err(error_message)?;
+
+    let main = Reflect::get(&instance.exports(), &"main".into())
+        .ok()
+        .and_then(|main| main.dyn_into::<Function>().ok())
+        .ok_or("there's no exported `main` function")?;
+
+    let args: Array = args.iter().map(to_js).collect();
+
+    let result = main.apply(&JsValue::NULL, &args).map_err(error_message)?;
+
+    Ok(describe(&result))
+}
+
+fn to_js(value: &Value) -> JsValue {
+    match *value {
+        Value::I32(value) => value.into(),
+        // i64s cross into JS as BigInts
+        Value::I64(value) => BigInt::from(value).into(),
+        Value::F32(value) => value.into(),
+    }
+}
+
+fn describe(value: &JsValue) -> String {
+    if value.is_undefined() {
+        return "(no value)".to_string();
+    }
+
+    if let Some(number) = value.as_f64() {
+        return number.to_string();
+    }
+
+    match value.dyn_ref::<BigInt>() {
+        Some(int) => int.to_string(10).map(String::from).unwrap_or_default(),
+        None => format!("{:?}", value),
+    }
+}
+
+fn error_message(error: JsValue) -> String {
+    match error.dyn_ref::<js_sys::Error>() {
+        Some(error) => String::from(error.message()),
+        None => format!("{:?}", error),
+    }
+}
@@ -0,0 +1,19 @@
+use miniz_oxide::deflate::compress_to_vec;
+use miniz_oxide::inflate::decompress_to_vec;
+
+// Shared links carry the whole program in the URL fragment, which never gets sent to a server.
+// It's deflated first so that longer programs still make links that can be pasted around
+pub fn encode_source(source: &str) -> String {
+    let compressed = compress_to_vec(source.as_bytes(), 9);
+
+    base64::encode_config(compressed, base64::URL_SAFE_NO_PAD)
+}
+
+// `None` for fragments that weren't made by `encode_source`, like ones mangled by being pasted
+pub fn decode_source(fragment: &str) -> Option<String> {
+    let compressed = base64::decode_config(fragment, base64::URL_SAFE_NO_PAD).ok()?;
+
+    let source = decompress_to_vec(&compressed).ok()?;
+
+    String::from_utf8(source).ok()
+}
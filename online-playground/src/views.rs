@@ -0,0 +1,214 @@
+use compiler_core::ast::*;
+use compiler_core::highlight::highlight;
+use compiler_core::parser::ParseSession;
+use compiler_core::tokeniser::tokenise;
+use compiler_core::wasm::interpreter::Value;
+
+// One token per line, stopping at the first one that couldn't be read
+pub fn tokens_text(source: &str) -> String {
+    let mut text = String::new();
+
+    for token in tokenise(source) {
+        match token {
+            Ok(token) => text.push_str(&format!("{:?}\n", token)),
+            Err(error) => {
+                text.push_str(&format!("error: {}\n", error));
+                break;
+            }
+        }
+    }
+
+    text
+}
+
+// The source coloured by token, for the layer behind the textarea
+pub fn syntax_html(source: &str) -> String {
+    let mut html = String::new();
+    let mut end = 0;
+
+    for (span, class) in highlight(source) {
+        html.push_str(&escape_html(&source[end..span.start]));
+        html.push_str(&format!(
+            "<span class=\"{}\">{}</span>",
+            class.name(),
+            escape_html(&source[span.clone()])
+        ));
+
+        end = span.end;
+    }
+
+    html.push_str(&escape_html(&source[end..]));
+
+    // a textarea shows a trailing newline as an empty line, but a div doesn't
+    html.push('\n');
+
+    html
+}
+
+// `None` when the program doesn't parse, which the diagnostics already explain
+pub fn ast_html(source: &str) -> Option<String> {
+    let session = ParseSession::new();
+
+    let ast = session.parse(source).ok()?;
+
+    Some(format!(
+        "<ul class=\"tree\">{}</ul>",
+        ast_tree(&ast).to_html()
+    ))
+}
+
+// A node in the AST view, which is shown as nested lists that can be collapsed
+#[derive(Debug, PartialEq, Eq)]
+pub struct TreeNode {
+    pub label: String,
+    pub children: Vec<TreeNode>,
+}
+
+impl TreeNode {
+    fn new(label: impl Into<String>, children: Vec<TreeNode>) -> Self {
+        TreeNode {
+            label: label.into(),
+            children,
+        }
+    }
+
+    fn leaf(label: impl Into<String>) -> Self {
+        TreeNode::new(label, vec![])
+    }
+
+    pub fn to_html(&self) -> String {
+        let label = escape_html(&self.label);
+
+        if self.children.is_empty() {
+            return format!("<li>{}</li>", label);
+        }
+
+        let children: String = self.children.iter().map(TreeNode::to_html).collect();
+
+        format!(
+            "<li><details open><summary>{}</summary><ul>{}</ul></details></li>",
+            label, children
+        )
+    }
+}
+
+pub fn ast_tree(ast: &Ast) -> TreeNode {
+    let statements = ast
+        .statements
+        .iter()
+        .map(|TopLevelStatement::Declaration { decl, exported }| {
+            let mut node = declaration(decl);
+
+            if *exported {
+                node.label = format!("export {}", node.label);
+            }
+
+            node
+        })
+        .collect();
+
+    TreeNode::new("Program", statements)
+}
+
+fn declaration(decl: &Declaration) -> TreeNode {
+    match decl {
+        Declaration::Assignment { name, expr } => {
+            TreeNode::new(format!("{} =", name), vec![expression(expr)])
+        }
+        Declaration::FunctionDecl {
+            name,
+            arguments,
+            body,
+        } => {
+            let arguments: Vec<_> = arguments
+                .args
+                .iter()
+                .map(|arg| match arg.type_name {
+                    Some(type_name) => format!("{}: {}", arg.name, type_name),
+                    None => arg.name.to_string(),
+                })
+                .collect();
+
+            TreeNode::new(
+                format!("fn {}({})", name, arguments.join(", ")),
+                block(body),
+            )
+        }
+    }
+}
+
+fn block(statements: &[CodeBlockStatement]) -> Vec<TreeNode> {
+    statements.iter().map(statement).collect()
+}
+
+fn statement(statement: &CodeBlockStatement) -> TreeNode {
+    match statement {
+        CodeBlockStatement::Declaration(decl) => declaration(decl),
+        CodeBlockStatement::BareExpression(expr) => expression(expr),
+        CodeBlockStatement::IfStatement { cases, else_case } => {
+            let mut children: Vec<_> = cases
+                .iter()
+                .map(
+                    |IfStatementCase {
+                         condition,
+                         block: body,
+                     }| {
+                        TreeNode::new(
+                            "case",
+                            vec![
+                                TreeNode::new("condition", vec![expression(condition)]),
+                                TreeNode::new("then", block(body)),
+                            ],
+                        )
+                    },
+                )
+                .collect();
+
+            if let Some(body) = else_case {
+                children.push(TreeNode::new("else", block(body)));
+            }
+
+            TreeNode::new("if", children)
+        }
+    }
+}
+
+fn expression(expr: &Expression) -> TreeNode {
+    match expr {
+        Expression::Variable(name) => TreeNode::leaf(name.as_str()),
+        Expression::Constant(constant) => TreeNode::leaf(format!("{:?}", constant)),
+        Expression::FunctionCall { name, args } => {
+            TreeNode::new(format!("{}()", name), args.iter().map(expression).collect())
+        }
+        Expression::BinaryOp {
+            left,
+            operator,
+            right,
+        } => TreeNode::new(
+            format!("{:?}", operator),
+            vec![expression(left), expression(right)],
+        ),
+        Expression::Negation(expr) => TreeNode::new("Negation", vec![expression(expr)]),
+    }
+}
+
+// Arguments are separated by spaces or commas, and are i32s unless they end in i64
+pub fn parse_args(text: &str) -> Result<Vec<Value>, String> {
+    text.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| {
+            let value = match arg.strip_suffix("i64") {
+                Some(int) => int.parse().map(Value::I64),
+                None => arg.parse().map(Value::I32),
+            };
+
+            value.map_err(|_| format!("`{}` isn't an integer", arg))
+        })
+        .collect()
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
@@ -0,0 +1,151 @@
+use compiler_core::parser::ParseSession;
+use compiler_core::wasm::interpreter::Value;
+use online_playground::examples::EXAMPLES;
+use online_playground::share::*;
+use online_playground::views::*;
+use wasm_bindgen_test::*;
+
+// the synchronous tests don't need a JS engine, so run natively too
+#[wasm_bindgen_test(unsupported = test)]
+fn tokens_are_one_per_line() {
+    let tokens = tokens_text("fn f()\n    1");
+
+    assert_eq!(tokens.lines().count(), 7);
+    assert!(tokens.starts_with("Keyword(Function)\n"));
+}
+
+#[wasm_bindgen_test(unsupported = test)]
+fn tokens_stop_at_the_first_error() {
+    let tokens = tokens_text("\"unterminated\n1");
+
+    assert_eq!(tokens, "error: unterminated string\n");
+}
+
+#[wasm_bindgen_test(unsupported = test)]
+fn syntax_is_coloured_by_token() {
+    assert_eq!(
+        syntax_html("fn f() // <hi>\n    \"a&b\""),
+        "<span class=\"keyword\">fn</span> <span class=\"name\">f</span>\
+         <span class=\"punctuation\">(</span><span class=\"punctuation\">)</span> // &lt;hi&gt;\n    \
+         <span class=\"string\">\"a&amp;b\"</span>\n"
+    );
+}
+
+#[wasm_bindgen_test(unsupported = test)]
+fn ast_is_a_tree_of_declarations() {
+    let session = compiler_core::parser::ParseSession::new();
+
+    let ast = session.parse("export fn f(x)\n    x + 1\n").unwrap();
+
+    let tree = ast_tree(&ast);
+
+    assert_eq!(tree.label, "Program");
+    assert_eq!(tree.children[0].label, "export fn f(x)");
+    assert_eq!(tree.children[0].children[0].label, "Plus");
+}
+
+#[wasm_bindgen_test(unsupported = test)]
+fn ast_html_escapes_labels() {
+    let html = ast_html("fn f()\n    \"<b>\"\n").unwrap();
+
+    assert!(html.starts_with("<ul class=\"tree\"><li><details open><summary>Program"));
+    assert!(html.contains("<li>Str(\"&lt;b&gt;\")</li>"));
+}
+
+#[wasm_bindgen_test(unsupported = test)]
+fn no_ast_when_the_program_doesnt_parse() {
+    assert_eq!(ast_html("fn f("), None);
+}
+
+#[wasm_bindgen_test(unsupported = test)]
+fn args_are_split_on_spaces_and_commas() {
+    assert_eq!(
+        parse_args(" 1, -2 3i64,"),
+        Ok(vec![Value::I32(1), Value::I32(-2), Value::I64(3)])
+    );
+}
+
+#[wasm_bindgen_test(unsupported = test)]
+fn args_that_arent_integers_are_errors() {
+    assert_eq!(
+        parse_args("1 two"),
+        Err("`two` isn't an integer".to_string())
+    );
+}
+
+#[wasm_bindgen_test(unsupported = test)]
+fn shared_source_round_trips() {
+    let source = "fn main()\n    \"héllo\"\n".repeat(20);
+
+    let fragment = encode_source(&source);
+
+    assert!(fragment.len() < source.len());
+    assert!(fragment
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+    assert_eq!(decode_source(&fragment), Some(source));
+}
+
+#[wasm_bindgen_test(unsupported = test)]
+fn mangled_fragments_arent_decoded() {
+    assert_eq!(decode_source(""), None);
+    assert_eq!(decode_source("not a program!"), None);
+    assert_eq!(decode_source(&encode_source("fn f()\n    1")[2..]), None);
+}
+
+#[wasm_bindgen_test(unsupported = test)]
+fn examples_compile() {
+    for (name, source) in EXAMPLES {
+        assert!(
+            !online_playground::compile(&ParseSession::new(), source).has_errors(),
+            "{} doesn't compile",
+            name
+        );
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod running {
+    use compiler_core::parser::ParseSession;
+    use compiler_core::wasm::interpreter::Value;
+    use online_playground::compile;
+    use online_playground::run::run_main;
+    use wasm_bindgen_test::*;
+
+    const FIBONACCI: &str = "
+fn fibo(n)
+    if n == 0
+        0
+    else if n == 1
+        1
+    else
+        fibo(n - 1) + fibo(n - 2)
+
+export fn main(n)
+    fibo(n)
+";
+
+    #[wasm_bindgen_test]
+    async fn runs_main_with_arguments() {
+        let session = ParseSession::new();
+        let binary = compile(&session, FIBONACCI)
+            .artifacts
+            .unwrap()
+            .wasm
+            .unwrap();
+
+        assert_eq!(
+            run_main(&binary, &[Value::I32(10)]).await,
+            Ok("55".to_string())
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn traps_are_errors() {
+        let source = "export fn main(n)\n    1 / n\n";
+        let session = ParseSession::new();
+        let binary = compile(&session, source).artifacts.unwrap().wasm.unwrap();
+
+        assert!(run_main(&binary, &[Value::I32(0)]).await.is_err());
+    }
+}